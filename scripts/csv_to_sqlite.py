@@ -31,7 +31,7 @@ def main(argv):
             email = row[2].strip()
             try:
                 cur.execute(
-                    "INSERT INTO import_person(prename, name, email, is_superuser) VALUES (?, ?, ?, 0)",
+                    "INSERT INTO import_person(prename, name, email, is_visible) VALUES (?, ?, ?, 1)",
                     (
                         prename,
                         name,
@@ -69,8 +69,8 @@ def main(argv):
     # stage 1: insert new persons
     cur.execute(
         """
-        INSERT INTO person (prename, name, email, is_superuser)
-            SELECT import_person.prename, import_person.name, import_person.email, false
+        INSERT INTO person (prename, name, email, is_visible)
+            SELECT import_person.prename, import_person.name, import_person.email, true
             FROM import_person
             LEFT OUTER JOIN person ON person.email == import_person.email
             WHERE person.email IS NULL
@@ -85,7 +85,8 @@ def main(argv):
             SELECT person.email
             FROM person
             LEFT OUTER JOIN import_person ON person.email == import_person.email
-            WHERE NOT person.is_superuser AND import_person.email IS NULL
+            WHERE person.person_id NOT IN (SELECT person_id FROM role)
+                AND import_person.email IS NULL
         )
         """
     )
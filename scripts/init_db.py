@@ -31,8 +31,13 @@ def init_database(filename: str = toplevel() / "testing-database.db"):
     cur.executescript(INIT_DB_SCRIPT)
 
     cur.execute(
-        """INSERT INTO person(prename, name, email, is_superuser, is_visible)
-    VALUES ('John', 'Doe', 'john_doe@example.com', true, true)"""
+        """INSERT INTO person(prename, name, email, is_visible)
+    VALUES ('John', 'Doe', 'john_doe@example.com', true)"""
+    )
+    cur.execute(
+        """INSERT INTO role(person_id, role)
+    VALUES (?, 'admin')""",
+        (cur.lastrowid,),
     )
 
     conn.commit()
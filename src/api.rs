@@ -0,0 +1,582 @@
+//! The versioned JSON API under `/api/v1`, for apps and scripts. It's a thin layer over
+//! [`sql_interface`], doing the same checks as the site itself. Clients authenticate with the JWT
+//! from `POST /api/v1/login/code` as bearer token, see `docs/authflow.md` and the OpenAPI document
+//! at `/api/v1/openapi.json`.
+
+use {
+    super::{
+        authflow::{self, ApiUser, CodeFailure, LoginLimits},
+        config::Config,
+        mail::Mailer,
+        notifications::{notify_promoted, notify_registration_change, RegistrationChange},
+        possible_to_register,
+        ratelimit::Lockout,
+        sql_interface::{
+            self, ApplyRegistrationError, Drive, DriveFilter, DriveOverview, InsertDriveError,
+            NewPerson, Person, PersonCreationError, RegistrationUpdate, Role, SearchPersonBy,
+            SearchPersonError, SearchRegistrationsBy, VisibilityFilter,
+        },
+        BususagesDBConn, ImpossibleReason,
+    },
+    rocket::{
+        http::{ContentType, Header, Status},
+        response::{self, Responder},
+        serde::json::Json,
+        Catcher, Request, Route, State,
+    },
+    serde::{Deserialize, Serialize},
+    std::net::IpAddr,
+    thiserror::Error,
+};
+
+pub fn routes() -> Vec<Route> {
+    routes![
+        openapi,
+        login_code,
+        me,
+        own_registrations,
+        update_own_registration,
+        drives,
+        create_drive,
+        drive_registrations,
+        persons,
+        create_person,
+        update_person_registration,
+    ]
+}
+
+pub fn catchers() -> Vec<Catcher> {
+    catchers![default_catcher]
+}
+
+/// Everything that can go wrong in the API apart from authentication, which is refused by the
+/// [`ApiUser`] guard and answered by [`default_catcher`]. Each variant is answered with its own
+/// status code and an [`ErrorBody`], so clients can tell errors apart without parsing messages.
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("Missing role for this action")]
+    Forbidden,
+    #[error("Unknown {0}")]
+    NotFound(&'static str),
+    #[error("A drive with the same date and label exists already")]
+    DriveAlreadyExists,
+    #[error("Email is already used")]
+    EmailAlreadyInUse,
+    #[error("Drive is restricted to groups the person isn't in")]
+    NotForPerson,
+    #[error("Registration deadline is over")]
+    DeadlineExpired,
+    #[error("Registration cap is reached")]
+    RegistrationCapReached,
+    #[error("Invalid or expired login code")]
+    InvalidLoginCode,
+    #[error("Too many failed attempts, retry in {} seconds", .0.retry_after.as_secs())]
+    TooManyAttempts(Lockout),
+    #[error("Internal server error")]
+    Internal,
+}
+
+impl ApiError {
+    fn status(&self) -> Status {
+        match self {
+            Self::InvalidLoginCode => Status::Unauthorized,
+            Self::Forbidden => Status::Forbidden,
+            Self::NotFound(_) => Status::NotFound,
+            Self::DriveAlreadyExists
+            | Self::EmailAlreadyInUse
+            | Self::NotForPerson
+            | Self::DeadlineExpired
+            | Self::RegistrationCapReached => Status::Conflict,
+            Self::TooManyAttempts(_) => Status::TooManyRequests,
+            Self::Internal => Status::InternalServerError,
+        }
+    }
+
+    /// A stable identifier of the error, unlike the message.
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Forbidden => "forbidden",
+            Self::NotFound(_) => "not_found",
+            Self::DriveAlreadyExists => "drive_already_exists",
+            Self::EmailAlreadyInUse => "email_already_in_use",
+            Self::NotForPerson => "drive_not_for_person",
+            Self::DeadlineExpired => "deadline_expired",
+            Self::RegistrationCapReached => "registration_cap_reached",
+            Self::InvalidLoginCode => "invalid_login_code",
+            Self::TooManyAttempts(_) => "too_many_attempts",
+            Self::Internal => "internal",
+        }
+    }
+}
+
+/// The body of every error response.
+#[derive(Debug, Serialize)]
+pub struct ErrorBody {
+    error: &'static str,
+    message: String,
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        let retry_after = match &self {
+            Self::TooManyAttempts(lockout) => Some(lockout.retry_after.as_secs().max(1)),
+            _ => None,
+        };
+        let body = ErrorBody {
+            error: self.code(),
+            message: self.to_string(),
+        };
+        let mut response = (self.status(), Json(body)).respond_to(req)?;
+        if let Some(seconds) = retry_after {
+            response.set_header(Header::new("Retry-After", seconds.to_string()));
+        }
+        Ok(response)
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(err: rusqlite::Error) -> Self {
+        log::error!("Database error in API: {}", err);
+        Self::Internal
+    }
+}
+
+impl From<ApplyRegistrationError> for ApiError {
+    fn from(err: ApplyRegistrationError) -> Self {
+        match err {
+            ApplyRegistrationError::UnknownDrive => Self::NotFound("drive"),
+            ApplyRegistrationError::NotForPerson => Self::NotForPerson,
+            ApplyRegistrationError::RusqliteError(err) => err.into(),
+        }
+    }
+}
+
+impl From<InsertDriveError> for ApiError {
+    fn from(err: InsertDriveError) -> Self {
+        match err {
+            InsertDriveError::AlreadyExists => Self::DriveAlreadyExists,
+            InsertDriveError::RusqliteError(err) => err.into(),
+        }
+    }
+}
+
+impl From<PersonCreationError> for ApiError {
+    fn from(err: PersonCreationError) -> Self {
+        match err {
+            PersonCreationError::EmailAlreadyInUse => Self::EmailAlreadyInUse,
+            PersonCreationError::RusqliteError(err) => err.into(),
+        }
+    }
+}
+
+impl From<SearchPersonError> for ApiError {
+    fn from(err: SearchPersonError) -> Self {
+        match err {
+            SearchPersonError::NotFound => Self::NotFound("person"),
+            err => {
+                log::error!("Error while searching person in API: {}", err);
+                Self::Internal
+            }
+        }
+    }
+}
+
+impl From<ImpossibleReason> for ApiError {
+    fn from(reason: ImpossibleReason) -> Self {
+        match reason {
+            ImpossibleReason::DeadlineExpired => Self::DeadlineExpired,
+            ImpossibleReason::RegistrationCapReached => Self::RegistrationCapReached,
+        }
+    }
+}
+
+/// Answers everything Rocket refuses by itself, like failed request guards, malformed JSON or
+/// unknown routes, with the same kind of body as [`ApiError`].
+#[catch(default)]
+fn default_catcher(status: Status, _req: &Request<'_>) -> (Status, Json<ErrorBody>) {
+    let error = match status.code {
+        400 | 422 => "invalid_request",
+        401 => "unauthorized",
+        403 => "forbidden",
+        404 => "not_found",
+        500..=599 => "internal",
+        _ => "unknown",
+    };
+    let body = ErrorBody {
+        error,
+        message: status.reason_lossy().to_string(),
+    };
+    (status, Json(body))
+}
+
+fn require_role(user: &ApiUser, role: Role) -> Result<(), ApiError> {
+    match user.has_role(role) {
+        true => Ok(()),
+        false => Err(ApiError::Forbidden),
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+/// A person as shown by the API, without anything related to logging in.
+#[derive(Debug, Serialize)]
+pub struct ApiPerson {
+    id: i64,
+    prename: String,
+    name: String,
+    email: String,
+    is_visible: bool,
+    /// Only known for the authenticated person itself.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    roles: Option<Vec<Role>>,
+}
+
+impl From<Person> for ApiPerson {
+    fn from(person: Person) -> Self {
+        Self {
+            id: person.id,
+            prename: person.prename,
+            name: person.name,
+            email: person.email.to_string(),
+            is_visible: person.is_visible,
+            roles: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiRegistration {
+    drive: Drive,
+    person: ApiPerson,
+    registered: bool,
+    waitlist_position: Option<u32>,
+    attended: Option<bool>,
+    group: Option<String>,
+}
+
+impl From<sql_interface::Registration> for ApiRegistration {
+    fn from(registration: sql_interface::Registration) -> Self {
+        Self {
+            drive: registration.drive,
+            person: registration.person.into(),
+            registered: registration.registered,
+            waitlist_position: registration.waitlist_position,
+            attended: registration.attended,
+            group: registration.group,
+        }
+    }
+}
+
+/// Which drives to list, relative to today.
+#[derive(FromFormField, Debug, Clone, Copy)]
+pub enum Period {
+    Future,
+    Past,
+    All,
+}
+
+impl From<Period> for DriveFilter {
+    fn from(period: Period) -> Self {
+        match period {
+            Period::Future => DriveFilter::OnlyFuture,
+            Period::Past => DriveFilter::OnlyPast,
+            Period::All => DriveFilter::ListAll,
+        }
+    }
+}
+
+#[get("/openapi.json")]
+fn openapi() -> (ContentType, &'static str) {
+    (ContentType::JSON, include_str!("../docs/openapi.json"))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginCode {
+    email: String,
+    code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Token {
+    token: String,
+    /// UNIX timestamp in seconds.
+    expires: i64,
+}
+
+/// Exchanges the code from a login mail for a JWT. The mail itself is requested like on the site,
+/// with `POST /`.
+#[post("/login/code", data = "<login>")]
+async fn login_code(
+    conn: BususagesDBConn,
+    config: &State<Config>,
+    limits: &State<LoginLimits>,
+    client_ip: Option<IpAddr>,
+    login: Json<LoginCode>,
+) -> ApiResult<Token> {
+    let person = authflow::check_login_code(&conn, limits, client_ip, &login.email, &login.code)
+        .await
+        .map_err(|failure| match failure {
+            CodeFailure::Invalid => ApiError::InvalidLoginCode,
+            CodeFailure::Lockout(lockout) => ApiError::TooManyAttempts(lockout),
+        })?;
+
+    let (token, expires) = authflow::sign_in_api(&conn, config, person.id).await?;
+    Ok(Json(Token { token, expires }))
+}
+
+#[get("/me")]
+async fn me(conn: BususagesDBConn, user: ApiUser) -> ApiResult<ApiPerson> {
+    let person_id = user.person_id();
+    let person = conn
+        .run(move |c| sql_interface::search_person(c, &SearchPersonBy::Id(person_id)))
+        .await?;
+    Ok(Json(ApiPerson {
+        roles: Some(user.roles().to_vec()),
+        ..person.into()
+    }))
+}
+
+/// The registrations of the authenticated person, one for every drive meant for them.
+#[get("/registrations?<period>")]
+async fn own_registrations(
+    conn: BususagesDBConn,
+    user: ApiUser,
+    period: Option<Period>,
+) -> ApiResult<Vec<ApiRegistration>> {
+    let by = SearchRegistrationsBy::PersonId {
+        id: user.person_id(),
+        filter: period.unwrap_or(Period::Future).into(),
+    };
+    let registrations = conn
+        .run(move |c| sql_interface::search_registrations(c, &by))
+        .await?;
+    Ok(Json(registrations.into_iter().map(Into::into).collect()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegistrationState {
+    registered: bool,
+}
+
+/// Applies a registration change made by `actor_id` and notifies everyone affected, just like the
+/// site does.
+async fn apply_registration(
+    conn: &BususagesDBConn,
+    mailer: &Mailer,
+    update: RegistrationUpdate,
+    actor_id: i64,
+    by_superuser: bool,
+) -> Result<(), ApiError> {
+    let (person_id, drive_id, registered) = (update.person_id, update.drive_id, update.registered);
+    let applied = conn
+        .run(move |c| sql_interface::update_registration(c, &update, Some(actor_id)))
+        .await?;
+
+    if applied.changed {
+        let change = RegistrationChange::new(registered, by_superuser);
+        notify_registration_change(conn, mailer, person_id, drive_id, change).await;
+    }
+    notify_promoted(conn, mailer, drive_id, applied.promoted).await;
+    Ok(())
+}
+
+/// Registers or unregisters the authenticated person. Deadline and registration cap apply, as on
+/// the dashboard.
+#[put("/registrations/<drive_id>", data = "<state>")]
+async fn update_own_registration(
+    conn: BususagesDBConn,
+    mailer: &State<Mailer>,
+    user: ApiUser,
+    drive_id: i64,
+    state: Json<RegistrationState>,
+) -> ApiResult<ApiRegistration> {
+    let person_id = user.person_id();
+    let drive = conn
+        .run(move |c| sql_interface::get_drive(c, drive_id))
+        .await?
+        .ok_or(ApiError::NotFound("drive"))?;
+    let currently_registered = conn
+        .run(move |c| sql_interface::is_registered(c, person_id, drive_id))
+        .await?;
+
+    if currently_registered != state.registered {
+        if state.registered {
+            possible_to_register(&drive, true)?;
+        }
+        let update = RegistrationUpdate {
+            drive_id,
+            person_id,
+            registered: state.registered,
+        };
+        apply_registration(&conn, mailer, update, person_id, false).await?;
+    }
+
+    let by = SearchRegistrationsBy::PersonId {
+        id: person_id,
+        filter: DriveFilter::ListAll,
+    };
+    conn.run(move |c| sql_interface::search_registrations(c, &by))
+        .await?
+        .into_iter()
+        .find(|registration| registration.drive.id == drive_id)
+        .map(|registration| Json(registration.into()))
+        .ok_or(ApiError::NotFound("drive"))
+}
+
+/// All drives, for anyone with a role.
+#[get("/drives")]
+async fn drives(conn: BususagesDBConn, user: ApiUser) -> ApiResult<DriveOverview> {
+    if user.roles().is_empty() {
+        return Err(ApiError::Forbidden);
+    }
+    Ok(Json(conn.run(sql_interface::list_drives).await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewDrive {
+    date: chrono::NaiveDate,
+    #[serde(default)]
+    label: String,
+    /// In UTC. Falls back to the `default-deadline` setting if left out.
+    deadline: Option<chrono::NaiveDateTime>,
+}
+
+#[post("/drives", data = "<drive>")]
+async fn create_drive(
+    conn: BususagesDBConn,
+    user: ApiUser,
+    drive: Json<NewDrive>,
+) -> Result<(Status, Json<Drive>), ApiError> {
+    require_role(&user, Role::Admin)?;
+    let NewDrive {
+        date,
+        label,
+        deadline,
+    } = drive.into_inner();
+    let label = label.trim().to_string();
+    let actor_id = user.person_id();
+
+    let created = conn
+        .run(move |c| {
+            let tx = c.transaction()?;
+            let deadline = match deadline {
+                Some(deadline) => Some(deadline),
+                None => sql_interface::default_deadline(&tx, date)?,
+            };
+            sql_interface::insert_new_drive(&tx, date, &label, deadline, Some(actor_id))?;
+            tx.commit()?;
+
+            let overview = sql_interface::list_drives(c)?;
+            Ok::<_, ApiError>(
+                overview
+                    .past
+                    .into_iter()
+                    .chain(overview.future)
+                    .find(|drive| drive.date == date && drive.label == label),
+            )
+        })
+        .await?
+        .ok_or(ApiError::Internal)?;
+    log::info!(
+        "Admin {} created drive {} through the API",
+        user.person_id(),
+        created.id
+    );
+
+    Ok((Status::Created, Json(created)))
+}
+
+/// Everyone a drive is meant for, like the printable list.
+#[get("/drives/<id>/registrations")]
+async fn drive_registrations(
+    conn: BususagesDBConn,
+    user: ApiUser,
+    id: i64,
+) -> ApiResult<Vec<ApiRegistration>> {
+    require_role(&user, Role::Driver)?;
+    conn.run(move |c| sql_interface::get_drive(c, id))
+        .await?
+        .ok_or(ApiError::NotFound("drive"))?;
+
+    let registrations = conn
+        .run(move |c| sql_interface::search_registrations(c, &SearchRegistrationsBy::DriveId(id)))
+        .await?;
+    Ok(Json(registrations.into_iter().map(Into::into).collect()))
+}
+
+#[get("/persons")]
+async fn persons(conn: BususagesDBConn, user: ApiUser) -> ApiResult<Vec<ApiPerson>> {
+    require_role(&user, Role::Registrar)?;
+    let persons = conn
+        .run(|c| sql_interface::list_all_persons(c, VisibilityFilter::IncludingInvisible))
+        .await?;
+    Ok(Json(persons.into_iter().map(Into::into).collect()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewPersonBody {
+    prename: String,
+    name: String,
+    email: lettre::Address,
+}
+
+#[post("/persons", data = "<person>")]
+async fn create_person(
+    conn: BususagesDBConn,
+    user: ApiUser,
+    person: Json<NewPersonBody>,
+) -> Result<(Status, Json<ApiPerson>), ApiError> {
+    require_role(&user, Role::Admin)?;
+    let NewPersonBody {
+        prename,
+        name,
+        email,
+    } = person.into_inner();
+
+    let new_person = NewPerson {
+        prename: prename.trim().to_string(),
+        name: name.trim().to_string(),
+        email,
+    };
+    let actor_id = user.person_id();
+    let created = conn
+        .run(move |c| {
+            let tx = c.transaction()?;
+            let person_id = sql_interface::insert_new_person(&tx, &new_person, Some(actor_id))?;
+            tx.commit()?;
+            let person = sql_interface::search_person(c, &SearchPersonBy::Id(person_id))?;
+            Ok::<_, ApiError>(person)
+        })
+        .await?;
+    log::info!(
+        "Admin {} created person {} through the API",
+        user.person_id(),
+        created.id
+    );
+
+    Ok((Status::Created, Json(created.into())))
+}
+
+/// Registers or unregisters someone else, bypassing deadline and registration cap just like the
+/// person panel does.
+#[put("/persons/<person_id>/registrations/<drive_id>", data = "<state>")]
+async fn update_person_registration(
+    conn: BususagesDBConn,
+    mailer: &State<Mailer>,
+    user: ApiUser,
+    person_id: i64,
+    drive_id: i64,
+    state: Json<RegistrationState>,
+) -> Result<Status, ApiError> {
+    require_role(&user, Role::Registrar)?;
+    conn.run(move |c| sql_interface::search_person(c, &SearchPersonBy::Id(person_id)))
+        .await?;
+
+    let update = RegistrationUpdate {
+        drive_id,
+        person_id,
+        registered: state.registered,
+    };
+    apply_registration(&conn, mailer, update, user.person_id(), true).await?;
+    Ok(Status::NoContent)
+}
@@ -0,0 +1,271 @@
+//! The append-only `audit_log`, recording who changed registrations, drives, persons, groups,
+//! sessions and settings. Entries are written in the same transaction as the change itself, so
+//! there's no change without an entry and the other way round.
+
+use {chrono::Utc, rocket_sync_db_pools::rusqlite, rusqlite::named_params, serde::Serialize};
+
+/// What kind of change an entry records.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditAction {
+    /// A person registered or unregistered, or someone did so for them.
+    Registration,
+    /// A person got a seat from the waitlist once one became free.
+    WaitlistPromotion,
+    /// Whether a person was on the bus was noted down.
+    Attendance,
+    /// A drive was created by hand, through the API or from a schedule.
+    DriveCreation,
+    /// The date, label, deadline or registration cap of a drive changed.
+    DriveUpdate,
+    /// A drive was restricted to other groups.
+    DriveGroupChange,
+    /// A drive was deleted together with its registrations.
+    DriveDeletion,
+    /// A person was created, including by a CSV import.
+    PersonCreation,
+    /// The name, email or visibility of a person changed, including by a CSV import.
+    PersonUpdate,
+    /// A CSV import of persons was applied.
+    PersonImport,
+    /// A person was deleted together with their registrations.
+    PersonDeletion,
+    /// The roles of a person changed.
+    RoleChange,
+    /// A group was created.
+    GroupCreation,
+    /// A group was deleted.
+    GroupDeletion,
+    /// A person was moved to another group or out of theirs.
+    GroupMembership,
+    /// All sessions of a person were ended.
+    SessionRevocation,
+    /// A superuser started viewing the site as a person.
+    ImpersonationStart,
+    /// A superuser stopped viewing the site as a person.
+    ImpersonationEnd,
+    /// A setting got a new value.
+    SettingChange,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 19] = [
+        AuditAction::Registration,
+        AuditAction::WaitlistPromotion,
+        AuditAction::Attendance,
+        AuditAction::DriveCreation,
+        AuditAction::DriveUpdate,
+        AuditAction::DriveGroupChange,
+        AuditAction::DriveDeletion,
+        AuditAction::PersonCreation,
+        AuditAction::PersonUpdate,
+        AuditAction::PersonImport,
+        AuditAction::PersonDeletion,
+        AuditAction::RoleChange,
+        AuditAction::GroupCreation,
+        AuditAction::GroupDeletion,
+        AuditAction::GroupMembership,
+        AuditAction::SessionRevocation,
+        AuditAction::ImpersonationStart,
+        AuditAction::ImpersonationEnd,
+        AuditAction::SettingChange,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Registration => "registration",
+            Self::WaitlistPromotion => "waitlist-promotion",
+            Self::Attendance => "attendance",
+            Self::DriveCreation => "drive-creation",
+            Self::DriveUpdate => "drive-update",
+            Self::DriveGroupChange => "drive-group-change",
+            Self::DriveDeletion => "drive-deletion",
+            Self::PersonCreation => "person-creation",
+            Self::PersonUpdate => "person-update",
+            Self::PersonImport => "person-import",
+            Self::PersonDeletion => "person-deletion",
+            Self::RoleChange => "role-change",
+            Self::GroupCreation => "group-creation",
+            Self::GroupDeletion => "group-deletion",
+            Self::GroupMembership => "group-membership",
+            Self::SessionRevocation => "session-revocation",
+            Self::ImpersonationStart => "impersonation-start",
+            Self::ImpersonationEnd => "impersonation-end",
+            Self::SettingChange => "setting-change",
+        }
+    }
+}
+
+impl std::str::FromStr for AuditAction {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|action| action.as_str() == name)
+            .ok_or(())
+    }
+}
+
+/// What a change was made to.
+#[derive(Clone, Copy, Debug)]
+pub enum AuditTarget<'a> {
+    Person(i64),
+    Drive(i64),
+    Registration {
+        person_id: i64,
+        drive_id: i64,
+    },
+    Group(i64),
+    Setting(&'a str),
+    /// Changes made to many things at once, like an import, which are listed in `after`.
+    Nothing,
+}
+
+/// Appends an entry to the audit log. `actor_id` is the person who made the change, which is
+/// [`Option::None`] if nobody in particular did, like on the command line. `before` and `after`
+/// are stored as JSON, [`serde_json::Value::Null`] if there's nothing, like after a deletion.
+pub fn record(
+    conn: &rusqlite::Connection,
+    actor_id: Option<i64>,
+    action: AuditAction,
+    target: AuditTarget,
+    before: serde_json::Value,
+    after: serde_json::Value,
+) -> Result<(), rusqlite::Error> {
+    let (person_id, drive_id, group_id, setting) = match target {
+        AuditTarget::Person(person_id) => (Some(person_id), None, None, None),
+        AuditTarget::Drive(drive_id) => (None, Some(drive_id), None, None),
+        AuditTarget::Registration {
+            person_id,
+            drive_id,
+        } => (Some(person_id), Some(drive_id), None, None),
+        AuditTarget::Group(group_id) => (None, None, Some(group_id), None),
+        AuditTarget::Setting(name) => (None, None, None, Some(name)),
+        AuditTarget::Nothing => (None, None, None, None),
+    };
+    let to_text = |value: serde_json::Value| (!value.is_null()).then(|| value.to_string());
+
+    conn.execute(
+        "INSERT INTO audit_log (happened, actor_id, action, target_person_id, target_drive_id,
+            target_group_id, target_setting, before, after)
+        VALUES (:happened, :actor_id, :action, :person_id, :drive_id, :group_id, :setting, :before,
+            :after)",
+        named_params! {
+            ":happened": Utc::now().timestamp(),
+            ":actor_id": actor_id,
+            ":action": action.as_str(),
+            ":person_id": person_id,
+            ":drive_id": drive_id,
+            ":group_id": group_id,
+            ":setting": setting,
+            ":before": to_text(before),
+            ":after": to_text(after),
+        },
+    )?;
+    Ok(())
+}
+
+/// An entry of the audit log, with the names of the persons, drives and groups as far as they still
+/// exist.
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    /// UNIX timestamp in seconds.
+    pub happened: i64,
+    pub actor_id: Option<i64>,
+    pub actor_name: Option<String>,
+    pub action: AuditAction,
+    pub target_person_id: Option<i64>,
+    pub target_person_name: Option<String>,
+    pub target_drive_id: Option<i64>,
+    pub target_drive_date: Option<chrono::NaiveDate>,
+    pub target_drive_label: Option<String>,
+    pub target_group_id: Option<i64>,
+    pub target_group_name: Option<String>,
+    pub target_setting: Option<String>,
+    /// JSON, if there was anything before.
+    pub before: Option<String>,
+    /// JSON, if there's anything after.
+    pub after: Option<String>,
+}
+
+/// Which entries [`list`] returns. Unset criteria match everything.
+#[derive(Debug, Default)]
+pub struct AuditFilter {
+    pub action: Option<AuditAction>,
+    /// Entries where the person either made the change or was affected by it.
+    pub person_id: Option<i64>,
+    pub drive_id: Option<i64>,
+    /// UNIX timestamp in seconds from which on entries are included.
+    pub since: Option<i64>,
+    /// UNIX timestamp in seconds before which entries are included.
+    pub until: Option<i64>,
+    /// How many of the newest entries are included at most.
+    pub limit: Option<u32>,
+}
+
+/// Lists the entries matching the filter, newest first.
+pub fn list(
+    conn: &rusqlite::Connection,
+    filter: &AuditFilter,
+) -> Result<Vec<AuditEntry>, rusqlite::Error> {
+    let mut statement = conn.prepare(
+        "SELECT audit_id, happened,
+            actor_id, actor.prename || ' ' || actor.name,
+            action,
+            target_person_id, target.prename || ' ' || target.name,
+            target_drive_id, drive.drivedate, drive.label,
+            target_group_id, person_group.name,
+            target_setting, before, after
+        FROM audit_log
+        LEFT JOIN person AS actor ON (actor.person_id == actor_id)
+        LEFT JOIN person AS target ON (target.person_id == target_person_id)
+        LEFT JOIN drive ON (drive.drive_id == target_drive_id)
+        LEFT JOIN person_group ON (person_group.group_id == target_group_id)
+        WHERE (:action IS NULL OR action == :action)
+            AND (:person_id IS NULL OR actor_id == :person_id OR target_person_id == :person_id)
+            AND (:drive_id IS NULL OR target_drive_id == :drive_id)
+            AND (:since IS NULL OR :since <= happened)
+            AND (:until IS NULL OR happened < :until)
+        ORDER BY audit_id DESC
+        LIMIT :limit",
+    )?;
+    let entries = statement
+        .query_map(
+            named_params! {
+                ":action": filter.action.map(AuditAction::as_str),
+                ":person_id": filter.person_id,
+                ":drive_id": filter.drive_id,
+                ":since": filter.since,
+                ":until": filter.until,
+                // a negative limit means none at all
+                ":limit": filter.limit.map_or(-1, i64::from),
+            },
+            |row| {
+                let action: String = row.get(4)?;
+                Ok(AuditEntry {
+                    id: row.get(0)?,
+                    happened: row.get(1)?,
+                    actor_id: row.get(2)?,
+                    actor_name: row.get(3)?,
+                    // can't be anything else due to the check constraint
+                    action: action
+                        .parse()
+                        .unwrap_or_else(|()| panic!("unknown action '{}' in audit_log", action)),
+                    target_person_id: row.get(5)?,
+                    target_person_name: row.get(6)?,
+                    target_drive_id: row.get(7)?,
+                    target_drive_date: row.get(8)?,
+                    target_drive_label: row.get(9)?,
+                    target_group_id: row.get(10)?,
+                    target_group_name: row.get(11)?,
+                    target_setting: row.get(12)?,
+                    before: row.get(13)?,
+                    after: row.get(14)?,
+                })
+            },
+        )?
+        .collect::<Result<_, _>>()?;
+    Ok(entries)
+}
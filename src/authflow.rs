@@ -1,30 +1,39 @@
 use {
     super::{
-        config::Config,
-        relative_to_absolute, server_error,
-        sql_interface::{self, SearchPersonBy, SearchPersonError},
+        config::{Config, RateLimits},
+        format_labeled_date,
+        mail::{MailError, Mailer},
+        notifications::{notify_registration_change, RegistrationChange},
+        possible_to_register,
+        ratelimit::{Lockout, RateLimiter},
+        relative_to_absolute, server_error, settings,
+        sql_interface::{
+            self, ApplyRegistrationError, Person, RegistrationUpdate, Role, Scope, SearchPersonBy,
+            SearchPersonError,
+        },
+        tokens::{
+            generate_token, hash_token, verify_code_hash, verify_feed_token_hash, verify_token_hash,
+        },
         BususagesDBConn,
     },
-    argon2::{
-        password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
-        Algorithm, Argon2, Params, Version,
-    },
-    base64ct::{Base64UrlUnpadded, Encoding},
     chrono::Utc,
     jwt::{SignWithKey, VerifyWithKey},
-    lettre::{message::Mailbox, transport::smtp::authentication::Credentials, AsyncTransport},
     rand::Rng,
     rocket::{
-        config::Config as RocketConfig,
+        fairing::AdHoc,
         form::{Form, Strict},
-        http::{Cookie, CookieJar, SameSite, Status},
+        http::{Cookie, CookieJar, Method, SameSite, Status},
         request::{FlashMessage, FromRequest, Outcome, Request},
         response::{Flash, Redirect},
         State,
     },
     rocket_dyn_templates::{context, Template},
     serde::{Deserialize, Serialize},
-    std::{fmt, time::Duration},
+    std::{
+        fmt,
+        net::IpAddr,
+        time::{Duration, Instant},
+    },
     thiserror::Error,
 };
 
@@ -33,6 +42,16 @@ pub struct LoginForm {
     email: String,
 }
 
+#[derive(FromForm)]
+pub struct LoginCodeForm {
+    email: String,
+    code: String,
+}
+
+/// After this many attempts, the code of a person isn't accepted anymore and they need to request a
+/// new mail.
+const LOGIN_CODE_ATTEMPTS: u32 = 5;
+
 #[get("/", rank = 2)]
 pub async fn index(
     conn: BususagesDBConn,
@@ -40,7 +59,7 @@ pub async fn index(
 ) -> Result<Template, Flash<Redirect>> {
     // TODO potential DoS, performing a query on _every_ index call? doesn't require auth
     let login_message = conn
-        .run(|c| sql_interface::get_setting(c, "login-message"))
+        .run(|c| settings::LOGIN_MESSAGE.get_text(c))
         .await
         .map_err(|err| {
             server_error(
@@ -48,53 +67,140 @@ pub async fn index(
                 "ein Fehler trat auf, während ich nach den Einstellungen geschaut habe",
             )
         })?;
-    let login_message = sql_interface::stringify_value(login_message);
+
+    let lockout = flash
+        .as_ref()
+        .map(|flash| flash.kind() == "lockout")
+        .unwrap_or(false);
 
     Ok(Template::render(
         "login",
         context! {
             flash: flash.map(|flash| flash.message().to_string()),
+            lockout,
             login_message,
         },
     ))
 }
 
-/// Generates a token with 128 random bytes, constant-time encoded in URL-safe base64. Also returns
-/// the random bytes used.
-fn generate_token() -> ([u8; 128], String) {
-    let mut rng = rand::thread_rng();
-    let mut bytes = [0_u8; 128];
+/// All limits on login attempts, available as managed state once [`rate_limit_fairing`] is
+/// attached.
+pub struct LoginLimits {
+    mails_per_email: RateLimiter<String>,
+    mails_per_ip: RateLimiter<IpAddr>,
+    failures_per_person: RateLimiter<i64>,
+    failures_per_ip: RateLimiter<IpAddr>,
+}
+
+impl LoginLimits {
+    pub fn new(limits: &RateLimits) -> Self {
+        let window = Duration::from_secs(limits.window_minutes * 60);
+        Self {
+            mails_per_email: RateLimiter::new(limits.login_mails_per_email, window),
+            mails_per_ip: RateLimiter::new(limits.login_mails_per_ip, window),
+            failures_per_person: RateLimiter::new(limits.failed_logins_per_person, window),
+            failures_per_ip: RateLimiter::new(limits.failed_logins_per_ip, window),
+        }
+    }
+
+    /// Uses up one login mail for the address and the IP, if both have one left. The IP is unknown
+    /// if Rocket couldn't determine it, then only the address is limited.
+    fn login_mail(&self, email: &str, ip: Option<IpAddr>) -> Result<(), Lockout> {
+        let now = Instant::now();
+        self.mails_per_email.attempt(email.to_string(), now)?;
+        if let Some(ip) = ip {
+            if let Err(lockout) = self.mails_per_ip.attempt(ip, now) {
+                self.mails_per_email.undo(&email.to_string(), now);
+                return Err(lockout);
+            }
+        }
+        Ok(())
+    }
+
+    /// Counts a login link or code for the person from the IP as invalid before it's even checked,
+    /// if both have attempts left. Counting afterwards would let parallel requests all get checked.
+    /// Valid ones are handed back with [`LoginLimits::forgive`].
+    fn reserve_failure(
+        &self,
+        person_id: i64,
+        ip: Option<IpAddr>,
+    ) -> Result<FailureReservation, Lockout> {
+        let now = Instant::now();
+        self.failures_per_person.attempt(person_id, now)?;
+        if let Some(ip) = ip {
+            if let Err(lockout) = self.failures_per_ip.attempt(ip, now) {
+                self.failures_per_person.undo(&person_id, now);
+                return Err(lockout);
+            }
+        }
+        Ok(FailureReservation {
+            person_id,
+            ip,
+            at: now,
+        })
+    }
+
+    /// Takes back a reserved failure, since the login link or code was valid after all.
+    fn forgive(&self, reservation: FailureReservation) {
+        self.failures_per_person
+            .undo(&reservation.person_id, reservation.at);
+        if let Some(ip) = reservation.ip {
+            self.failures_per_ip.undo(&ip, reservation.at);
+        }
+    }
+
+    /// Counts an invalid login attempt for an email address nobody has, so only the IP is known.
+    fn record_unknown_person(&self, ip: Option<IpAddr>) {
+        if let Some(ip) = ip {
+            // already locked out, which is all that'd be recorded
+            let _ = self.failures_per_ip.attempt(ip, Instant::now());
+        }
+    }
+}
+
+/// A login attempt already counted as failure, see [`LoginLimits::reserve_failure`].
+#[must_use]
+struct FailureReservation {
+    person_id: i64,
+    ip: Option<IpAddr>,
+    at: Instant,
+}
 
-    rng.fill(&mut bytes);
+/// Puts [`LoginLimits`] into managed state. Needs to be attached after the [`Config`] fairing.
+pub fn rate_limit_fairing() -> AdHoc {
+    AdHoc::on_ignite("Login rate limits", |rocket| async move {
+        let limits = {
+            let config = rocket
+                .state::<Config>()
+                .expect("Config is not set in main!");
+            LoginLimits::new(&config.rate_limits)
+        };
+        rocket.manage(limits)
+    })
+}
 
-    (bytes, Base64UrlUnpadded::encode_string(&bytes))
+fn lockout_flash(lockout: Lockout) -> Flash<Redirect> {
+    Flash::new(Redirect::to(uri!(index)), "lockout", lockout.to_string())
 }
 
-#[derive(Error, Debug)]
-enum SendMailError {
-    #[error("SMTP error sending mail: {0}")]
-    LettreError(#[from] lettre::transport::smtp::Error),
-    #[error("Error while building email: {0}")]
-    BuildError(#[from] lettre::error::Error),
+/// Generates a random six-digit code, for typing in by hand.
+fn generate_login_code() -> String {
+    format!("{:06}", rand::thread_rng().gen_range(0..1_000_000))
 }
 
-/// Sends a login mail with the given credentials and mail server.
-async fn send_login_mail(
+/// Sends a login mail containing the given link and code.
+pub async fn send_login_mail(
+    mailer: &Mailer,
     url: impl AsRef<str> + fmt::Debug + fmt::Display,
-    from: lettre::Address,
+    code: &str,
     to: lettre::Address,
-    password: &str,
-    smtp_server: &str,
-) -> Result<(), SendMailError> {
-    let email = lettre::Message::builder()
-        .from(Mailbox::new(
-            Some("Komplett normale Busliste".to_string()),
-            from.clone(),
-        ))
-        .to(Mailbox::new(None, to))
-        .subject("[Komplett normale Busliste] Anmeldung")
-        .body(format!(
-            r#"Hallo,
+) -> Result<(), MailError> {
+    mailer
+        .send(
+            to,
+            "[Komplett normale Busliste] Anmeldung",
+            format!(
+                r#"Hallo,
 
 hier ist dein Link für die Anmeldung in Komplett normale Busliste. Er wird
 in einer Stunde automatisch ungültig, aber sobald du einmal angemeldet bist,
@@ -102,6 +208,9 @@ bist du das 30 Tage lang.
 
 Hier ist dein Link: {}
 
+Falls du dich auf einem anderen Gerät anmelden willst, kannst du dort stattdessen
+auch diesen Code eingeben, er ist 10 Minuten lang gültig: {}
+
 Mit freundlichen Grüßen,
 Komplett normale Busliste
 
@@ -111,36 +220,29 @@ Komplett normale Busliste
  versuche erneut, eine Email anzufordern.)
 
 (P.P.S. Zudem kann ein Link nur einmal verwendet werden. Tut mir leid.)"#,
-            url,
-        ))?;
-
-    let creds = Credentials::new(from.to_string(), password.to_string());
-    let conn = lettre::AsyncSmtpTransport::<lettre::Tokio1Executor>::relay(smtp_server)?
-        .credentials(creds)
-        .build();
-
-    conn.send(email).await?;
-    Ok(())
-}
-
-// Constructs an [`argon2::Argon2`] instance with reasonable settings.
-fn construct_argon2_instance() -> Argon2<'static> {
-    Argon2::new(
-        Algorithm::Argon2id,
-        Version::V0x13,
-        Params::new(15_u32 * 1024_u32, 2, 1, None).unwrap(),
-    )
+                url, code,
+            ),
+        )
+        .await
 }
 
 #[post("/", data = "<login_details>")]
 pub async fn login(
     conn: BususagesDBConn,
     config: &State<Config>,
+    mailer: &State<Mailer>,
+    limits: &State<LoginLimits>,
+    client_ip: Option<IpAddr>,
     login_details: Form<Strict<LoginForm>>,
 ) -> Result<Flash<Redirect>, Flash<Redirect>> {
     // strip and normalize a bit
     let login_email = login_details.email.trim().to_lowercase();
 
+    // counted even if the address doesn't exist, otherwise one could probe addresses for free
+    limits
+        .login_mail(&login_email, client_ip)
+        .map_err(lockout_flash)?;
+
     // first try to find the email in the database
     let search_result = conn
         .run(move |c| sql_interface::search_person(c, &SearchPersonBy::Email(login_email)))
@@ -161,61 +263,53 @@ pub async fn login(
         Ok(address) => address,
     };
 
-    // second, generate the token
+    // second, generate the token and the code
     let (raw_token, encoded_token) = generate_token();
+    let code = generate_login_code();
     let url = uri!(
         config.outside_address.clone(),
         verify_token(token = encoded_token, person_id = person.id)
     );
 
     // third, send the email to the search result
-    // or if running in debug mode, just print the login link instead
-    let rocket_config = RocketConfig::figment();
-    if rocket_config.profile() == RocketConfig::DEBUG_PROFILE {
-        log::info!("login link: {}", url);
-        drop(url);
-    } else {
-        let send_mail_result = send_login_mail(
-            url.to_string(),
-            config.email.clone(),
-            person.email.clone(),
-            &config.email_creds,
-            &config.smtp_server,
-        );
-        match send_mail_result.await {
-            Err(SendMailError::LettreError(err)) => {
-                let (logmsg, flashmsg) = if err.is_permanent() {
-                    (
-                        format!("Permanent SMTP error while sending email: {}", err),
-                        "ein permanenter Fehler trat auf, während ich versuchte, die Anmeldemail zu verschicken",
-                    )
-                } else if err.is_transient() {
-                    (
-                        format!("Transient SMTP error while sending email: {}", err),
-                        "ein temporärer Fehler trat auf, während ich versuchte, die Anmeldemail zu verschicken",
-                    )
-                } else {
-                    (
-                        format!("Error occured while trying to send email: {}", err),
-                        "ein Fehler trat auf, während ich versuchte, die Anmeldemail zu verschicken",
-                    )
-                };
-                return Err(server_error(&logmsg, flashmsg));
-            }
-            Err(SendMailError::BuildError(err)) => panic!("{}", err),
-            _ => (),
+    match send_login_mail(mailer, url.to_string(), &code, person.email.clone()).await {
+        Err(MailError::SmtpError(err)) => {
+            let (logmsg, flashmsg) = if err.is_permanent() {
+                (
+                    format!("Permanent SMTP error while sending email: {}", err),
+                    "ein permanenter Fehler trat auf, während ich versuchte, die Anmeldemail zu verschicken",
+                )
+            } else if err.is_transient() {
+                (
+                    format!("Transient SMTP error while sending email: {}", err),
+                    "ein temporärer Fehler trat auf, während ich versuchte, die Anmeldemail zu verschicken",
+                )
+            } else {
+                (
+                    format!("Error occured while trying to send email: {}", err),
+                    "ein Fehler trat auf, während ich versuchte, die Anmeldemail zu verschicken",
+                )
+            };
+            return Err(server_error(&logmsg, flashmsg));
+        }
+        Err(MailError::BuildError(err)) => panic!("{}", err),
+        Err(err) => {
+            return Err(server_error(
+                format!("Error occured while trying to send email: {}", err),
+                "ein Fehler trat auf, während ich versuchte, die Anmeldemail zu verschicken",
+            ))
         }
+        Ok(()) => (),
     }
 
-    // fourth, hash token and insert into DB
-    let salt = SaltString::generate(rand::thread_rng());
-    let argon2 = construct_argon2_instance();
-    let hashed_token = argon2
-        .hash_password(&raw_token, &salt)
-        .expect("Could not hash token!")
-        .to_string();
+    // fourth, hash token and code and insert into DB
+    let hashed_token = hash_token(&raw_token);
+    let hashed_code = hash_token(code.as_bytes());
     if let Err(err) = conn
-        .run(move |c| sql_interface::update_token(c, person.id, Some(hashed_token)))
+        .run(move |c| {
+            sql_interface::update_token(c, person.id, Some(hashed_token))?;
+            sql_interface::set_login_code(c, person.id, Some(hashed_code))
+        })
         .await
     {
         return Err(server_error(
@@ -226,7 +320,7 @@ pub async fn login(
 
     Ok(Flash::success(
         Redirect::to(uri!(index)),
-        "Anmeldelink per Email versendet. Folge diesem, um fortzufahren.\n\nHinweis: Das heißt, die Adresse wurde gefunden und alles ist ok!",
+        "Anmeldelink und -code per Email versendet. Folge dem Link oder gib den Code unten ein, um fortzufahren.\n\nHinweis: Das heißt, die Adresse wurde gefunden und alles ist ok!",
     ))
 }
 
@@ -251,7 +345,11 @@ fn verify_failure_flash() -> Flash<Redirect> {
 struct Claims {
     exp: i64,
     sub: i64,
-    superuser: bool,
+    /// The ID of the session in the database, which has to exist for the JWT to be valid.
+    sid: i64,
+    /// The superuser viewing the site as this person, if any.
+    #[serde(default)]
+    imp: Option<i64>,
 }
 
 impl Claims {
@@ -259,25 +357,291 @@ impl Claims {
         timepoint_expired(self.exp)
     }
 
-    fn try_from_request(req: &Request<'_>) -> Result<Self, AuthError> {
+    /// Verifies the signature and expiration of a JWT, regardless of how the client sent it.
+    fn verify(req: &Request<'_>, jwt: &str) -> Result<Self, AuthError> {
         let config = req
             .rocket()
             .state::<Config>()
             .expect("Config is not set in main!");
-        let cookies = req.cookies();
-        let claims: Claims = cookies
-            .get("auth-token")
-            .ok_or(AuthError::CookieNotFound)?
-            .value()
-            .verify_with_key(&config.jwt_key)?;
+        let claims: Claims = jwt.verify_with_key(&config.jwt_key)?;
 
         if claims.expired() {
-            cookies.remove(Cookie::named("auth-token"));
             return Err(AuthError::JwtExpired);
         }
 
         Ok(claims)
     }
+
+    fn try_from_request(req: &Request<'_>) -> Result<Self, AuthError> {
+        let cookies = req.cookies();
+        let cookie = cookies.get("auth-token").ok_or(AuthError::CookieNotFound)?;
+
+        let claims = Self::verify(req, cookie.value());
+        if let Err(AuthError::JwtExpired) = claims {
+            cookies.remove(Cookie::named("auth-token"));
+        }
+        claims
+    }
+
+    /// Takes the JWT from the `Authorization: Bearer` header instead of the cookie, as sent by
+    /// clients of the JSON API.
+    fn try_from_bearer(req: &Request<'_>) -> Result<Self, AuthError> {
+        let jwt = req
+            .headers()
+            .get_one("Authorization")
+            .and_then(|header| header.strip_prefix("Bearer "))
+            .ok_or(AuthError::BearerNotFound)?;
+        Self::verify(req, jwt.trim())
+    }
+
+    /// Whether the session of the JWT still exists in the database and wasn't revoked.
+    async fn session_valid(&self, req: &Request<'_>) -> Result<bool, AuthError> {
+        let conn = BususagesDBConn::get_one(req.rocket())
+            .await
+            .expect("Database fairing not attached!");
+        let (session_id, person_id) = (self.sid, self.sub);
+        let now = Utc::now().timestamp();
+        Ok(conn
+            .run(move |c| sql_interface::session_valid(c, session_id, person_id, now))
+            .await?)
+    }
+
+    /// Like [`Claims::try_from_request`], but also checks that the session wasn't revoked in the
+    /// meantime.
+    async fn try_from_request_with_session(req: &Request<'_>) -> Result<Self, AuthError> {
+        let claims = Self::try_from_request(req)?;
+
+        if !claims.session_valid(req).await? {
+            req.cookies().remove(Cookie::named("auth-token"));
+            return Err(AuthError::SessionRevoked);
+        }
+
+        Ok(claims)
+    }
+}
+
+/// Prefix of personal API tokens, which tells them apart from JWTs in the `Authorization` header.
+pub const API_TOKEN_PREFIX: &str = "knb_";
+
+/// The personal API token in the `Authorization: Bearer` header without its prefix, if there is
+/// one. JWTs sent the same way by clients of the JSON API aren't API tokens.
+fn bearer_api_token<'a>(req: &'a Request<'_>) -> Option<&'a str> {
+    req.headers()
+        .get_one("Authorization")?
+        .strip_prefix("Bearer ")?
+        .trim()
+        .strip_prefix(API_TOKEN_PREFIX)
+}
+
+/// The routes of the JSON API each scope allows, as method and mount path. Segments in angle
+/// brackets stand for any value. Everything else, including all routes of the site itself, is
+/// refused for API tokens.
+const SCOPE_ROUTES: &[(Scope, Method, &str)] = &[
+    (Scope::Read, Method::Get, "/api/v1/me"),
+    (Scope::Read, Method::Get, "/api/v1/registrations"),
+    (Scope::Read, Method::Get, "/api/v1/drives"),
+    (
+        Scope::Read,
+        Method::Get,
+        "/api/v1/drives/<id>/registrations",
+    ),
+    (
+        Scope::Register,
+        Method::Put,
+        "/api/v1/registrations/<drive_id>",
+    ),
+    (Scope::Drives, Method::Post, "/api/v1/drives"),
+];
+
+/// Whether a token with the scope may be used for the request, see [`SCOPE_ROUTES`].
+pub fn scope_permits(scope: Scope, method: Method, path: &str) -> bool {
+    SCOPE_ROUTES
+        .iter()
+        .any(|(route_scope, route_method, route)| {
+            *route_scope == scope && *route_method == method && route_matches(route, path)
+        })
+}
+
+/// Whether the path matches the route from [`SCOPE_ROUTES`] segment by segment.
+fn route_matches(route: &str, path: &str) -> bool {
+    route.split('/').count() == path.split('/').count()
+        && route
+            .split('/')
+            .zip(path.split('/'))
+            .all(|(expected, segment)| {
+                expected == segment || (expected.starts_with('<') && !segment.is_empty())
+            })
+}
+
+/// Checks a personal API token of the form `<id>_<secret>` against its hash and its scopes against
+/// the request. Returns the ID of the person owning it.
+async fn authenticate_api_token(req: &Request<'_>, token: &str) -> Result<i64, AuthError> {
+    let (id, secret) = token
+        .split_once('_')
+        .and_then(|(id, secret)| Some((id.parse::<i64>().ok()?, secret.to_string())))
+        .ok_or(AuthError::InvalidApiToken)?;
+
+    let conn = BususagesDBConn::get_one(req.rocket())
+        .await
+        .expect("Database fairing not attached!");
+    let api_token = conn
+        .run(move |c| sql_interface::get_api_token(c, id))
+        .await?
+        .ok_or(AuthError::InvalidApiToken)?;
+    if !verify_feed_token_hash(&secret, &api_token.hash) {
+        return Err(AuthError::InvalidApiToken);
+    }
+
+    let path = req.uri().path();
+    if !api_token
+        .scopes
+        .iter()
+        .any(|scope| scope_permits(*scope, req.method(), path.as_str()))
+    {
+        return Err(AuthError::MissingScope);
+    }
+
+    let now = Utc::now().timestamp();
+    conn.run(move |c| sql_interface::touch_api_token(c, id, now))
+        .await?;
+    Ok(api_token.person_id)
+}
+
+/// Starts a new session for the person and stores a JWT referring to it in a cookie, so they're
+/// logged in for 30 days or until the session is revoked.
+async fn sign_in(
+    conn: &BususagesDBConn,
+    jar: &CookieJar<'_>,
+    config: &Config,
+    person: &Person,
+) -> Result<(), rusqlite::Error> {
+    let claims = start_session(
+        conn,
+        person.id,
+        None,
+        Duration::from_secs(60 * 60 * 24 * 30),
+    )
+    .await?;
+    let jwt = claims.sign_with_key(&config.jwt_key).unwrap();
+    jar.add(
+        Cookie::build("auth-token", jwt)
+            .same_site(SameSite::Lax)
+            .max_age(time::Duration::days(30))
+            .finish(),
+    );
+    Ok(())
+}
+
+/// Starts a new session for an API client and returns the signed JWT for it together with its
+/// expiration, as UNIX timestamp. Just like logging in on the site, it's valid for 30 days or until
+/// the session is revoked.
+pub async fn sign_in_api(
+    conn: &BususagesDBConn,
+    config: &Config,
+    person_id: i64,
+) -> Result<(String, i64), rusqlite::Error> {
+    let claims = start_session(
+        conn,
+        person_id,
+        None,
+        Duration::from_secs(60 * 60 * 24 * 30),
+    )
+    .await?;
+    let expiration = claims.exp;
+    let jwt = claims.sign_with_key(&config.jwt_key).unwrap();
+    Ok((jwt, expiration))
+}
+
+/// Inserts a session valid for `lifetime` into the DB and returns the claims for it.
+async fn start_session(
+    conn: &BususagesDBConn,
+    person_id: i64,
+    impersonator_id: Option<i64>,
+    lifetime: Duration,
+) -> Result<Claims, rusqlite::Error> {
+    let expiration = relative_to_absolute(lifetime);
+    let session_id = conn
+        .run(move |c| sql_interface::create_session(c, person_id, expiration, impersonator_id))
+        .await?;
+
+    Ok(Claims {
+        exp: expiration,
+        sub: person_id,
+        sid: session_id,
+        imp: impersonator_id,
+    })
+}
+
+/// How long a superuser may view the site as someone else before having to start over.
+const IMPERSONATION_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// Lets the superuser view the site as the given person, by swapping their own JWT for one of a
+/// separate session. Their own JWT is kept in another cookie, so [`stop_impersonating`] can
+/// return to it. The new session never has any roles, even if the person has some.
+pub async fn impersonate(
+    conn: &BususagesDBConn,
+    jar: &CookieJar<'_>,
+    config: &Config,
+    superuser: &Superuser,
+    person_id: i64,
+) -> Result<(), rusqlite::Error> {
+    let claims = start_session(
+        conn,
+        person_id,
+        Some(superuser.person_id()),
+        IMPERSONATION_LIFETIME,
+    )
+    .await?;
+    let jwt = claims.sign_with_key(&config.jwt_key).unwrap();
+
+    if let Some(own_jwt) = jar.get("auth-token") {
+        jar.add(
+            Cookie::build("impersonator-token", own_jwt.value().to_string())
+                .same_site(SameSite::Lax)
+                .max_age(time::Duration::days(30))
+                .finish(),
+        );
+    }
+    jar.add(
+        Cookie::build("auth-token", jwt)
+            .same_site(SameSite::Lax)
+            .max_age(time::Duration::hours(1))
+            .finish(),
+    );
+    Ok(())
+}
+
+/// Ends the impersonation session of the user and restores the JWT of the superuser. Returns
+/// whether that was possible, if not, the superuser needs to log in again.
+pub async fn stop_impersonating(
+    conn: &BususagesDBConn,
+    jar: &CookieJar<'_>,
+    user: &User,
+) -> Result<bool, rusqlite::Error> {
+    if let Some(session_id) = user.session_id() {
+        conn.run(move |c| sql_interface::delete_session(c, session_id))
+            .await?;
+    }
+
+    match jar
+        .get("impersonator-token")
+        .map(|cookie| cookie.value().to_string())
+    {
+        Some(own_jwt) => {
+            jar.remove(Cookie::named("impersonator-token"));
+            jar.add(
+                Cookie::build("auth-token", own_jwt)
+                    .same_site(SameSite::Lax)
+                    .max_age(time::Duration::days(30))
+                    .finish(),
+            );
+            Ok(true)
+        }
+        None => {
+            jar.remove(Cookie::named("auth-token"));
+            Ok(false)
+        }
+    }
 }
 
 #[get("/login/<token>?<person_id>")]
@@ -285,9 +649,16 @@ pub async fn verify_token(
     conn: BususagesDBConn,
     jar: &CookieJar<'_>,
     config: &State<Config>,
+    limits: &State<LoginLimits>,
+    client_ip: Option<IpAddr>,
     token: String,
     person_id: i64,
 ) -> Flash<Redirect> {
+    let reservation = match limits.reserve_failure(person_id, client_ip) {
+        Ok(reservation) => reservation,
+        Err(lockout) => return lockout_flash(lockout),
+    };
+
     // first, find the person (which contains the token hash) in the DB
     let search_result = conn
         .run(move |c| sql_interface::search_person(c, &SearchPersonBy::Id(person_id)))
@@ -315,48 +686,48 @@ pub async fn verify_token(
         return verify_failure_flash();
     }
 
-    let db_token = if let Some(token) = person.token {
+    let db_token = if let Some(token) = &person.token {
         token
     } else {
         return verify_failure_flash();
     };
-    let client_token_bytes = match Base64UrlUnpadded::decode_vec(&token) {
-        // possibly evil client, but we just friendly say "something happened and idk what"
-        Err(_) => return verify_failure_flash(),
-        Ok(x) => x,
-    };
 
     // third, verify client token with token hash we got above
-    let argon2 = construct_argon2_instance();
-    let db_token_hash = PasswordHash::new(&db_token).expect("Invalid token hash in DB!");
-    let passed_verification = argon2
-        .verify_password(&client_token_bytes, &db_token_hash)
-        .is_ok();
-    if !passed_verification {
+    if !verify_token_hash(&token, db_token) {
         return verify_failure_flash();
     }
 
-    // the person is authenticated, let's delete the login token because it's useless now
-    let person_id = person.id;
-    conn.run(move |c| sql_interface::update_token(c, person_id, None))
-        .await
-        .unwrap();
+    // fourth, start a session and store a JWT for it in a cookie
+    limits.forgive(reservation);
+    clear_login_secrets(&conn, person_id).await;
+    finish_login(&conn, jar, config, &person).await
+}
 
-    // fourth, generate a JWT and store it in a cookie
-    let claims = Claims {
-        exp: relative_to_absolute(Duration::from_secs(60 * 60 * 24 * 30)),
-        sub: person.id,
-        superuser: person.is_superuser,
-    };
-    let jwt = claims.sign_with_key(&config.jwt_key).unwrap();
-    jar.add(
-        Cookie::build("auth-token", jwt)
-            .same_site(SameSite::Lax)
-            .max_age(time::Duration::days(30))
-            .finish(),
-    );
+/// Deletes the login token and code of a person who just authenticated, they're useless now.
+async fn clear_login_secrets(conn: &BususagesDBConn, person_id: i64) {
+    conn.run(move |c| {
+        sql_interface::update_token(c, person_id, None)?;
+        sql_interface::set_login_code(c, person_id, None)
+    })
+    .await
+    .unwrap();
+}
+
+/// Logs in a person who just proved to own their email address, with either the link or the code.
+async fn finish_login(
+    conn: &BususagesDBConn,
+    jar: &CookieJar<'_>,
+    config: &Config,
+    person: &Person,
+) -> Flash<Redirect> {
+    if let Err(err) = sign_in(conn, jar, config, person).await {
+        return server_error(
+            format!("Database error while starting session: {}", err),
+            "ein Fehler trat auf, während ich versuchte, dich anzumelden",
+        );
+    }
 
-    let redirect = if person.is_superuser {
+    let redirect = if !person.roles.is_empty() {
         Redirect::to(uri!(super::superuser::panel))
     } else {
         Redirect::to(uri!(super::dashboard))
@@ -367,14 +738,334 @@ pub async fn verify_token(
     )
 }
 
+fn code_failure_flash() -> Flash<Redirect> {
+    Flash::error(
+        Redirect::to(uri!(index)),
+        "Ungültiger oder abgelaufener Code. Bitte versuche es erneut oder fordere eine neue Email an!",
+    )
+}
+
+/// Why [`check_login_code`] refused a code.
+pub enum CodeFailure {
+    /// Unknown email, wrong or expired code, whatever it was, the client shouldn't know.
+    Invalid,
+    /// Too many wrong attempts, the code isn't even checked.
+    Lockout(Lockout),
+}
+
+/// Checks the six-digit code from the login mail of the person with the given email. Wrong codes
+/// count towards the rate limits. On success, the login token and code are deleted, so the caller
+/// only needs to start a session.
+pub async fn check_login_code(
+    conn: &BususagesDBConn,
+    limits: &LoginLimits,
+    client_ip: Option<IpAddr>,
+    email: &str,
+    code: &str,
+) -> Result<Person, CodeFailure> {
+    let login_email = email.trim().to_lowercase();
+
+    // first, find the person, also fetching roles and so on by searching by ID
+    let person_id = match conn
+        .run(move |c| sql_interface::search_person(c, &SearchPersonBy::Email(login_email)))
+        .await
+    {
+        Ok(person) => person.id,
+        Err(SearchPersonError::NotFound) => {
+            limits.record_unknown_person(client_ip);
+            return Err(CodeFailure::Invalid);
+        }
+        Err(err) => {
+            log::error!("Error while searching for person by email: {}", err);
+            return Err(CodeFailure::Invalid);
+        }
+    };
+    let reservation = limits
+        .reserve_failure(person_id, client_ip)
+        .map_err(CodeFailure::Lockout)?;
+    let failure = || Err(CodeFailure::Invalid);
+
+    let person = match conn
+        .run(move |c| sql_interface::search_person(c, &SearchPersonBy::Id(person_id)))
+        .await
+    {
+        Ok(person) => person,
+        Err(err) => {
+            log::error!("Error while searching for person by ID: {}", err);
+            return failure();
+        }
+    };
+
+    // second, use up one of the attempts for the code, unless it expired or they're all used up.
+    // That happens before verifying, so parallel requests can't try more codes than allowed.
+    let now = Utc::now().timestamp();
+    let hash = match conn
+        .run(move |c| {
+            sql_interface::reserve_login_code_attempt(c, person_id, LOGIN_CODE_ATTEMPTS, now)
+        })
+        .await
+    {
+        Ok(Some(hash)) => hash,
+        Ok(None) => return failure(),
+        Err(err) => {
+            log::error!("Database error while counting login code attempt: {}", err);
+            return failure();
+        }
+    };
+
+    // third, verify the code
+    if !verify_code_hash(code, &hash) {
+        return failure();
+    }
+
+    limits.forgive(reservation);
+    clear_login_secrets(conn, person_id).await;
+    Ok(person)
+}
+
+/// Logs a person in with the six-digit code from the login mail, as alternative to the link.
+#[post("/login/code", data = "<login_details>")]
+pub async fn verify_code(
+    conn: BususagesDBConn,
+    jar: &CookieJar<'_>,
+    config: &State<Config>,
+    limits: &State<LoginLimits>,
+    client_ip: Option<IpAddr>,
+    login_details: Form<Strict<LoginCodeForm>>,
+) -> Flash<Redirect> {
+    match check_login_code(
+        &conn,
+        limits,
+        client_ip,
+        &login_details.email,
+        &login_details.code,
+    )
+    .await
+    {
+        Ok(person) => finish_login(&conn, jar, config, &person).await,
+        Err(CodeFailure::Lockout(lockout)) => lockout_flash(lockout),
+        Err(CodeFailure::Invalid) => code_failure_flash(),
+    }
+}
+
+#[derive(FromForm)]
+pub struct QuickRegistrationForm {
+    id: i64,
+    token: String,
+}
+
+/// Looks up the quick registration and checks its token, without using it up.
+async fn valid_quick_registration(
+    conn: &BususagesDBConn,
+    id: i64,
+    token: &str,
+) -> Result<sql_interface::QuickRegistration, Flash<Redirect>> {
+    let quick_registration = match conn
+        .run(move |c| sql_interface::get_quick_registration(c, id))
+        .await
+    {
+        Ok(Some(quick_registration)) => quick_registration,
+        Ok(None) => return Err(verify_failure_flash()),
+        Err(err) => {
+            log::error!("Database error while searching quick registration: {}", err);
+            return Err(verify_failure_flash());
+        }
+    };
+
+    if timepoint_expired(quick_registration.token_expiration)
+        || !verify_token_hash(token, &quick_registration.token)
+    {
+        return Err(verify_failure_flash());
+    }
+    Ok(quick_registration)
+}
+
+/// Asks for confirmation before using the link from a reminder mail. Mail scanners and link
+/// previews open links on their own, so following the link mustn't change anything yet, that's
+/// only done by [`confirm_quick_register`].
+#[get("/register/quick/<token>?<id>")]
+pub async fn quick_register(
+    conn: BususagesDBConn,
+    token: String,
+    id: i64,
+) -> Result<Template, Flash<Redirect>> {
+    let quick_registration = valid_quick_registration(&conn, id, &token).await?;
+
+    let drive_id = quick_registration.drive_id;
+    let drive = conn
+        .run(move |c| sql_interface::get_drive(c, drive_id))
+        .await
+        .map_err(|err| {
+            server_error(
+                format!("Error while querying drive {}: {}", drive_id, err),
+                "ein Fehler trat während des Abfragens der Busfahrt auf",
+            )
+        })?
+        .ok_or_else(|| {
+            Flash::error(
+                Redirect::to(uri!(index)),
+                "Die Busfahrt existiert nicht mehr.",
+            )
+        })?;
+
+    Ok(Template::render(
+        "quick-register",
+        context! {
+            pretty_date: format_labeled_date(drive.date, &drive.label),
+            id,
+            token,
+        },
+    ))
+}
+
+/// Logs a person in and registers them for a drive at once, using a one-use token from a reminder
+/// mail. The token is bound to that person and drive and expires with the drive's deadline.
+#[post("/register/quick", data = "<confirmation>")]
+pub async fn confirm_quick_register(
+    conn: BususagesDBConn,
+    jar: &CookieJar<'_>,
+    config: &State<Config>,
+    mailer: &State<Mailer>,
+    confirmation: Form<Strict<QuickRegistrationForm>>,
+) -> Flash<Redirect> {
+    let id = confirmation.id;
+    let quick_registration = match valid_quick_registration(&conn, id, &confirmation.token).await {
+        Ok(quick_registration) => quick_registration,
+        Err(flash) => return flash,
+    };
+
+    // only whoever deletes the token gets to use it
+    match conn
+        .run(move |c| sql_interface::delete_quick_registration(c, id))
+        .await
+    {
+        Ok(true) => (),
+        Ok(false) => return verify_failure_flash(),
+        Err(err) => {
+            log::error!("Database error while deleting quick registration: {}", err);
+            return verify_failure_flash();
+        }
+    }
+
+    let person_id = quick_registration.person_id;
+    let person = match conn
+        .run(move |c| sql_interface::search_person(c, &SearchPersonBy::Id(person_id)))
+        .await
+    {
+        Ok(person) => person,
+        Err(err) => {
+            log::error!(
+                "Error while searching person of quick registration: {}",
+                err
+            );
+            return verify_failure_flash();
+        }
+    };
+    if let Err(err) = sign_in(&conn, jar, config, &person).await {
+        return server_error(
+            format!("Database error while starting session: {}", err),
+            "ein Fehler trat auf, während ich versuchte, dich anzumelden",
+        );
+    }
+
+    // from here on the person is logged in, so errors only concern the registration itself
+    let drive_id = quick_registration.drive_id;
+    let drive = match conn
+        .run(move |c| sql_interface::get_drive(c, drive_id))
+        .await
+    {
+        Ok(Some(drive)) => drive,
+        Ok(None) => {
+            return Flash::error(
+                Redirect::to(uri!(super::dashboard)),
+                "Angemeldet, aber die Busfahrt existiert nicht mehr.",
+            )
+        }
+        Err(err) => {
+            return server_error(
+                format!("Error while querying drive {}: {}", drive_id, err),
+                "ein Fehler trat während des Abfragens der Busfahrt auf",
+            )
+        }
+    };
+
+    let already_registered = match conn
+        .run(move |c| sql_interface::is_registered(c, person_id, drive_id))
+        .await
+    {
+        Ok(registered) => registered,
+        Err(err) => {
+            return server_error(
+                format!(
+                    "Error while querying registration for {} on drive {}: {}",
+                    person_id, drive_id, err
+                ),
+                "ein Fehler trat während des Abprüfens der aktuellen Registrierung auf",
+            )
+        }
+    };
+    if !already_registered {
+        if let Err(reason) = possible_to_register(&drive, true) {
+            return Flash::error(
+                Redirect::to(uri!(super::dashboard)),
+                format!("Angemeldet, aber die Registrierung ging nicht: {}", reason),
+            );
+        }
+
+        let update = RegistrationUpdate {
+            drive_id,
+            person_id,
+            registered: true,
+        };
+        match conn
+            .run(move |c| sql_interface::update_registration(c, &update, Some(person_id)))
+            .await
+        {
+            Ok(applied) if applied.changed => {
+                let change = RegistrationChange::Registered;
+                notify_registration_change(&conn, mailer, person_id, drive_id, change).await;
+            }
+            Ok(_) => (),
+            Err(ApplyRegistrationError::NotForPerson) => {
+                return Flash::error(
+                    Redirect::to(uri!(super::dashboard)),
+                    "Angemeldet, aber die Busfahrt ist nicht für deine Gruppe.",
+                );
+            }
+            Err(err) => {
+                return server_error(
+                    format!("Error while applying quick registration: {}", err),
+                    "ein Fehler trat während der Aktualisierung der Anmeldung auf",
+                );
+            }
+        }
+    }
+
+    Flash::success(
+        Redirect::to(uri!(super::dashboard)),
+        format!(
+            "Erfolgreich angemeldet und registriert für {}.",
+            format_labeled_date(drive.date, &drive.label)
+        ),
+    )
+}
+
 #[derive(Debug, Error)]
 pub enum AuthError {
     #[error("JWT cookie doesn't exist")]
     CookieNotFound,
+    #[error("Authorization header with bearer token doesn't exist")]
+    BearerNotFound,
     #[error("JWT verification error")]
     JwtVerificationError(#[from] jwt::error::Error),
     #[error("JWT cookie expired")]
     JwtExpired,
+    #[error("Session was revoked")]
+    SessionRevoked,
+    #[error("API token doesn't exist or doesn't match")]
+    InvalidApiToken,
+    #[error("API token lacks the scope for this request")]
+    MissingScope,
     #[error("No permission for the wanted role")]
     NoPermission,
     #[error("Person doesn't exist")]
@@ -399,16 +1090,28 @@ impl From<SearchPersonError> for AuthError {
 // scope.
 pub struct User {
     person_id: i64,
+    session_id: Option<i64>,
+    impersonator_id: Option<i64>,
 }
 
 impl User {
-    fn from_request_result(req: &Request<'_>) -> Result<Self, AuthError> {
-        let claims = Claims::try_from_request(req)?;
+    async fn from_request_result(req: &Request<'_>) -> Result<Self, AuthError> {
+        if let Some(token) = bearer_api_token(req) {
+            return Ok(User {
+                person_id: authenticate_api_token(req, token).await?,
+                session_id: None,
+                impersonator_id: None,
+            });
+        }
+
+        let claims = Claims::try_from_request_with_session(req).await?;
 
         // note that a check for a superuser is left out on purpose, a superuser can still do all
         // the things normal users are also able to do
         Ok(User {
             person_id: claims.sub,
+            session_id: Some(claims.sid),
+            impersonator_id: claims.imp,
         })
     }
 
@@ -416,6 +1119,19 @@ impl User {
     pub fn person_id(&self) -> i64 {
         self.person_id
     }
+
+    /// The session logged in with, [`Option::None`] if authenticated by an API token instead.
+    #[inline]
+    pub fn session_id(&self) -> Option<i64> {
+        self.session_id
+    }
+
+    /// The superuser who is only viewing the site as this person, if so. Such sessions shouldn't
+    /// change anything.
+    #[inline]
+    pub fn impersonator_id(&self) -> Option<i64> {
+        self.impersonator_id
+    }
 }
 
 #[async_trait]
@@ -423,70 +1139,217 @@ impl<'r> FromRequest<'r> for User {
     type Error = AuthError;
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        match Self::from_request_result(req) {
+        match Self::from_request_result(req).await {
             Ok(user) => Outcome::Success(user),
+            Err(AuthError::ServerDBFailure(err)) => {
+                log::error!("Database error while validating session: {}", err);
+                Outcome::Forward(())
+            }
             Err(_) => Outcome::Forward(()), // idk how I could both forward and say "you failed auth"
         }
     }
 }
 
-/// An authenticated superuser, which is defined in the database.
-///
-/// A superuser has the ability to see all persons who registered for a specific date and to
-/// register additional drive dates, but nothing more.
-pub struct Superuser {
-    _person_id: i64,
+/// A person authenticated by a JWT or personal API token in the `Authorization: Bearer` header, for
+/// the JSON API. Unlike
+/// [`User`], failing authentication isn't forwarded but answered with 401, as API clients have no
+/// login page to fall back to. Roles are looked up on every request, just like for [`Staff`].
+pub struct ApiUser {
+    person_id: i64,
+    roles: Vec<Role>,
 }
 
-impl Superuser {
+impl ApiUser {
     async fn from_request_result(req: &Request<'_>) -> Result<Self, AuthError> {
-        let claims = Claims::try_from_request(req)?;
+        let person_id = match bearer_api_token(req) {
+            Some(token) => authenticate_api_token(req, token).await?,
+            None => {
+                let claims = Claims::try_from_bearer(req)?;
+
+                // the JWT of an impersonation session could be copied out of the cookie, but
+                // viewing the site as someone else never allows changes
+                if claims.imp.is_some() {
+                    return Err(AuthError::NoPermission);
+                }
+                if !claims.session_valid(req).await? {
+                    return Err(AuthError::SessionRevoked);
+                }
+                claims.sub
+            }
+        };
 
-        // might seem unneeded, but a person could have been revoked superuser access
-        // then the JWT flag persists, but isn't valid anymore
         let conn = BususagesDBConn::get_one(req.rocket())
             .await
             .expect("Database fairing not attached!");
-        let claims_person_id = claims.sub;
-        let person = conn
-            .run(move |c| sql_interface::search_person(c, &SearchPersonBy::Id(claims_person_id)))
+        let roles = conn
+            .run(move |c| sql_interface::get_roles(c, person_id))
             .await?;
 
-        if !claims.superuser || !person.is_superuser {
-            return Err(AuthError::NoPermission);
-        }
+        Ok(ApiUser { person_id, roles })
+    }
 
-        Ok(Superuser {
-            _person_id: claims.sub,
-        })
+    #[inline]
+    pub fn person_id(&self) -> i64 {
+        self.person_id
+    }
+
+    /// Whether the person has the role or is an admin, which implies all other roles.
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role) || self.roles.contains(&Role::Admin)
+    }
+
+    pub fn roles(&self) -> &[Role] {
+        &self.roles
     }
 }
 
 #[async_trait]
-impl<'r> FromRequest<'r> for Superuser {
-    type Error = &'static str;
+impl<'r> FromRequest<'r> for ApiUser {
+    type Error = AuthError;
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
         match Self::from_request_result(req).await {
             Ok(user) => Outcome::Success(user),
             Err(AuthError::ServerDBFailure(err)) => {
-                log::error!("{}", err);
-                Outcome::Failure((
-                    Status::InternalServerError,
-                    "Server side error while validating login token, please notify the administrator of this instance!",
-                ))
+                log::error!("Database error while validating API session: {}", err);
+                Outcome::Failure((Status::InternalServerError, AuthError::ServerDBFailure(err)))
             }
-            Err(AuthError::ServerEmailFailure(err)) => {
-                log::error!("{}", err);
-                Outcome::Failure((
-                    Status::InternalServerError,
-                    "Server side error while validating login token, please notify the administrator of this instance!",
-                ))
-            }
-            Err(e) => {
-                println!("{}", e);
-                Outcome::Forward(())
+            Err(err) => Outcome::Failure((Status::Unauthorized, err)),
+        }
+    }
+}
+
+/// An authenticated person with at least one [`Role`], which are defined in the database. The roles
+/// are looked up on every request, so granting or revoking them takes effect immediately.
+///
+/// Use [`Driver`], [`Registrar`] or [`Superuser`] to require a specific role.
+pub struct Staff {
+    person_id: i64,
+    roles: Vec<Role>,
+}
+
+impl Staff {
+    async fn from_request_result(req: &Request<'_>) -> Result<Self, AuthError> {
+        let person_id = match bearer_api_token(req) {
+            Some(token) => authenticate_api_token(req, token).await?,
+            None => {
+                let claims = Claims::try_from_request_with_session(req).await?;
+
+                // viewing the site as someone else never grants their roles
+                if claims.imp.is_some() {
+                    return Err(AuthError::NoPermission);
+                }
+                claims.sub
             }
+        };
+
+        let conn = BususagesDBConn::get_one(req.rocket())
+            .await
+            .expect("Database fairing not attached!");
+        let roles = conn
+            .run(move |c| sql_interface::get_roles(c, person_id))
+            .await?;
+
+        if roles.is_empty() {
+            return Err(AuthError::NoPermission);
+        }
+
+        Ok(Staff { person_id, roles })
+    }
+
+    async fn with_role(req: &Request<'_>, role: Role) -> Result<Self, AuthError> {
+        let staff = Self::from_request_result(req).await?;
+        if !staff.has_role(role) {
+            return Err(AuthError::NoPermission);
+        }
+        Ok(staff)
+    }
+
+    #[inline]
+    pub fn person_id(&self) -> i64 {
+        self.person_id
+    }
+
+    /// Whether the person has the role or is an admin, which implies all other roles.
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role) || self.roles.contains(&Role::Admin)
+    }
+}
+
+/// Turns the result of checking roles into a request guard outcome.
+fn staff_outcome<T>(result: Result<T, AuthError>) -> Outcome<T, &'static str> {
+    match result {
+        Ok(user) => Outcome::Success(user),
+        Err(AuthError::ServerDBFailure(err)) => {
+            log::error!("{}", err);
+            Outcome::Failure((
+                Status::InternalServerError,
+                "Server side error while validating login token, please notify the administrator of this instance!",
+            ))
         }
+        Err(AuthError::ServerEmailFailure(err)) => {
+            log::error!("{}", err);
+            Outcome::Failure((
+                Status::InternalServerError,
+                "Server side error while validating login token, please notify the administrator of this instance!",
+            ))
+        }
+        Err(e) => {
+            println!("{}", e);
+            Outcome::Forward(())
+        }
+    }
+}
+
+#[async_trait]
+impl<'r> FromRequest<'r> for Staff {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        staff_outcome(Self::from_request_result(req).await)
     }
 }
+
+/// Defines a request guard requiring the given role, dereferencing to [`Staff`].
+macro_rules! role_guard {
+    ($(#[$doc:meta])* $name:ident, $role:expr) => {
+        $(#[$doc])*
+        pub struct $name(Staff);
+
+        impl std::ops::Deref for $name {
+            type Target = Staff;
+
+            fn deref(&self) -> &Staff {
+                &self.0
+            }
+        }
+
+        #[async_trait]
+        impl<'r> FromRequest<'r> for $name {
+            type Error = &'static str;
+
+            async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+                staff_outcome(Staff::with_role(req, $role).await.map($name))
+            }
+        }
+    };
+}
+
+role_guard!(
+    /// Someone who sees the lists of registered persons and marks their attendance.
+    Driver,
+    Role::Driver
+);
+
+role_guard!(
+    /// Someone who registers and unregisters other persons.
+    Registrar,
+    Role::Registrar
+);
+
+role_guard!(
+    /// An admin, who is allowed to do everything: manage persons, drives and settings, on top of
+    /// what drivers and registrars can do. Named after the single superuser flag this used to be.
+    Superuser,
+    Role::Admin
+);
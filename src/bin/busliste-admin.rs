@@ -0,0 +1,542 @@
+//! Administration from the command line, working directly on the database configured in
+//! `Rocket.toml`. Meant for setting up a new instance and for when the superuser panel isn't
+//! reachable, like after locking oneself out.
+
+use {
+    base64ct::{Base64, Encoding},
+    komplett_normale_busliste::{
+        date_helpers::format_labeled_date,
+        migrations::{self, MigrationOptions, PreparedDatabase},
+        sql_interface::{
+            self, DatabaseStatus, InsertDriveError, NewPerson, PersonCreationError, Role,
+            SearchPersonBy, SearchPersonError, SearchRegistrationsBy,
+        },
+        tokens,
+    },
+    rand::Rng,
+    rocket_sync_db_pools::rusqlite,
+    std::{env, fs, path::PathBuf, process::ExitCode},
+    thiserror::Error,
+};
+
+const USAGE: &str = "Usage: busliste-admin <command>
+
+Commands:
+    init                                        Create the database if it's empty
+    migrate [--dry-run] [--backup <directory>]  Apply pending migrations
+    person add <prename> <name> <email> [<role>...]
+                                                Create a person, optionally with roles
+    person promote <email> <role>...            Grant roles to a person
+    drive add <YYYY-MM-DD> [<label>]            Create a drive with the default deadline
+    drive list <YYYY-MM-DD> [<label>]           Print who's registered for a drive
+    login-link <email>                          Print a login link instead of mailing it
+    rotate-jwt-key                              Replace jwt_key in the config, logging out everyone
+
+Roles are driver, registrar and admin. The database and outside_address are read from Rocket.toml,
+or from the file in ROCKET_CONFIG.";
+
+#[derive(Debug, Error)]
+enum AdminError {
+    #[error("{0}")]
+    Usage(String),
+    /// Boxed, since figment errors are huge compared to the others.
+    #[error("Couldn't read the config: {0}")]
+    Config(Box<figment::Error>),
+    #[error("Database error: {0}")]
+    Rusqlite(#[from] rusqlite::Error),
+    #[error("{0}")]
+    Migration(#[from] migrations::MigrationError),
+    #[error("Invalid email address: {0}")]
+    Address(#[from] lettre::address::AddressError),
+    #[error("There's already a person with the email {0}")]
+    EmailAlreadyInUse(String),
+    #[error("There's no person with the email {0}")]
+    PersonNotFound(String),
+    #[error("There's no drive on {0}")]
+    DriveNotFound(String),
+    #[error("The drive already exists")]
+    DriveAlreadyExists,
+    #[error("Couldn't update the config at {path}: {source}")]
+    ConfigFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+impl From<figment::Error> for AdminError {
+    fn from(err: figment::Error) -> Self {
+        Self::Config(Box::new(err))
+    }
+}
+
+fn usage_error(message: impl Into<String>) -> AdminError {
+    AdminError::Usage(message.into())
+}
+
+fn config_path() -> PathBuf {
+    env::var_os("ROCKET_CONFIG").map_or_else(|| PathBuf::from("Rocket.toml"), PathBuf::from)
+}
+
+/// Opens the database the server would use, with the same profile selection as Rocket.
+fn open_database() -> Result<rusqlite::Connection, AdminError> {
+    let url: String = rocket::Config::figment().extract_inner("databases.bususages.url")?;
+    let conn = rusqlite::Connection::open(url)?;
+    // just like the connection pool does, the schema relies on cascading deletes
+    conn.execute_batch("PRAGMA foreign_keys = ON")?;
+    Ok(conn)
+}
+
+fn parse_roles(names: &[String]) -> Result<Vec<Role>, AdminError> {
+    names
+        .iter()
+        .map(|name| {
+            name.parse()
+                .map_err(|()| usage_error(format!("Unknown role {name}")))
+        })
+        .collect()
+}
+
+fn parse_date(date: &str) -> Result<chrono::NaiveDate, AdminError> {
+    chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| usage_error(format!("Invalid date {date}, expected YYYY-MM-DD")))
+}
+
+fn find_person(
+    conn: &mut rusqlite::Connection,
+    email: &str,
+) -> Result<sql_interface::Person, AdminError> {
+    match sql_interface::search_person(conn, &SearchPersonBy::Email(email.to_string())) {
+        Ok(person) => Ok(person),
+        Err(SearchPersonError::NotFound) => Err(AdminError::PersonNotFound(email.to_string())),
+        Err(SearchPersonError::RusqliteError(err)) => Err(err.into()),
+        Err(SearchPersonError::ParseAddressError(err)) => Err(err.into()),
+    }
+}
+
+fn init() -> Result<(), AdminError> {
+    let mut conn = open_database()?;
+    match sql_interface::init_db_if_necessary(&mut conn)? {
+        DatabaseStatus::Created => println!("Created new database"),
+        DatabaseStatus::AlreadyExistent => println!("Database already exists, nothing to do"),
+    }
+    Ok(())
+}
+
+fn migrate(args: &[String]) -> Result<(), AdminError> {
+    let mut options = MigrationOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dry-run" => options.dry_run = true,
+            "--backup" => {
+                let directory = args
+                    .next()
+                    .ok_or_else(|| usage_error("--backup needs a directory"))?;
+                options.backup_directory = Some(directory.into());
+            }
+            _ => return Err(usage_error(format!("Unknown option {arg}"))),
+        }
+    }
+
+    let mut conn = open_database()?;
+    match migrations::prepare_database(&mut conn, &options)? {
+        PreparedDatabase::UpToDate => println!("Database is up to date"),
+        PreparedDatabase::Migrated(applied) => {
+            for name in applied {
+                println!("Applied {name}");
+            }
+        }
+        PreparedDatabase::DryRun(pending) => {
+            for name in pending {
+                println!("Would apply {name}");
+            }
+            println!("All pending migrations apply, rolled them back");
+        }
+    }
+    Ok(())
+}
+
+fn add_person(args: &[String]) -> Result<(), AdminError> {
+    let [prename, name, email, roles @ ..] = args else {
+        return Err(usage_error("person add needs a prename, name and email"));
+    };
+    let roles = parse_roles(roles)?;
+    let new_person = NewPerson {
+        prename: prename.clone(),
+        name: name.clone(),
+        email: email.parse()?,
+    };
+
+    let mut conn = open_database()?;
+    let person_id = create_person(&mut conn, &new_person, &roles)?;
+
+    println!("Created {} {} with ID {}", prename, name, person_id);
+    Ok(())
+}
+
+/// Inserts the person together with their roles, so there's no person left without them if
+/// anything fails. Returns the ID of the new person.
+fn create_person(
+    conn: &mut rusqlite::Connection,
+    new_person: &NewPerson,
+    roles: &[Role],
+) -> Result<i64, AdminError> {
+    let tx = conn.transaction()?;
+    let person_id = match sql_interface::insert_new_person(&tx, new_person, None) {
+        Err(PersonCreationError::EmailAlreadyInUse) => {
+            return Err(AdminError::EmailAlreadyInUse(new_person.email.to_string()))
+        }
+        Err(PersonCreationError::RusqliteError(err)) => return Err(err.into()),
+        Ok(person_id) => person_id,
+    };
+    sql_interface::replace_roles(&tx, person_id, roles, None)?;
+    tx.commit()?;
+    Ok(person_id)
+}
+
+fn promote_person(args: &[String]) -> Result<(), AdminError> {
+    let [email, roles @ ..] = args else {
+        return Err(usage_error("person promote needs an email and roles"));
+    };
+    if roles.is_empty() {
+        return Err(usage_error("person promote needs at least one role"));
+    }
+    let new_roles = parse_roles(roles)?;
+
+    let mut conn = open_database()?;
+    let (person, roles) = grant_roles(&mut conn, email, new_roles)?;
+    let names: Vec<_> = roles.iter().map(|role| role.as_str()).collect();
+    println!(
+        "{} {} now has the roles {}",
+        person.prename,
+        person.name,
+        names.join(", ")
+    );
+    Ok(())
+}
+
+/// Adds the roles to those the person with the email has already. Returns the person and all of
+/// their roles afterwards.
+fn grant_roles(
+    conn: &mut rusqlite::Connection,
+    email: &str,
+    new_roles: Vec<Role>,
+) -> Result<(sql_interface::Person, Vec<Role>), AdminError> {
+    let person = find_person(conn, email)?;
+    let mut roles = sql_interface::get_roles(conn, person.id)?;
+    roles.extend(new_roles);
+    sql_interface::set_roles(conn, person.id, &roles, None)?;
+
+    let roles = sql_interface::get_roles(conn, person.id)?;
+    Ok((person, roles))
+}
+
+fn add_drive(args: &[String]) -> Result<(), AdminError> {
+    let (date, label) = match args {
+        [date] => (parse_date(date)?, ""),
+        [date, label] => (parse_date(date)?, label.as_str()),
+        _ => return Err(usage_error("drive add needs a date and optionally a label")),
+    };
+
+    let mut conn = open_database()?;
+    let tx = conn.transaction()?;
+    let deadline = sql_interface::default_deadline(&tx, date)?;
+    match sql_interface::insert_new_drive(&tx, date, label, deadline, None) {
+        Err(InsertDriveError::AlreadyExists) => return Err(AdminError::DriveAlreadyExists),
+        Err(InsertDriveError::RusqliteError(err)) => return Err(err.into()),
+        Ok(()) => (),
+    }
+    tx.commit()?;
+
+    match deadline {
+        Some(deadline) => println!(
+            "Created drive on {}, registration until {}",
+            format_labeled_date(date, label),
+            deadline.format("%d.%m.%Y %H:%M")
+        ),
+        None => println!("Created drive on {}", format_labeled_date(date, label)),
+    }
+    Ok(())
+}
+
+fn list_drive(args: &[String]) -> Result<(), AdminError> {
+    let (date, label) = match args {
+        [date] => (parse_date(date)?, ""),
+        [date, label] => (parse_date(date)?, label.as_str()),
+        _ => {
+            return Err(usage_error(
+                "drive list needs a date and optionally a label",
+            ))
+        }
+    };
+
+    let mut conn = open_database()?;
+    let drives = sql_interface::list_drives(&mut conn)?;
+    let drive = drives
+        .past
+        .into_iter()
+        .chain(drives.future)
+        .find(|drive| drive.date == date && drive.label == label)
+        .ok_or_else(|| AdminError::DriveNotFound(format_labeled_date(date, label)))?;
+    let registrations =
+        sql_interface::search_registrations(&mut conn, &SearchRegistrationsBy::DriveId(drive.id))?;
+
+    println!("{}", format_labeled_date(drive.date, &drive.label));
+    match drive.registration_cap {
+        Some(cap) => println!("{} / {} registered", drive.already_registered_count, cap),
+        None => println!("{} registered", drive.already_registered_count),
+    }
+    println!();
+    println!(
+        "{:<20} {:<20} {:<10} Waitlist",
+        "Prename", "Name", "Registered"
+    );
+    for registration in registrations {
+        println!(
+            "{:<20} {:<20} {:<10} {}",
+            registration.person.prename,
+            registration.person.name,
+            if registration.registered { "yes" } else { "no" },
+            registration
+                .waitlist_position
+                .map(|position| position.to_string())
+                .unwrap_or_default(),
+        );
+    }
+    Ok(())
+}
+
+fn login_link(args: &[String]) -> Result<(), AdminError> {
+    let [email] = args else {
+        return Err(usage_error("login-link needs an email"));
+    };
+    let outside_address: String = rocket::Config::figment().extract_inner("outside_address")?;
+
+    let mut conn = open_database()?;
+    let person = find_person(&mut conn, email)?;
+    let (raw_token, encoded_token) = tokens::generate_token();
+    sql_interface::update_token(&mut conn, person.id, Some(tokens::hash_token(&raw_token)))?;
+
+    // same link as in the login mail, valid for an hour as well
+    println!(
+        "{}/login/{}?person_id={}",
+        outside_address.trim_end_matches('/'),
+        encoded_token,
+        person.id
+    );
+    Ok(())
+}
+
+fn rotate_jwt_key() -> Result<(), AdminError> {
+    let mut bytes = [0_u8; 64];
+    rand::thread_rng().fill(&mut bytes);
+    let key = Base64::encode_string(&bytes);
+
+    let path = config_path();
+    let config_error = |source| AdminError::ConfigFile {
+        path: path.clone(),
+        source,
+    };
+    let config = fs::read_to_string(&path).map_err(config_error)?;
+    let mut replaced = 0;
+    let mut rotated: String = config
+        .lines()
+        .map(|line| {
+            let indentation = &line[..line.len() - line.trim_start().len()];
+            let is_key = line
+                .trim_start()
+                .strip_prefix("jwt_key")
+                .is_some_and(|rest| rest.trim_start().starts_with('='));
+            if is_key {
+                replaced += 1;
+                format!("{indentation}jwt_key = \"{key}\"\n")
+            } else {
+                format!("{line}\n")
+            }
+        })
+        .collect();
+    if !config.ends_with('\n') {
+        rotated.pop();
+    }
+
+    if replaced == 0 {
+        println!("No jwt_key in {}, add this one:", path.display());
+        println!("jwt_key = \"{key}\"");
+        return Ok(());
+    }
+    fs::write(&path, rotated).map_err(config_error)?;
+
+    println!("Replaced jwt_key in {}", path.display());
+    if env::var_os("ROCKET_JWT_KEY").is_some() {
+        println!("Note that ROCKET_JWT_KEY is set and takes precedence over the file");
+    }
+    println!("Restart the server to apply it. Everyone will have to log in again.");
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<(), AdminError> {
+    match args {
+        [command] if command == "init" => init(),
+        [command, rest @ ..] if command == "migrate" => migrate(rest),
+        [command, sub, rest @ ..] if command == "person" && sub == "add" => add_person(rest),
+        [command, sub, rest @ ..] if command == "person" && sub == "promote" => {
+            promote_person(rest)
+        }
+        [command, sub, rest @ ..] if command == "drive" && sub == "add" => add_drive(rest),
+        [command, sub, rest @ ..] if command == "drive" && sub == "list" => list_drive(rest),
+        [command, rest @ ..] if command == "login-link" => login_link(rest),
+        [command] if command == "rotate-jwt-key" => rotate_jwt_key(),
+        [] => Err(usage_error("No command given")),
+        [command, ..] => Err(usage_error(format!("Unknown command {command}"))),
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if matches!(
+        args.first().map(String::as_str),
+        Some("help" | "-h" | "--help")
+    ) {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(AdminError::Usage(message)) => {
+            eprintln!("{message}\n\n{USAGE}");
+            ExitCode::from(2)
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates a fresh empty database with tables defined.
+    fn init_db() -> rusqlite::Connection {
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch("PRAGMA foreign_keys = ON").unwrap();
+        sql_interface::init_db_if_necessary(&mut conn).unwrap();
+        conn
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn alice() -> NewPerson {
+        NewPerson {
+            prename: "Alice".to_string(),
+            name: "Beta".to_string(),
+            email: "alice_beta@non-existent-domain".parse().unwrap(),
+        }
+    }
+
+    fn count_persons(conn: &rusqlite::Connection) -> i64 {
+        conn.query_row("SELECT count(*) FROM person", [], |row| row.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn argument_parsing() {
+        assert_eq!(
+            parse_roles(&args(&["driver", "admin"])).unwrap(),
+            [Role::Driver, Role::Admin]
+        );
+        assert!(matches!(
+            parse_roles(&args(&["superuser"])),
+            Err(AdminError::Usage(_))
+        ));
+        assert_eq!(
+            parse_date("2009-01-16").unwrap(),
+            chrono::NaiveDate::from_ymd_opt(2009, 1, 16).unwrap()
+        );
+        assert!(matches!(
+            parse_date("16.01.2009"),
+            Err(AdminError::Usage(_))
+        ));
+
+        // all of them are refused before even looking for the config or the database
+        let invalid: &[&[&str]] = &[
+            &[],
+            &["frobnicate"],
+            &["init", "now"],
+            &["migrate", "--backup"],
+            &["person", "add", "Alice", "Beta"],
+            &[
+                "person",
+                "add",
+                "Alice",
+                "Beta",
+                "alice@non-existent-domain",
+                "superuser",
+            ],
+            &["person", "promote", "alice@non-existent-domain"],
+            &["person", "remove", "alice@non-existent-domain"],
+            &["drive", "add"],
+            &["drive", "add", "16.01.2009"],
+            &["drive", "list", "2009-01-16", "Hinfahrt", "Rückfahrt"],
+            &["login-link"],
+            &["rotate-jwt-key", "now"],
+        ];
+        for arguments in invalid {
+            assert!(
+                matches!(run(&args(arguments)), Err(AdminError::Usage(_))),
+                "{:?}",
+                arguments
+            );
+        }
+        assert!(matches!(
+            run(&args(&["person", "add", "Alice", "Beta", "alice"])),
+            Err(AdminError::Address(_))
+        ));
+    }
+
+    #[test]
+    fn persons() {
+        let mut conn = init_db();
+
+        let alice_id = create_person(&mut conn, &alice(), &[Role::Driver]).unwrap();
+        assert_eq!(
+            sql_interface::get_roles(&mut conn, alice_id).unwrap(),
+            [Role::Driver]
+        );
+        assert!(matches!(
+            create_person(&mut conn, &alice(), &[]),
+            Err(AdminError::EmailAlreadyInUse(_))
+        ));
+        assert_eq!(count_persons(&conn), 1);
+
+        let (person, roles) = grant_roles(
+            &mut conn,
+            "alice_beta@non-existent-domain",
+            vec![Role::Admin, Role::Driver],
+        )
+        .unwrap();
+        assert_eq!(person.id, alice_id);
+        assert_eq!(roles, [Role::Driver, Role::Admin]);
+        assert!(matches!(
+            grant_roles(&mut conn, "bob_echo@non-existent-domain", vec![Role::Admin]),
+            Err(AdminError::PersonNotFound(_))
+        ));
+
+        // if the roles can't be set, the person isn't created either
+        conn.execute_batch("DROP TABLE role").unwrap();
+        let bob = NewPerson {
+            prename: "Bob".to_string(),
+            name: "Echo".to_string(),
+            email: "bob_echo@non-existent-domain".parse().unwrap(),
+        };
+        assert!(matches!(
+            create_person(&mut conn, &bob, &[Role::Registrar]),
+            Err(AdminError::Rusqlite(_))
+        ));
+        assert_eq!(count_persons(&conn), 1);
+    }
+}
@@ -0,0 +1,116 @@
+use {
+    super::{format_labeled_date, sql_interface::Registration},
+    chrono::{Days, NaiveDateTime},
+};
+
+const DATE_FORMAT: &str = "%Y%m%d";
+const DATETIME_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+/// Renders the iCalendar feed of one person. Every drive they're registered for is an all-day
+/// event. Upcoming drives they aren't registered for yet get an event at the deadline instead,
+/// which alarms `alarm_hours` before, so the deadline can't slip by unnoticed.
+///
+/// `registrations` are the ones of a single person, as found by
+/// [`super::sql_interface::SearchRegistrationsBy::PersonId`].
+pub fn feed(registrations: &[Registration], now: NaiveDateTime, alarm_hours: i64) -> String {
+    let stamp = now.format(DATETIME_FORMAT).to_string();
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//Komplett normale Busliste//DE".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+        "X-WR-CALNAME:Busfahrten".to_string(),
+    ];
+
+    for registration in registrations {
+        let drive = &registration.drive;
+        let pretty_date = format_labeled_date(drive.date, &drive.label);
+
+        if registration.registered {
+            let summary = match drive.label.as_str() {
+                "" => "Busfahrt".to_string(),
+                label => format!("Busfahrt ({})", label),
+            };
+            lines.extend([
+                "BEGIN:VEVENT".to_string(),
+                format!("UID:drive-{}@komplett-normale-busliste", drive.id),
+                format!("DTSTAMP:{}", stamp),
+                format!("DTSTART;VALUE=DATE:{}", drive.date.format(DATE_FORMAT)),
+                format!(
+                    "DTEND;VALUE=DATE:{}",
+                    (drive.date + Days::new(1)).format(DATE_FORMAT)
+                ),
+                format!("SUMMARY:{}", escape(&summary)),
+                format!(
+                    "DESCRIPTION:{}",
+                    escape(&format!("Du bist für den {} angemeldet.", pretty_date))
+                ),
+                "TRANSP:TRANSPARENT".to_string(),
+                "END:VEVENT".to_string(),
+            ]);
+            continue;
+        }
+
+        let Some(deadline) = drive.deadline.filter(|deadline| now < *deadline) else {
+            continue;
+        };
+        let deadline = deadline.format(DATETIME_FORMAT);
+        let summary = format!("Anmeldeschluss: Busfahrt am {}", pretty_date);
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            format!("UID:deadline-{}@komplett-normale-busliste", drive.id),
+            format!("DTSTAMP:{}", stamp),
+            format!("DTSTART:{}", deadline),
+            format!("DTEND:{}", deadline),
+            format!("SUMMARY:{}", escape(&summary)),
+            "TRANSP:TRANSPARENT".to_string(),
+            "BEGIN:VALARM".to_string(),
+            "ACTION:DISPLAY".to_string(),
+            format!("TRIGGER:-PT{}H", alarm_hours.max(0)),
+            format!("DESCRIPTION:{}", escape(&summary)),
+            "END:VALARM".to_string(),
+            "END:VEVENT".to_string(),
+        ]);
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    let mut output = String::new();
+    for line in lines {
+        fold(&mut output, &line);
+    }
+    output
+}
+
+/// Escapes text values as required by RFC 5545.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | ';' | ',' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' => escaped.push_str("\\n"),
+            '\r' => (),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Appends the line to `output`, folding it so no physical line exceeds 75 bytes, and terminates
+/// it with CRLF.
+fn fold(output: &mut String, line: &str) {
+    let mut width = 0;
+    for c in line.chars() {
+        if width + c.len_utf8() > 75 {
+            output.push_str("\r\n ");
+            // the leading space counts towards the line length
+            width = 1;
+        }
+        output.push(c);
+        width += c.len_utf8();
+    }
+    output.push_str("\r\n");
+}
@@ -1,4 +1,5 @@
 use {
+    super::migrations::MigrationOptions,
     base64ct::{Base64, Encoding, Error},
     hmac::{Hmac, NewMac},
     rocket::{
@@ -6,7 +7,7 @@ use {
         serde::{de, Deserialize},
     },
     sha2::Sha256,
-    std::fmt,
+    std::{fmt, path::PathBuf},
 };
 
 #[derive(Debug, Deserialize)]
@@ -17,15 +18,79 @@ pub struct Config {
     /// The email from which mails with login links will be sent.
     pub email: lettre::Address,
 
-    /// Password for the email.
-    pub email_creds: String,
+    /// How mails are delivered. Defaults to `stdout` in the debug profile and `smtp` otherwise.
+    pub mail_transport: Option<MailTransportKind>,
+
+    /// Password for the email. Only needed for the `smtp` transport.
+    pub email_creds: Option<String>,
 
     /// The key used for JWT encryption. Should be base64 decodable.
     #[serde(deserialize_with = "deserialize_base64_to_hmac")]
     pub jwt_key: Hmac<Sha256>,
 
-    /// The SMTP server which the email belongs to.
-    pub smtp_server: String,
+    /// The SMTP server which the email belongs to. Only needed for the `smtp` transport.
+    pub smtp_server: Option<String>,
+
+    /// The sendmail binary used by the `sendmail` transport, by default `/usr/sbin/sendmail`.
+    pub sendmail_command: Option<String>,
+
+    /// The directory the `file` transport writes one `.eml` file per mail into.
+    pub mail_directory: Option<PathBuf>,
+
+    /// How many login attempts are allowed before further ones are refused for a while.
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+
+    /// The secret the kiosk screen under `/mensa` has to be opened with. The kiosk is disabled if
+    /// this isn't set.
+    pub kiosk_token: Option<String>,
+
+    /// How pending database migrations are applied at startup.
+    #[serde(default)]
+    pub migrations: MigrationOptions,
+}
+
+/// Limits against flooding inboxes with login mails and guessing login tokens. Each limit is the
+/// number of attempts allowed within `window_minutes`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimits {
+    pub window_minutes: u64,
+    /// Login mails sent to the same address.
+    pub login_mails_per_email: u32,
+    /// Login mails requested from the same IP address.
+    pub login_mails_per_ip: u32,
+    /// Login links for the same person which turned out to be invalid.
+    pub failed_logins_per_person: u32,
+    /// Login links opened from the same IP address which turned out to be invalid.
+    pub failed_logins_per_ip: u32,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            window_minutes: 15,
+            login_mails_per_email: 3,
+            login_mails_per_ip: 200,
+            failed_logins_per_person: 5,
+            failed_logins_per_ip: 20,
+        }
+    }
+}
+
+/// The ways mails can leave this instance, see [`crate::mail`].
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransportKind {
+    /// Sends mails over the SMTP server in `smtp_server`, logging in with `email` and
+    /// `email_creds`.
+    Smtp,
+    /// Hands mails to a local sendmail binary.
+    Sendmail,
+    /// Writes mails into `mail_directory`.
+    File,
+    /// Prints mails to stdout.
+    Stdout,
 }
 
 /// Deserializes standard base64 in constant time into a [`std::vec::Vec`] of [`u8`]s.
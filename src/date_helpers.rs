@@ -46,6 +46,29 @@ pub fn format_date(date: chrono::NaiveDate) -> String {
     date.format("%A, %d.%m.%Y").to_string()
 }
 
+/// The German name of the weekday, for text which is German throughout, unlike [`format_date`].
+pub fn german_weekday(weekday: chrono::Weekday) -> &'static str {
+    match weekday {
+        chrono::Weekday::Mon => "Montag",
+        chrono::Weekday::Tue => "Dienstag",
+        chrono::Weekday::Wed => "Mittwoch",
+        chrono::Weekday::Thu => "Donnerstag",
+        chrono::Weekday::Fri => "Freitag",
+        chrono::Weekday::Sat => "Samstag",
+        chrono::Weekday::Sun => "Sonntag",
+    }
+}
+
+/// Like [`format_date`], but appends the label of a drive if there is one, so multiple drives on
+/// the same date can be told apart.
+pub fn format_labeled_date(date: chrono::NaiveDate, label: &str) -> String {
+    if label.is_empty() {
+        format_date(date)
+    } else {
+        format!("{} ({})", format_date(date), label)
+    }
+}
+
 pub fn figure_out_exact_deadline(
     deadline_weekday: u32,
     drive_date: chrono::NaiveDate,
@@ -0,0 +1,312 @@
+use {
+    super::{
+        audit::AuditEntry,
+        sql_interface::{CountedRegistrations, Registration},
+    },
+    rust_xlsxwriter::{Format, Workbook, XlsxError},
+    std::io::{Cursor, Write},
+    thiserror::Error,
+    zip::{result::ZipError, write::SimpleFileOptions, CompressionMethod, ZipWriter},
+};
+
+/// The file formats lists can be downloaded in.
+#[derive(FromFormField, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Xlsx,
+    Ods,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Xlsx => "xlsx",
+            Self::Ods => "ods",
+        }
+    }
+}
+
+/// One cell of an exported table. Numbers are kept as such, so spreadsheets can calculate with them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    Text(String),
+    Number(i64),
+    Empty,
+}
+
+impl From<&str> for Cell {
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_string())
+    }
+}
+
+impl From<i64> for Cell {
+    fn from(number: i64) -> Self {
+        Self::Number(number)
+    }
+}
+
+impl<T: Into<Cell>> From<Option<T>> for Cell {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Empty, Into::into)
+    }
+}
+
+/// A table ready to be written into any [`ExportFormat`].
+#[derive(Debug)]
+pub struct Table {
+    pub headers: Vec<&'static str>,
+    pub rows: Vec<Vec<Cell>>,
+}
+
+#[derive(Debug, Error)]
+pub enum ExportError {
+    #[error("Error while writing CSV: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("Error while writing XLSX: {0}")]
+    Xlsx(#[from] XlsxError),
+    #[error("Error while writing ODS: {0}")]
+    Ods(#[from] ZipError),
+}
+
+fn yes_no(value: bool) -> Cell {
+    Cell::from(if value { "yes" } else { "no" })
+}
+
+/// The list of a drive, one row per [`Registration`], in the order they're given.
+pub fn drive_table(registrations: &[Registration]) -> Table {
+    Table {
+        headers: vec![
+            "First name",
+            "Last name",
+            "Email",
+            "Group",
+            "Registered",
+            "Waitlist position",
+            "Attended",
+        ],
+        rows: registrations
+            .iter()
+            .map(|registration| {
+                vec![
+                    registration.person.prename.as_str().into(),
+                    registration.person.name.as_str().into(),
+                    registration.person.email.to_string().as_str().into(),
+                    registration.group.as_deref().into(),
+                    yes_no(registration.registered),
+                    registration.waitlist_position.map(i64::from).into(),
+                    registration.attended.map(yes_no).unwrap_or(Cell::Empty),
+                ]
+            })
+            .collect(),
+    }
+}
+
+/// The counted registrations of every person in a date range, one row per person.
+pub fn registrations_table(counted: &CountedRegistrations) -> Table {
+    Table {
+        headers: vec![
+            "First name",
+            "Last name",
+            "Email",
+            "Registrations",
+            "No-shows",
+            "Unregistered shows",
+            "Checked registrations",
+        ],
+        rows: counted
+            .persons
+            .iter()
+            .map(|entry| {
+                vec![
+                    entry.person.prename.as_str().into(),
+                    entry.person.name.as_str().into(),
+                    entry.person.email.to_string().as_str().into(),
+                    entry.count.into(),
+                    entry.no_shows.into(),
+                    entry.unregistered_shows.into(),
+                    entry.checked.into(),
+                ]
+            })
+            .collect(),
+    }
+}
+
+/// Audit log entries, one row per entry. Persons and drives which don't exist anymore only have
+/// their ID.
+pub fn audit_table(entries: &[AuditEntry]) -> Table {
+    let name_or_id = |name: &Option<String>, id: Option<i64>| match (name, id) {
+        (Some(name), _) => Cell::from(name.as_str()),
+        (None, id) => id.map(|id| format!("#{}", id)).as_deref().into(),
+    };
+    Table {
+        headers: vec![
+            "Time (UTC)",
+            "Actor",
+            "Action",
+            "Person",
+            "Drive",
+            "Group",
+            "Setting",
+            "Before",
+            "After",
+        ],
+        rows: entries
+            .iter()
+            .map(|entry| {
+                vec![
+                    chrono::NaiveDateTime::from_timestamp_opt(entry.happened, 0)
+                        .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+                        .as_deref()
+                        .into(),
+                    name_or_id(&entry.actor_name, entry.actor_id),
+                    entry.action.as_str().into(),
+                    name_or_id(&entry.target_person_name, entry.target_person_id),
+                    name_or_id(
+                        &entry.target_drive_date.map(|date| date.to_string()),
+                        entry.target_drive_id,
+                    ),
+                    name_or_id(&entry.target_group_name, entry.target_group_id),
+                    entry.target_setting.as_deref().into(),
+                    entry.before.as_deref().into(),
+                    entry.after.as_deref().into(),
+                ]
+            })
+            .collect(),
+    }
+}
+
+impl Table {
+    /// Writes the table in the given format. `title` names the sheet, if the format has sheets.
+    pub fn export(&self, format: ExportFormat, title: &str) -> Result<Vec<u8>, ExportError> {
+        match format {
+            ExportFormat::Csv => self.to_csv(),
+            ExportFormat::Xlsx => self.to_xlsx(title),
+            ExportFormat::Ods => self.to_ods(title),
+        }
+    }
+
+    fn to_csv(&self) -> Result<Vec<u8>, ExportError> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(&self.headers)?;
+        for row in &self.rows {
+            writer.write_record(row.iter().map(|cell| match cell {
+                Cell::Text(text) => defuse_formula(text),
+                Cell::Number(number) => number.to_string(),
+                Cell::Empty => String::new(),
+            }))?;
+        }
+        writer
+            .into_inner()
+            .map_err(|err| ExportError::Csv(err.into_error().into()))
+    }
+
+    fn to_xlsx(&self, title: &str) -> Result<Vec<u8>, ExportError> {
+        let mut workbook = Workbook::new();
+        let sheet = workbook.add_worksheet();
+        // sheet names are limited to 31 characters and some can't be used at all
+        let title: String = title
+            .chars()
+            .filter(|c| !"[]:*?/\\".contains(*c))
+            .take(31)
+            .collect();
+        if !title.is_empty() {
+            sheet.set_name(title)?;
+        }
+
+        let bold = Format::new().set_bold();
+        for (col, header) in (0..).zip(&self.headers) {
+            sheet.write_string_with_format(0, col, *header, &bold)?;
+        }
+        for (row, cells) in (1..).zip(&self.rows) {
+            for (col, cell) in (0..).zip(cells) {
+                match cell {
+                    Cell::Text(text) => sheet.write_string(row, col, text)?,
+                    Cell::Number(number) => sheet.write_number(row, col, *number as f64)?,
+                    Cell::Empty => continue,
+                };
+            }
+        }
+        sheet.autofit();
+
+        Ok(workbook.save_to_buffer()?)
+    }
+
+    fn to_ods(&self, title: &str) -> Result<Vec<u8>, ExportError> {
+        let mut content = String::from(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" xmlns:style="urn:oasis:names:tc:opendocument:xmlns:style:1.0" xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0" xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0" xmlns:fo="urn:oasis:names:tc:opendocument:xmlns:xsl-fo-compatible:1.0" office:version="1.2">
+<office:automatic-styles><style:style style:name="header" style:family="table-cell"><style:text-properties fo:font-weight="bold"/></style:style></office:automatic-styles>
+<office:body><office:spreadsheet>"#,
+        );
+        content += &format!(r#"<table:table table:name="{}">"#, escape_xml(title));
+        content += "<table:table-row>";
+        for header in &self.headers {
+            content += &format!(
+                r#"<table:table-cell table:style-name="header" office:value-type="string"><text:p>{}</text:p></table:table-cell>"#,
+                escape_xml(header)
+            );
+        }
+        content += "</table:table-row>";
+        for row in &self.rows {
+            content += "<table:table-row>";
+            for cell in row {
+                content += &match cell {
+                    Cell::Text(text) => format!(
+                        r#"<table:table-cell office:value-type="string"><text:p>{}</text:p></table:table-cell>"#,
+                        escape_xml(text)
+                    ),
+                    Cell::Number(number) => format!(
+                        r#"<table:table-cell office:value-type="float" office:value="{0}"><text:p>{0}</text:p></table:table-cell>"#,
+                        number
+                    ),
+                    Cell::Empty => "<table:table-cell/>".to_string(),
+                };
+            }
+            content += "</table:table-row>";
+        }
+        content += "</table:table></office:spreadsheet></office:body></office:document-content>";
+
+        // the mimetype has to come first and uncompressed, so it can be recognized without unpacking
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file(
+            "mimetype",
+            SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+        )?;
+        zip.write_all(ODS_MIMETYPE.as_bytes())
+            .map_err(ZipError::from)?;
+        zip.start_file("META-INF/manifest.xml", SimpleFileOptions::default())?;
+        zip.write_all(ODS_MANIFEST.as_bytes())
+            .map_err(ZipError::from)?;
+        zip.start_file("content.xml", SimpleFileOptions::default())?;
+        zip.write_all(content.as_bytes()).map_err(ZipError::from)?;
+        Ok(zip.finish()?.into_inner())
+    }
+}
+
+const ODS_MIMETYPE: &str = "application/vnd.oasis.opendocument.spreadsheet";
+
+const ODS_MANIFEST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<manifest:manifest xmlns:manifest="urn:oasis:names:tc:opendocument:xmlns:manifest:1.0" manifest:version="1.2">
+<manifest:file-entry manifest:full-path="/" manifest:version="1.2" manifest:media-type="application/vnd.oasis.opendocument.spreadsheet"/>
+<manifest:file-entry manifest:full-path="content.xml" manifest:media-type="text/xml"/>
+</manifest:manifest>
+"#;
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Spreadsheet programs run CSV cells starting like a formula as one, so names like
+/// `=HYPERLINK(...)` are prefixed with `'` to stay plain text.
+fn defuse_formula(text: &str) -> String {
+    if text.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("'{}", text)
+    } else {
+        text.to_string()
+    }
+}
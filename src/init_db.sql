@@ -5,24 +5,62 @@ CREATE TABLE IF NOT EXISTS person(
     email TEXT NOT NULL,
     token TEXT,
     token_expiration INTEGER,
-    is_superuser BOOLEAN NOT NULL,
     is_visible BOOLEAN NOT NULL,
+    reminders TEXT NOT NULL DEFAULT 'unregistered'
+        CHECK (reminders IN ('never', 'unregistered', 'always')),
+    registration_mails BOOLEAN NOT NULL DEFAULT true,
+    login_code TEXT,
+    login_code_expiration INTEGER,
+    login_code_attempts INTEGER NOT NULL DEFAULT 0,
+    group_id INTEGER,
+    calendar_token TEXT,
     UNIQUE(email),
+    FOREIGN KEY (group_id) REFERENCES person_group(group_id)
+            ON DELETE SET NULL
+            ON UPDATE CASCADE,
     PRIMARY KEY (person_id AUTOINCREMENT)
 );
+CREATE TABLE IF NOT EXISTS person_group(
+    group_id INTEGER,
+    name TEXT NOT NULL,
+    UNIQUE(name),
+    PRIMARY KEY (group_id AUTOINCREMENT)
+);
+CREATE TABLE IF NOT EXISTS role(
+    person_id INTEGER NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('driver', 'registrar', 'admin')),
+    FOREIGN KEY (person_id) REFERENCES person(person_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    PRIMARY KEY (person_id, role)
+);
 CREATE TABLE IF NOT EXISTS drive(
     drive_id INTEGER,
     drivedate DATE NOT NULL,
+    label TEXT NOT NULL DEFAULT '',
     deadline DATETIME,
     registration_cap INTEGER,
-    UNIQUE(drivedate),
+    reminder_sent BOOLEAN NOT NULL DEFAULT false,
+    UNIQUE(drivedate, label),
     PRIMARY KEY (drive_id AUTOINCREMENT)
 );
+CREATE TABLE IF NOT EXISTS drive_group(
+    drive_id INTEGER NOT NULL,
+    group_id INTEGER NOT NULL,
+    FOREIGN KEY (drive_id) REFERENCES drive(drive_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    FOREIGN KEY (group_id) REFERENCES person_group(group_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    PRIMARY KEY (drive_id, group_id)
+);
 CREATE TABLE IF NOT EXISTS registration(
     id INTEGER,
     person_id INTEGER NOT NULL,
     drive_id INTEGER NOT NULL,
     registered BOOLEAN NOT NULL,
+    attended BOOLEAN DEFAULT null,
     UNIQUE(person_id, drive_id),
     FOREIGN KEY (person_id) REFERENCES person(person_id)
             ON DELETE CASCADE
@@ -32,19 +70,136 @@ CREATE TABLE IF NOT EXISTS registration(
             ON UPDATE CASCADE,
     PRIMARY KEY (id AUTOINCREMENT) 
 );
+CREATE TABLE IF NOT EXISTS waitlist(
+    id INTEGER,
+    person_id INTEGER NOT NULL,
+    drive_id INTEGER NOT NULL,
+    UNIQUE(person_id, drive_id),
+    FOREIGN KEY (person_id) REFERENCES person(person_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    FOREIGN KEY (drive_id) REFERENCES drive(drive_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    PRIMARY KEY (id AUTOINCREMENT)
+);
+CREATE TABLE IF NOT EXISTS drive_schedule(
+    schedule_id INTEGER,
+    weekday INTEGER NOT NULL,
+    label TEXT NOT NULL DEFAULT '',
+    generated_until DATE,
+    PRIMARY KEY (schedule_id AUTOINCREMENT)
+);
+CREATE TABLE IF NOT EXISTS schedule_exception(
+    exception_id INTEGER,
+    first_date DATE NOT NULL,
+    last_date DATE NOT NULL,
+    note TEXT NOT NULL DEFAULT '',
+    PRIMARY KEY (exception_id AUTOINCREMENT)
+);
+CREATE TABLE IF NOT EXISTS quick_registration(
+    quick_registration_id INTEGER,
+    person_id INTEGER NOT NULL,
+    drive_id INTEGER NOT NULL,
+    token TEXT NOT NULL,
+    token_expiration INTEGER NOT NULL,
+    FOREIGN KEY (person_id) REFERENCES person(person_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    FOREIGN KEY (drive_id) REFERENCES drive(drive_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    PRIMARY KEY (quick_registration_id AUTOINCREMENT)
+);
+CREATE TABLE IF NOT EXISTS session(
+    session_id INTEGER,
+    person_id INTEGER NOT NULL,
+    created INTEGER NOT NULL,
+    expiration INTEGER NOT NULL,
+    impersonator_id INTEGER,
+    FOREIGN KEY (person_id) REFERENCES person(person_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    FOREIGN KEY (impersonator_id) REFERENCES person(person_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    PRIMARY KEY (session_id AUTOINCREMENT)
+);
+CREATE TABLE IF NOT EXISTS api_token(
+    api_token_id INTEGER,
+    person_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    token TEXT NOT NULL,
+    scopes TEXT NOT NULL,
+    created INTEGER NOT NULL,
+    last_used INTEGER,
+    FOREIGN KEY (person_id) REFERENCES person(person_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    PRIMARY KEY (api_token_id AUTOINCREMENT)
+);
 CREATE TABLE settings(
     name TEXT NOT NULL,
     value,
     PRIMARY KEY (name)
 );
-INSERT INTO settings(name, value)
-VALUES (
-    "login-message",
-    "Falls du nicht reinkommen solltest, du hast dich vermutlich vertippt oder die falsche Email angegeben."
-), (
-    "default-deadline",
-    2
-), (
-    "default-registration-cap",
-    50
+-- the values are filled in from src/settings.rs
+-- no foreign keys on purpose, entries stay as they are when persons or drives are deleted
+CREATE TABLE audit_log(
+    audit_id INTEGER,
+    happened INTEGER NOT NULL,
+    actor_id INTEGER,
+    action TEXT NOT NULL CHECK (action IN (
+        'registration', 'waitlist-promotion', 'attendance',
+        'drive-creation', 'drive-update', 'drive-group-change', 'drive-deletion',
+        'person-creation', 'person-update', 'person-import', 'person-deletion', 'role-change',
+        'group-creation', 'group-deletion', 'group-membership',
+        'session-revocation', 'impersonation-start', 'impersonation-end',
+        'setting-change'
+    )),
+    target_person_id INTEGER,
+    target_drive_id INTEGER,
+    target_group_id INTEGER,
+    target_setting TEXT,
+    before TEXT,
+    after TEXT,
+    PRIMARY KEY (audit_id AUTOINCREMENT)
+);
+CREATE TRIGGER audit_log_no_update
+BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+CREATE TRIGGER audit_log_no_delete
+BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+-- a fresh database already contains every migration, see src/migrations.rs
+CREATE TABLE schema_version(
+    name TEXT NOT NULL,
+    applied INTEGER,
+    PRIMARY KEY (name)
 );
+INSERT INTO schema_version(name)
+VALUES
+    ('0.1.0-1-add-is-visible'),
+    ('0.1.0-2-settings-table'),
+    ('0.1.0-3-drive-deadline'),
+    ('0.2.0-1-drive-registration-cap'),
+    ('0.3.0-1-waitlist'),
+    ('0.3.0-2-drive-labels'),
+    ('0.3.0-3-drive-schedules'),
+    ('0.3.0-4-attendance'),
+    ('0.3.0-5-deadline-reminders'),
+    ('0.3.0-6-registration-mails'),
+    ('0.3.0-7-sessions'),
+    ('0.3.0-8-login-codes'),
+    ('0.3.0-9-impersonation'),
+    ('0.3.0-10-roles'),
+    ('0.3.0-11-groups'),
+    ('0.3.0-12-calendar-token'),
+    ('0.3.0-13-api-tokens'),
+    ('0.3.0-14-audit-log'),
+    ('0.3.0-15-calendar-token-sha256'),
+    ('0.3.0-16-api-token-sha256');
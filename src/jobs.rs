@@ -0,0 +1,223 @@
+use {
+    super::{
+        authflow,
+        config::Config,
+        format_labeled_date,
+        mail::Mailer,
+        sql_interface::{self, Drive, Person},
+        tokens, BususagesDBConn,
+    },
+    chrono::Utc,
+    rocket::{fairing::AdHoc, http::uri, tokio},
+    std::time::Duration,
+};
+
+/// How often the background jobs are run.
+const JOB_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A fairing spawning everything which needs to be done regularly inside of the Rocket process,
+/// like generating drives from schedules, cleaning up expired sessions or sending deadline
+/// reminders. All jobs also run once right after launch. Needs to be attached after the mail
+/// fairing.
+pub fn fairing() -> AdHoc {
+    AdHoc::on_liftoff("Background jobs", |rocket| {
+        Box::pin(async move {
+            let pool = BususagesDBConn::pool(rocket)
+                .expect("Database fairing not attached!")
+                .clone();
+            let mailer = rocket
+                .state::<Mailer>()
+                .expect("Mail fairing not attached!")
+                .clone();
+            let outside_address = rocket
+                .state::<Config>()
+                .expect("Config is not set in main!")
+                .outside_address
+                .clone();
+
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(JOB_INTERVAL);
+                loop {
+                    interval.tick().await;
+
+                    // only borrow a connection while the jobs run, it'd be missing for requests
+                    // otherwise
+                    let Some(conn) = pool.get().await.map(BususagesDBConn) else {
+                        log::error!("No database connection for the background jobs");
+                        continue;
+                    };
+                    generate_scheduled_drives(&conn).await;
+                    delete_expired_sessions(&conn).await;
+                    send_deadline_reminders(&conn, &mailer, &outside_address).await;
+                }
+            });
+        })
+    })
+}
+
+async fn generate_scheduled_drives(conn: &BususagesDBConn) {
+    let today = Utc::now().naive_utc().date();
+    match conn
+        .run(move |c| sql_interface::generate_scheduled_drives(c, today))
+        .await
+    {
+        Ok(0) => (),
+        Ok(created) => log::info!("Generated {} drive(s) from schedules", created),
+        Err(err) => log::error!("Error while generating scheduled drives: {}", err),
+    }
+}
+
+async fn delete_expired_sessions(conn: &BususagesDBConn) {
+    let now = Utc::now().timestamp();
+    match conn
+        .run(move |c| sql_interface::delete_expired_sessions(c, now))
+        .await
+    {
+        Ok(0) => (),
+        Ok(deleted) => log::info!("Deleted {} expired session(s)", deleted),
+        Err(err) => log::error!("Error while deleting expired sessions: {}", err),
+    }
+}
+
+/// Mails everyone who wants to be reminded of a deadline coming up within `reminder-hours`. Persons
+/// who aren't registered yet get a link registering them with one click.
+async fn send_deadline_reminders(
+    conn: &BususagesDBConn,
+    mailer: &Mailer,
+    outside_address: &uri::Absolute<'static>,
+) {
+    let now = Utc::now();
+    let timestamp = now.timestamp();
+    if let Err(err) = conn
+        .run(move |c| sql_interface::delete_expired_quick_registrations(c, timestamp))
+        .await
+    {
+        log::error!("Error while deleting expired quick registrations: {}", err);
+    }
+
+    let now = now.naive_utc();
+    let drives = match conn
+        .run(move |c| sql_interface::drives_due_for_reminder(c, now))
+        .await
+    {
+        Ok(drives) => drives,
+        Err(err) => {
+            log::error!("Error while searching drives due for reminders: {}", err);
+            return;
+        }
+    };
+
+    for drive in drives {
+        let drive_id = drive.id;
+        let recipients = match conn
+            .run(move |c| sql_interface::reminder_recipients(c, drive_id))
+            .await
+        {
+            Ok(recipients) => recipients,
+            Err(err) => {
+                log::error!(
+                    "Error while listing reminder recipients for {}: {}",
+                    drive_id,
+                    err
+                );
+                continue;
+            }
+        };
+
+        // marked before sending, a broken mail server shouldn't lead to a reminder every hour
+        if let Err(err) = conn
+            .run(move |c| sql_interface::mark_reminder_sent(c, drive_id))
+            .await
+        {
+            log::error!(
+                "Error while marking reminder of {} as sent: {}",
+                drive_id,
+                err
+            );
+            continue;
+        }
+
+        let mut sent = 0;
+        for (person, registered) in recipients {
+            match send_reminder(conn, mailer, outside_address, &drive, person, registered).await {
+                Ok(()) => sent += 1,
+                Err(err) => log::error!("Error while sending reminder for {}: {}", drive_id, err),
+            }
+        }
+        log::info!("Sent {} reminder(s) for drive {}", sent, drive_id);
+    }
+}
+
+async fn send_reminder(
+    conn: &BususagesDBConn,
+    mailer: &Mailer,
+    outside_address: &uri::Absolute<'static>,
+    drive: &Drive,
+    person: Person,
+    registered: bool,
+) -> Result<(), String> {
+    let deadline = drive
+        .deadline
+        .expect("Drives without deadline don't get reminders");
+    let pretty_date = format_labeled_date(drive.date, &drive.label);
+
+    let action = if registered {
+        "Du bist bereits angemeldet, es gibt also nichts weiter zu tun.".to_string()
+    } else {
+        let (raw_token, encoded_token) = tokens::generate_token();
+        let (person_id, drive_id) = (person.id, drive.id);
+        let expiration = deadline.timestamp();
+        let id = conn
+            .run(move |c| {
+                let hashed_token = tokens::hash_token(&raw_token);
+                sql_interface::insert_quick_registration(
+                    c,
+                    person_id,
+                    drive_id,
+                    hashed_token,
+                    expiration,
+                )
+            })
+            .await
+            .map_err(|err| format!("Database error while storing quick registration: {}", err))?;
+        let url = uri!(
+            outside_address.clone(),
+            authflow::quick_register(token = encoded_token, id = id)
+        );
+
+        format!(
+            "Du bist noch nicht angemeldet. Über diesen Link kannst du dich direkt
+anmelden:
+
+{}
+
+Falls du nicht mitfahren willst, kannst du diese Email einfach ignorieren.",
+            url
+        )
+    };
+
+    mailer
+        .send(
+            person.email,
+            format!("[Komplett normale Busliste] Erinnerung: {}", pretty_date),
+            format!(
+                r#"Hallo {},
+
+die Anmeldung für die Busfahrt am {} endet am {} UTC.
+
+{}
+
+Ob und wann du solche Erinnerungen bekommst, kannst du auf deinem Dashboard
+einstellen.
+
+Mit freundlichen Grüßen,
+Komplett normale Busliste"#,
+                person.prename,
+                pretty_date,
+                deadline.format("%d.%m.%Y um %H:%M"),
+                action,
+            ),
+        )
+        .await
+        .map_err(|err| err.to_string())
+}
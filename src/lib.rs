@@ -0,0 +1,11 @@
+//! Everything working on the database without needing a running server, shared by the server and
+//! `busliste-admin`.
+
+pub mod audit;
+pub mod date_helpers;
+pub mod migrations;
+pub mod settings;
+pub mod sql_interface;
+pub mod tokens;
+
+use date_helpers::*;
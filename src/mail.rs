@@ -0,0 +1,210 @@
+use {
+    super::config::{Config, MailTransportKind},
+    lettre::{
+        message::Mailbox, transport::smtp::authentication::Credentials, AsyncFileTransport,
+        AsyncSendmailTransport, AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
+    },
+    rocket::{config::Config as RocketConfig, fairing::AdHoc},
+    std::sync::Arc,
+    thiserror::Error,
+};
+
+#[derive(Error, Debug)]
+pub enum MailError {
+    #[error("SMTP error sending mail: {0}")]
+    SmtpError(#[from] lettre::transport::smtp::Error),
+    #[error("sendmail error sending mail: {0}")]
+    SendmailError(#[from] lettre::transport::sendmail::Error),
+    #[error("Error writing mail to file: {0}")]
+    FileError(#[from] lettre::transport::file::Error),
+    #[error("Error while building email: {0}")]
+    BuildError(#[from] lettre::error::Error),
+}
+
+/// A plain text mail, not yet encoded for the wire.
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub from: Mailbox,
+    pub to: Mailbox,
+    pub subject: String,
+    pub body: String,
+}
+
+impl Mail {
+    /// Builds the actual mail which can be handed to lettre.
+    pub fn build(&self) -> Result<lettre::Message, lettre::error::Error> {
+        lettre::Message::builder()
+            .from(self.from.clone())
+            .to(self.to.clone())
+            .subject(&self.subject)
+            .body(self.body.clone())
+    }
+}
+
+/// Something which is able to get a mail out of this instance, be it to an actual inbox or just
+/// somewhere an admin can look at it.
+#[async_trait]
+pub trait MailTransport: Send + Sync {
+    async fn send(&self, mail: &Mail) -> Result<(), MailError>;
+}
+
+#[async_trait]
+impl MailTransport for AsyncSmtpTransport<Tokio1Executor> {
+    async fn send(&self, mail: &Mail) -> Result<(), MailError> {
+        AsyncTransport::send(self, mail.build()?).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MailTransport for AsyncSendmailTransport<Tokio1Executor> {
+    async fn send(&self, mail: &Mail) -> Result<(), MailError> {
+        AsyncTransport::send(self, mail.build()?).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl MailTransport for AsyncFileTransport<Tokio1Executor> {
+    async fn send(&self, mail: &Mail) -> Result<(), MailError> {
+        AsyncTransport::send(self, mail.build()?).await?;
+        Ok(())
+    }
+}
+
+/// Just prints mails undecoded to stdout, useful for development where nobody wants to set up a
+/// mail server.
+pub struct StdoutTransport;
+
+#[async_trait]
+impl MailTransport for StdoutTransport {
+    async fn send(&self, mail: &Mail) -> Result<(), MailError> {
+        println!(
+            "From: {}\nTo: {}\nSubject: {}\n\n{}\n",
+            mail.from, mail.to, mail.subject, mail.body
+        );
+        Ok(())
+    }
+}
+
+/// Keeps mails in memory instead of sending them, so tests can check what would've been sent.
+/// Clones share the same mails.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MemoryTransport {
+    pub sent: Arc<std::sync::Mutex<Vec<Mail>>>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl MailTransport for MemoryTransport {
+    async fn send(&self, mail: &Mail) -> Result<(), MailError> {
+        // make sure it'd be accepted by the other transports as well
+        mail.build()?;
+        self.sent.lock().unwrap().push(mail.clone());
+        Ok(())
+    }
+}
+
+/// Sends all mails of this instance with the configured sender and transport. Available as managed
+/// state once [`fairing`] is attached. Cheap to clone, so background jobs can get their own.
+#[derive(Clone)]
+pub struct Mailer {
+    from: Mailbox,
+    transport: Arc<dyn MailTransport>,
+}
+
+impl Mailer {
+    pub fn new(from: lettre::Address, transport: Box<dyn MailTransport>) -> Self {
+        Self {
+            from: Mailbox::new(Some("Komplett normale Busliste".to_string()), from),
+            transport: transport.into(),
+        }
+    }
+
+    /// Sends a plain text mail to the given address.
+    pub async fn send(
+        &self,
+        to: lettre::Address,
+        subject: impl Into<String>,
+        body: impl Into<String>,
+    ) -> Result<(), MailError> {
+        let mail = Mail {
+            from: self.from.clone(),
+            to: Mailbox::new(None, to),
+            subject: subject.into(),
+            body: body.into(),
+        };
+        self.transport.send(&mail).await
+    }
+}
+
+/// Constructs the transport the config asks for, or the reason why that isn't possible.
+fn transport_from_config(
+    config: &Config,
+    kind: MailTransportKind,
+) -> Result<Box<dyn MailTransport>, String> {
+    let transport: Box<dyn MailTransport> = match kind {
+        MailTransportKind::Smtp => {
+            let (smtp_server, email_creds) = match (&config.smtp_server, &config.email_creds) {
+                (Some(smtp_server), Some(email_creds)) => (smtp_server, email_creds),
+                _ => {
+                    return Err(
+                        "the SMTP transport needs both smtp_server and email_creds".to_string()
+                    )
+                }
+            };
+            let creds = Credentials::new(config.email.to_string(), email_creds.clone());
+            Box::new(
+                AsyncSmtpTransport::<Tokio1Executor>::relay(smtp_server)
+                    .map_err(|err| format!("invalid SMTP server: {}", err))?
+                    .credentials(creds)
+                    .build(),
+            )
+        }
+        MailTransportKind::Sendmail => Box::new(match &config.sendmail_command {
+            Some(command) => AsyncSendmailTransport::<Tokio1Executor>::new_with_command(command),
+            None => AsyncSendmailTransport::<Tokio1Executor>::new(),
+        }),
+        MailTransportKind::File => {
+            let Some(directory) = &config.mail_directory else {
+                return Err("the file transport needs mail_directory".to_string());
+            };
+            Box::new(AsyncFileTransport::<Tokio1Executor>::new(directory))
+        }
+        MailTransportKind::Stdout => Box::new(StdoutTransport),
+    };
+    Ok(transport)
+}
+
+/// Puts a [`Mailer`] into managed state. Needs to be attached after the [`Config`] fairing.
+pub fn fairing() -> AdHoc {
+    AdHoc::try_on_ignite("Mail transport", |rocket| async move {
+        let mailer = {
+            let config = rocket
+                .state::<Config>()
+                .expect("Config is not set in main!");
+
+            // in debug mode nobody wants to set up a mail server, so just print everything by
+            // default
+            let kind = config.mail_transport.unwrap_or_else(|| {
+                if rocket.figment().profile() == RocketConfig::DEBUG_PROFILE {
+                    MailTransportKind::Stdout
+                } else {
+                    MailTransportKind::Smtp
+                }
+            });
+
+            transport_from_config(config, kind)
+                .map(|transport| Mailer::new(config.email.clone(), transport))
+        };
+
+        match mailer {
+            Ok(mailer) => Ok(rocket.manage(mailer)),
+            Err(err) => {
+                log::error!("Invalid mail configuration: {}", err);
+                Err(rocket)
+            }
+        }
+    })
+}
@@ -1,30 +1,50 @@
 #[macro_use]
 extern crate rocket;
 
+mod api;
 mod authflow;
+mod calendar;
 mod config;
-mod date_helpers;
-mod sql_interface;
+mod export;
+mod jobs;
+mod mail;
+mod mensa_screen;
+mod notifications;
+mod pdf;
+mod person_import;
+mod ratelimit;
 mod superuser;
 #[cfg(test)]
 mod tests;
 
 use {
-    authflow::{Superuser, User},
+    authflow::{Staff, User},
     chrono::Utc,
-    date_helpers::*,
+    config::Config,
+    komplett_normale_busliste::{
+        audit,
+        date_helpers::{self, *},
+        migrations::{self, PreparedDatabase},
+        settings,
+        sql_interface::{
+            self, ApplyRegistrationError, DriveFilter, Scope, SearchPersonBy, SearchRegistrationsBy,
+        },
+        tokens,
+    },
+    mail::Mailer,
+    notifications::{notify_promoted, notify_registration_change, RegistrationChange},
     rocket::{
         fairing::AdHoc,
-        form::{Form, Strict},
+        form::{Form, Lenient, Strict},
         fs::FileServer,
-        http::{Cookie, CookieJar},
+        http::{ContentType, Cookie, CookieJar, Status},
         request::FlashMessage,
         response::{Flash, Redirect},
+        State,
     },
     rocket_dyn_templates::{context, handlebars::handlebars_helper, Template},
     rocket_sync_db_pools::{database, rusqlite},
     serde::Serialize,
-    sql_interface::{ApplyRegistrationError, DriveFilter, SearchRegistrationsBy},
     std::fmt,
 };
 
@@ -35,6 +55,17 @@ pub fn server_error(admin_err: impl fmt::Display, user_err: impl AsRef<str>) ->
     Flash::error(Redirect::to(uri!(server_error_panel)), user_err.as_ref())
 }
 
+/// Refuses any changes while a superuser is only viewing the site as the person.
+fn deny_if_impersonated(user: &User) -> Result<(), Flash<Redirect>> {
+    match user.impersonator_id() {
+        Some(_) => Err(Flash::error(
+            Redirect::to(uri!(dashboard)),
+            "Du siehst die Seite nur so, wie diese Person sie sieht. Ändern kannst du hier nichts.",
+        )),
+        None => Ok(()),
+    }
+}
+
 #[database("bususages")]
 pub struct BususagesDBConn(rusqlite::Connection);
 
@@ -57,16 +88,26 @@ fn server_error_panel(flash: FlashMessage<'_>) -> Template {
 async fn dashboard(
     conn: BususagesDBConn,
     user: User,
-    superuser: Option<Superuser>,
+    staff: Option<Staff>,
     flash: Option<FlashMessage<'_>>,
 ) -> Result<Template, Flash<Redirect>> {
     #[derive(Debug, Serialize)]
     struct TemplateRegistration {
         pretty_date: String,
         locked_reason: Option<String>,
+        waitlist_possible: bool,
         registration: sql_interface::Registration,
     }
 
+    #[derive(Debug, Serialize)]
+    struct TemplateApiToken {
+        id: i64,
+        name: String,
+        scopes: String,
+        created: String,
+        last_used: Option<String>,
+    }
+
     let mut registrations = [Vec::new(), Vec::new()];
     let person_id = user.person_id();
 
@@ -94,18 +135,81 @@ async fn dashboard(
 
         let as_template = from_db
             .into_iter()
-            .map(|registration| TemplateRegistration {
-                pretty_date: format_date(registration.drive.date),
-                locked_reason: possible_to_register(&registration.drive, !registration.registered)
-                    .err()
-                    .map(|reason| reason.to_string()),
-                registration,
+            .map(|registration| {
+                let impossible_reason =
+                    possible_to_register(&registration.drive, !registration.registered).err();
+                TemplateRegistration {
+                    pretty_date: format_labeled_date(
+                        registration.drive.date,
+                        &registration.drive.label,
+                    ),
+                    waitlist_possible: matches!(
+                        impossible_reason,
+                        Some(ImpossibleReason::RegistrationCapReached)
+                    ) && registration.waitlist_position.is_none(),
+                    locked_reason: impossible_reason.map(|reason| reason.to_string()),
+                    registration,
+                }
             })
             .collect();
 
         registrations[i] = as_template;
     }
 
+    let (reminders, registration_mails, calendar_link, api_tokens) = conn
+        .run(move |c| {
+            Ok::<_, rusqlite::Error>((
+                sql_interface::get_reminder_preference(c, person_id)?,
+                sql_interface::wants_registration_mails(c, person_id)?,
+                sql_interface::get_calendar_token(c, person_id)?.is_some(),
+                sql_interface::list_api_tokens(c, person_id)?,
+            ))
+        })
+        .await
+        .map_err(|err| {
+            server_error(
+                format!("Error while loading mail preferences: {}", err),
+                "ein Fehler trat während des Ladens der Emaileinstellungen auf",
+            )
+        })?;
+
+    let impersonated = match user.impersonator_id() {
+        Some(_) => {
+            let person = conn
+                .run(move |c| sql_interface::search_person(c, &SearchPersonBy::Id(person_id)))
+                .await
+                .map_err(|err| {
+                    server_error(
+                        format!("Error while loading impersonated person: {}", err),
+                        "ein Fehler trat während des Ladens der Person auf",
+                    )
+                })?;
+            Some(format!("{} {}", person.prename, person.name))
+        }
+        None => None,
+    };
+
+    let format_timestamp = |timestamp: i64| {
+        chrono::NaiveDateTime::from_timestamp_opt(timestamp, 0)
+            .map(|datetime| datetime.format("%d.%m.%Y %H:%M").to_string())
+            .unwrap_or_default()
+    };
+    let api_tokens: Vec<_> = api_tokens
+        .into_iter()
+        .map(|token| TemplateApiToken {
+            id: token.id,
+            name: token.name,
+            scopes: token
+                .scopes
+                .iter()
+                .map(|scope| scope.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            created: format_timestamp(token.created),
+            last_used: token.last_used.map(format_timestamp),
+        })
+        .collect();
+
     let flash = flash.map(|flashmsg| flashmsg.message().to_string());
     let [future_regs, past_regs] = registrations;
 
@@ -115,21 +219,97 @@ async fn dashboard(
             flash,
             future_regs,
             past_regs,
-            show_superuser_controls: superuser.is_some(),
+            reminders: reminders.as_str(),
+            registration_mails,
+            calendar_link,
+            api_tokens,
+            show_superuser_controls: staff.is_some(),
+            impersonated,
         },
     ))
 }
 
 #[post("/logout")]
-fn logout(_user: User, jar: &CookieJar<'_>) -> Redirect {
+async fn logout(
+    conn: BususagesDBConn,
+    user: User,
+    jar: &CookieJar<'_>,
+) -> Result<Redirect, Flash<Redirect>> {
+    // API tokens have no session, they're revoked on the dashboard instead
+    if let Some(session_id) = user.session_id() {
+        conn.run(move |c| sql_interface::delete_session(c, session_id))
+            .await
+            .map_err(|err| {
+                server_error(
+                    format!("Error while deleting session {}: {}", session_id, err),
+                    "ein Fehler trat beim Abmelden auf",
+                )
+            })?;
+    }
+
     jar.remove(Cookie::named("auth-token"));
-    Redirect::to(uri!(authflow::index))
+    jar.remove(Cookie::named("impersonator-token"));
+    Ok(Redirect::to(uri!(authflow::index)))
+}
+
+/// Ends all sessions of the person, e.g. when they forgot to log out on a shared device.
+#[post("/logout/everywhere")]
+async fn logout_everywhere(
+    conn: BususagesDBConn,
+    user: User,
+    jar: &CookieJar<'_>,
+) -> Result<Redirect, Flash<Redirect>> {
+    deny_if_impersonated(&user)?;
+    let person_id = user.person_id();
+    conn.run(move |c| sql_interface::delete_sessions_of_person(c, person_id, Some(person_id)))
+        .await
+        .map_err(|err| {
+            server_error(
+                format!("Error while deleting sessions of {}: {}", person_id, err),
+                "ein Fehler trat beim Abmelden auf",
+            )
+        })?;
+
+    jar.remove(Cookie::named("auth-token"));
+    Ok(Redirect::to(uri!(authflow::index)))
+}
+
+/// Returns from viewing the site as someone else to the own superuser session.
+#[post("/impersonation/stop")]
+async fn stop_impersonating(
+    conn: BususagesDBConn,
+    user: User,
+    jar: &CookieJar<'_>,
+) -> Result<Redirect, Flash<Redirect>> {
+    let Some(superuser_id) = user.impersonator_id() else {
+        return Ok(Redirect::to(uri!(dashboard)));
+    };
+
+    let restored = authflow::stop_impersonating(&conn, jar, &user)
+        .await
+        .map_err(|err| {
+            server_error(
+                format!("Error while stopping impersonation: {}", err),
+                "an error occured while returning to the superuser session",
+            )
+        })?;
+
+    log::warn!(
+        "Superuser {} stopped viewing the site as {}",
+        superuser_id,
+        user.person_id()
+    );
+    if restored {
+        Ok(Redirect::to(uri!(superuser::person_panel)))
+    } else {
+        Ok(Redirect::to(uri!(authflow::index)))
+    }
 }
 
 /// A registration form to be returned by the frontend.
 #[derive(FromForm, Debug, Clone)]
 pub struct Registration {
-    date: time::Date,
+    drive_id: i64,
     new_state: bool,
 }
 
@@ -140,7 +320,7 @@ impl Registration {
         user: &authflow::User,
     ) -> sql_interface::RegistrationUpdate {
         sql_interface::RegistrationUpdate {
-            date: time_to_chrono_date(self.date),
+            drive_id: self.drive_id,
             person_id: user.person_id(),
             registered: self.new_state,
         }
@@ -150,18 +330,20 @@ impl Registration {
 #[post("/register", data = "<registration>")]
 async fn register(
     conn: BususagesDBConn,
+    mailer: &State<Mailer>,
     user: User,
     registration: Form<Strict<Registration>>,
 ) -> Result<Redirect, Flash<Redirect>> {
-    let query_date = time_to_chrono_date(registration.date);
+    deny_if_impersonated(&user)?;
+    let drive_id = registration.drive_id;
     let drive = conn
-        .run(move |c| sql_interface::get_drive(c, query_date))
+        .run(move |c| sql_interface::get_drive(c, drive_id))
         .await
         .map_err(|err| {
             server_error(
                 format!(
-                    "Error while querying drive deadline for '{}': {}",
-                    registration.date, err
+                    "Error while querying drive deadline for drive {}: {}",
+                    drive_id, err
                 ),
                 "ein Fehler trat während des Abfragens der Anmeldungsdeadline auf",
             )
@@ -169,19 +351,19 @@ async fn register(
         .ok_or_else(|| {
             Flash::error(
                 Redirect::to(uri!(dashboard)),
-                "Das Datum der Fahrt ist nicht valide, versuch es nochmal.",
+                "Die Fahrt ist nicht valide, versuch es nochmal.",
             )
         })?;
 
     let person_id = user.person_id();
     let currently_registered = conn
-        .run(move |c| sql_interface::is_registered(c, person_id, drive.date))
+        .run(move |c| sql_interface::is_registered(c, person_id, drive_id))
         .await
         .map_err(|err| {
             server_error(
                 format!(
-                    "Error while querying registration for {} on {}: {}",
-                    person_id, registration.date, err
+                    "Error while querying registration for {} on drive {}: {}",
+                    person_id, drive_id, err
                 ),
                 "ein Fehler trat während des Abprüfens der aktuellen Registrierung auf",
             )
@@ -200,14 +382,20 @@ async fn register(
     }
 
     let update = registration.to_registration_update(&user);
-    match conn
-        .run(move |c| sql_interface::update_registration(c, &update))
+    let applied = match conn
+        .run(move |c| sql_interface::update_registration(c, &update, Some(person_id)))
         .await
     {
-        Err(ApplyRegistrationError::UnknownDriveDate) => {
+        Err(ApplyRegistrationError::UnknownDrive) => {
+            return Err(Flash::error(
+                Redirect::to(uri!(dashboard)),
+                "Unbekannte Busfahrt, vielleicht wurde sie gerade gelöscht.",
+            ))
+        }
+        Err(ApplyRegistrationError::NotForPerson) => {
             return Err(Flash::error(
                 Redirect::to(uri!(dashboard)),
-                "Ungültiges Fahrdatum, es ist keine Busfahrt an diesem Datum bekannt.",
+                "Diese Busfahrt ist nicht für deine Gruppe.",
             ))
         }
         Err(err) => {
@@ -216,12 +404,410 @@ async fn register(
                 "ein Fehler trat während der Aktualisierung der Anmeldung auf",
             ))
         }
-        _ => (),
+        Ok(applied) => applied,
     };
 
+    if applied.changed {
+        let change = RegistrationChange::new(registration.new_state, false);
+        notify_registration_change(&conn, mailer, person_id, drive_id, change).await;
+    }
+    notify_promoted(&conn, mailer, drive_id, applied.promoted).await;
+
     Ok(Redirect::to(uri!(dashboard)))
 }
 
+/// A form to join or leave the waitlist of a drive.
+#[derive(FromForm, Debug, Clone)]
+pub struct WaitlistForm {
+    drive_id: i64,
+    join: bool,
+}
+
+#[post("/waitlist", data = "<form>")]
+async fn waitlist(
+    conn: BususagesDBConn,
+    user: User,
+    form: Form<Strict<WaitlistForm>>,
+) -> Result<Redirect, Flash<Redirect>> {
+    deny_if_impersonated(&user)?;
+    let drive_id = form.drive_id;
+    let person_id = user.person_id();
+
+    if !form.join {
+        conn.run(move |c| sql_interface::leave_waitlist(c, person_id, drive_id))
+            .await
+            .map_err(|err| {
+                server_error(
+                    format!(
+                        "Error while removing {} from waitlist of drive {}: {}",
+                        person_id, drive_id, err
+                    ),
+                    "ein Fehler trat während des Verlassens der Warteliste auf",
+                )
+            })?;
+        return Ok(Redirect::to(uri!(dashboard)));
+    }
+
+    let drive = conn
+        .run(move |c| sql_interface::get_drive(c, drive_id))
+        .await
+        .map_err(|err| {
+            server_error(
+                format!("Error while querying drive {}: {}", drive_id, err),
+                "ein Fehler trat während des Abfragens der Busfahrt auf",
+            )
+        })?
+        .ok_or_else(|| {
+            Flash::error(
+                Redirect::to(uri!(dashboard)),
+                "Die Fahrt ist nicht valide, versuch es nochmal.",
+            )
+        })?;
+
+    let currently_registered = conn
+        .run(move |c| sql_interface::is_registered(c, person_id, drive_id))
+        .await
+        .map_err(|err| {
+            server_error(
+                format!(
+                    "Error while querying registration for {} on drive {}: {}",
+                    person_id, drive_id, err
+                ),
+                "ein Fehler trat während des Abprüfens der aktuellen Registrierung auf",
+            )
+        })?;
+
+    // the waitlist is only for drives which are full, everything else can be registered directly
+    match possible_to_register(&drive, !currently_registered) {
+        Err(ImpossibleReason::RegistrationCapReached) => (),
+        Err(reason) => {
+            return Err(Flash::error(
+                Redirect::to(uri!(dashboard)),
+                reason.to_string(),
+            ))
+        }
+        Ok(()) => {
+            return Err(Flash::error(
+                Redirect::to(uri!(dashboard)),
+                "Es sind noch Plätze frei, du kannst dich direkt anmelden.",
+            ))
+        }
+    }
+
+    match conn
+        .run(move |c| sql_interface::join_waitlist(c, person_id, drive_id))
+        .await
+    {
+        Err(ApplyRegistrationError::UnknownDrive) => Err(Flash::error(
+            Redirect::to(uri!(dashboard)),
+            "Unbekannte Busfahrt, vielleicht wurde sie gerade gelöscht.",
+        )),
+        Err(ApplyRegistrationError::NotForPerson) => Err(Flash::error(
+            Redirect::to(uri!(dashboard)),
+            "Diese Busfahrt ist nicht für deine Gruppe.",
+        )),
+        Err(err) => Err(server_error(
+            format!("Error while joining waitlist: {}", err),
+            "ein Fehler trat während des Eintragens in die Warteliste auf",
+        )),
+        Ok(()) => Ok(Redirect::to(uri!(dashboard))),
+    }
+}
+
+/// A form to choose when to get reminder mails before deadlines.
+#[derive(FromForm, Debug, Clone)]
+pub struct ReminderForm {
+    reminders: String,
+}
+
+#[post("/reminders", data = "<form>")]
+async fn set_reminders(
+    conn: BususagesDBConn,
+    user: User,
+    form: Form<Strict<ReminderForm>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    deny_if_impersonated(&user)?;
+    let preference = form.reminders.parse().map_err(|_| {
+        Flash::error(
+            Redirect::to(uri!(dashboard)),
+            "Unbekannte Einstellung für Erinnerungen.",
+        )
+    })?;
+
+    let person_id = user.person_id();
+    conn.run(move |c| sql_interface::set_reminder_preference(c, person_id, preference))
+        .await
+        .map_err(|err| {
+            server_error(
+                format!(
+                    "Error while setting reminder preference of {}: {}",
+                    person_id, err
+                ),
+                "ein Fehler trat während des Speicherns der Erinnerungseinstellung auf",
+            )
+        })?;
+
+    Ok(Flash::success(
+        Redirect::to(uri!(dashboard)),
+        "Erinnerungseinstellung gespeichert.",
+    ))
+}
+
+/// A form to opt in or out of mails about changed registrations.
+#[derive(FromForm, Debug, Clone)]
+pub struct RegistrationMailsForm {
+    enabled: bool,
+}
+
+#[post("/registration-mails", data = "<form>")]
+async fn set_registration_mails(
+    conn: BususagesDBConn,
+    user: User,
+    form: Form<Strict<RegistrationMailsForm>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    deny_if_impersonated(&user)?;
+    let person_id = user.person_id();
+    let enabled = form.enabled;
+    conn.run(move |c| sql_interface::set_registration_mails(c, person_id, enabled))
+        .await
+        .map_err(|err| {
+            server_error(
+                format!(
+                    "Error while setting registration mails of {}: {}",
+                    person_id, err
+                ),
+                "ein Fehler trat während des Speicherns der Emaileinstellung auf",
+            )
+        })?;
+
+    Ok(Flash::success(
+        Redirect::to(uri!(dashboard)),
+        "Emaileinstellung gespeichert.",
+    ))
+}
+
+/// Creates a new secret link to the calendar feed of the person, replacing the old one. Only the
+/// hash of the token is stored, so the link can only be shown once.
+#[post("/calendar/link")]
+async fn create_calendar_link(
+    conn: BususagesDBConn,
+    user: User,
+    config: &State<Config>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    deny_if_impersonated(&user)?;
+    let person_id = user.person_id();
+    let (raw_token, encoded_token) = tokens::generate_token();
+    let hashed_token = tokens::hash_feed_token(&raw_token);
+    conn.run(move |c| sql_interface::set_calendar_token(c, person_id, Some(hashed_token)))
+        .await
+        .map_err(|err| {
+            server_error(
+                format!(
+                    "Error while setting calendar token of {}: {}",
+                    person_id, err
+                ),
+                "ein Fehler trat während des Erstellens des Kalender-Links auf",
+            )
+        })?;
+
+    let url = uri!(
+        config.outside_address.clone(),
+        calendar_feed(token = encoded_token, person_id = person_id)
+    );
+    Ok(Flash::success(
+        Redirect::to(uri!(dashboard)),
+        format!(
+            "Dein Kalender-Link, er wird nur dieses eine Mal angezeigt: {}",
+            url
+        ),
+    ))
+}
+
+#[post("/calendar/link/delete")]
+async fn delete_calendar_link(
+    conn: BususagesDBConn,
+    user: User,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    deny_if_impersonated(&user)?;
+    let person_id = user.person_id();
+    conn.run(move |c| sql_interface::set_calendar_token(c, person_id, None))
+        .await
+        .map_err(|err| {
+            server_error(
+                format!(
+                    "Error while deleting calendar token of {}: {}",
+                    person_id, err
+                ),
+                "ein Fehler trat während des Löschens des Kalender-Links auf",
+            )
+        })?;
+
+    Ok(Flash::success(
+        Redirect::to(uri!(dashboard)),
+        "Kalender-Link gelöscht.",
+    ))
+}
+
+/// The iCalendar feed of a person, for subscribing to it in a calendar app. Calendar apps can't
+/// log in, so the secret token in the link is all the authentication there is.
+#[get("/calendar/<token>?<person_id>")]
+async fn calendar_feed(
+    conn: BususagesDBConn,
+    token: String,
+    person_id: i64,
+) -> Result<(ContentType, String), Status> {
+    let db_token = match conn
+        .run(move |c| sql_interface::get_calendar_token(c, person_id))
+        .await
+    {
+        Ok(Some(db_token)) => db_token,
+        Ok(None) | Err(rusqlite::Error::QueryReturnedNoRows) => return Err(Status::NotFound),
+        Err(err) => {
+            log::error!(
+                "Error while querying calendar token of {}: {}",
+                person_id,
+                err
+            );
+            return Err(Status::InternalServerError);
+        }
+    };
+    if !tokens::verify_feed_token_hash(&token, &db_token) {
+        return Err(Status::NotFound);
+    }
+
+    let (registrations, alarm_hours) = conn
+        .run(move |c| {
+            let registrations = sql_interface::search_registrations(
+                c,
+                &SearchRegistrationsBy::PersonId {
+                    id: person_id,
+                    filter: DriveFilter::ListAll,
+                },
+            )?;
+            let alarm_hours = settings::REMINDER_HOURS.get_integer(c)?.unwrap_or_default();
+            Ok::<_, rusqlite::Error>((registrations, alarm_hours))
+        })
+        .await
+        .map_err(|err| {
+            log::error!(
+                "Error while loading calendar feed of {}: {}",
+                person_id,
+                err
+            );
+            Status::InternalServerError
+        })?;
+
+    Ok((
+        ContentType::Calendar,
+        calendar::feed(&registrations, Utc::now().naive_utc(), alarm_hours),
+    ))
+}
+
+/// A form to create a personal API token.
+#[derive(FromForm, Debug)]
+pub struct ApiTokenForm {
+    name: String,
+    scopes: Lenient<Vec<String>>,
+}
+
+/// Creates a personal API token for scripts. Only the hash of it is stored, so it can only be
+/// shown once.
+#[post("/api-tokens/new", data = "<form>")]
+async fn create_api_token(
+    conn: BususagesDBConn,
+    user: User,
+    form: Form<Strict<ApiTokenForm>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    deny_if_impersonated(&user)?;
+    let ApiTokenForm { name, scopes } = form.into_inner().into_inner();
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(Flash::error(
+            Redirect::to(uri!(dashboard)),
+            "Bitte gib dem Token einen Namen.",
+        ));
+    }
+
+    let mut scopes = scopes
+        .iter()
+        .map(|scope| scope.parse::<Scope>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| Flash::error(Redirect::to(uri!(dashboard)), "Unbekannte Berechtigung."))?;
+    scopes.sort();
+    scopes.dedup();
+    if scopes.is_empty() {
+        return Err(Flash::error(
+            Redirect::to(uri!(dashboard)),
+            "Bitte wähle mindestens eine Berechtigung aus.",
+        ));
+    }
+
+    let person_id = user.person_id();
+    let (raw_token, encoded_token) = tokens::generate_token();
+    let hashed_token = tokens::hash_feed_token(&raw_token);
+    let created = Utc::now().timestamp();
+    let id = conn
+        .run(move |c| {
+            sql_interface::insert_api_token(c, person_id, &name, &hashed_token, &scopes, created)
+        })
+        .await
+        .map_err(|err| {
+            server_error(
+                format!("Error while creating API token of {}: {}", person_id, err),
+                "ein Fehler trat während des Erstellens des API-Tokens auf",
+            )
+        })?;
+
+    Ok(Flash::success(
+        Redirect::to(uri!(dashboard)),
+        format!(
+            "Dein API-Token, es wird nur dieses eine Mal angezeigt: {}{}_{}",
+            authflow::API_TOKEN_PREFIX,
+            id,
+            encoded_token
+        ),
+    ))
+}
+
+#[derive(FromForm, Debug, Clone)]
+pub struct DeleteApiTokenForm {
+    id: i64,
+}
+
+#[post("/api-tokens/delete", data = "<form>")]
+async fn delete_api_token(
+    conn: BususagesDBConn,
+    user: User,
+    form: Form<Strict<DeleteApiTokenForm>>,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    deny_if_impersonated(&user)?;
+    let person_id = user.person_id();
+    let id = form.id;
+    let deleted = conn
+        .run(move |c| sql_interface::delete_api_token(c, person_id, id))
+        .await
+        .map_err(|err| {
+            server_error(
+                format!(
+                    "Error while deleting API token {} of {}: {}",
+                    id, person_id, err
+                ),
+                "ein Fehler trat während des Löschens des API-Tokens auf",
+            )
+        })?;
+
+    if !deleted {
+        return Err(Flash::error(
+            Redirect::to(uri!(dashboard)),
+            "Dieses API-Token gibt es nicht.",
+        ));
+    }
+    Ok(Flash::success(
+        Redirect::to(uri!(dashboard)),
+        "API-Token gelöscht.",
+    ))
+}
+
 enum ImpossibleReason {
     RegistrationCapReached,
     DeadlineExpired,
@@ -262,6 +848,43 @@ fn possible_to_register(
     }
 }
 
+/// A fairing creating the database if it's empty and applying pending migrations before launch,
+/// as configured in [`config::Config::migrations`]. After a dry run, launching is refused since the
+/// schema is still outdated. Needs to be attached after the database fairing.
+fn migration_fairing() -> AdHoc {
+    AdHoc::try_on_ignite("Database migrations", |rocket| {
+        Box::pin(async move {
+            let options = rocket
+                .state::<Config>()
+                .expect("Config is not set in main!")
+                .migrations
+                .clone();
+            let conn = BususagesDBConn::get_one(&rocket)
+                .await
+                .expect("Database fairing not attached!");
+
+            match conn
+                .run(move |c| migrations::prepare_database(c, &options))
+                .await
+            {
+                Ok(PreparedDatabase::UpToDate) => Ok(rocket),
+                Ok(PreparedDatabase::Migrated(applied)) => {
+                    log::info!("Applied {} migration(s)", applied.len());
+                    Ok(rocket)
+                }
+                Ok(PreparedDatabase::DryRun(_)) => {
+                    log::info!("Dry run: all pending migrations apply, rolled them back");
+                    Err(rocket)
+                }
+                Err(err) => {
+                    log::error!("Error while migrating the database: {}", err);
+                    Err(rocket)
+                }
+            }
+        })
+    })
+}
+
 #[launch]
 fn rocket() -> _ {
     rocket::build()
@@ -275,33 +898,75 @@ fn rocket() -> _ {
             engines.handlebars.register_helper("equals", Box::new(equals));
         }))
         .attach(AdHoc::config::<config::Config>())
+        .attach(mail::fairing())
+        .attach(authflow::rate_limit_fairing())
         .attach(BususagesDBConn::fairing())
+        .attach(migration_fairing())
+        .attach(jobs::fairing())
         .mount(
             "/",
             routes![
                 dashboard,
                 logout,
+                logout_everywhere,
+                stop_impersonating,
                 register,
+                waitlist,
+                set_reminders,
+                set_registration_mails,
+                create_calendar_link,
+                delete_calendar_link,
+                calendar_feed,
+                create_api_token,
+                delete_api_token,
+                mensa_screen::mensa,
+                mensa_screen::mensa_events,
                 server_error_panel,
                 superuser::panel,
                 superuser::drives_panel,
                 superuser::create_new_drive,
                 superuser::delete_drive,
                 superuser::update_deadline,
+                superuser::create_new_schedule,
+                superuser::delete_schedule,
+                superuser::create_new_schedule_exception,
+                superuser::delete_schedule_exception,
+                superuser::generate_scheduled_drives,
+                superuser::update_drive_groups,
                 superuser::introspect_drive,
+                superuser::export_drive,
+                superuser::export_drive_pdf,
+                superuser::attendance_panel,
+                superuser::update_attendance,
                 superuser::registrations_panel,
+                superuser::export_registrations,
+                superuser::no_shows_panel,
+                superuser::audit_panel,
+                superuser::export_audit_log,
                 superuser::person_panel,
                 superuser::create_new_person,
                 superuser::update_person,
+                superuser::preview_import,
+                superuser::apply_import,
                 superuser::delete_person,
+                superuser::revoke_sessions,
+                superuser::impersonate_person,
+                superuser::update_roles,
+                superuser::create_new_group,
+                superuser::delete_group,
                 superuser::introspect_person,
                 superuser::register_person,
                 superuser::settings,
                 superuser::set_setting,
                 authflow::index,
                 authflow::login,
-                authflow::verify_token
+                authflow::verify_token,
+                authflow::verify_code,
+                authflow::quick_register,
+                authflow::confirm_quick_register
             ],
         )
+        .mount("/api/v1", api::routes())
+        .register("/api/v1", api::catchers())
         .mount("/static", FileServer::from("./static"))
 }
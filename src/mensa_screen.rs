@@ -1,15 +1,123 @@
-use crate::sql_interface;
-use rocket_dyn_templates::{context, Template};
-
-#[get("/mensa")]
-pub fn mensa() -> Template {
-    let mut next_drive;
-    let mut next_next_drive;
-    Template::render(
-        "mensa",
-        context!(
-            test0: "test0",
-            test1: "test1"
-        ),
-    )
+use {
+    super::{config::Config, format_labeled_date, sql_interface, BususagesDBConn},
+    chrono::Utc,
+    rocket::{
+        http::Status,
+        response::stream::{Event, EventStream},
+        tokio::{self, select},
+        Shutdown, State,
+    },
+    rocket_dyn_templates::{context, Template},
+    rocket_sync_db_pools::ConnectionPool,
+    serde::Serialize,
+    std::time::Duration,
+    subtle::ConstantTimeEq,
+};
+
+/// How often the event stream looks for changed registrations.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How many of the upcoming drives the kiosk shows.
+const SHOWN_DRIVES: usize = 2;
+
+/// An upcoming drive as shown on the kiosk screen.
+#[derive(Debug, PartialEq, Serialize)]
+struct KioskDrive {
+    pretty_date: String,
+    /// UNIX timestamp in seconds, for counting down in the browser.
+    deadline: Option<i64>,
+    pretty_deadline: Option<String>,
+    registered: u32,
+    registration_cap: Option<u32>,
+}
+
+/// The kiosk is only reachable with the token from the config, so the link on the cafeteria TV
+/// can't just be guessed. Without a configured token, the kiosk is disabled entirely.
+fn check_token(config: &Config, token: &str) -> Result<(), Status> {
+    let valid = config
+        .kiosk_token
+        .as_deref()
+        .is_some_and(|expected| expected.as_bytes().ct_eq(token.as_bytes()).into());
+    if valid {
+        Ok(())
+    } else {
+        Err(Status::NotFound)
+    }
+}
+
+async fn next_drives(conn: &BususagesDBConn) -> Result<Vec<KioskDrive>, rusqlite::Error> {
+    let drives = conn.run(sql_interface::list_drives).await?;
+    let now = Utc::now().naive_utc();
+
+    Ok(drives
+        .future
+        .into_iter()
+        .take(SHOWN_DRIVES)
+        .map(|drive| KioskDrive {
+            pretty_date: format_labeled_date(drive.date, &drive.label),
+            deadline: drive.deadline.map(|deadline| deadline.timestamp()),
+            pretty_deadline: drive
+                .deadline
+                .filter(|deadline| now < *deadline)
+                .map(|deadline| deadline.format("%d.%m.%Y um %H:%M").to_string()),
+            registered: drive.already_registered_count,
+            registration_cap: drive.registration_cap,
+        })
+        .collect())
+}
+
+/// A read-only screen for the TV in the cafeteria, showing the next drives and how full they are.
+/// It keeps itself up to date through [`mensa_events`].
+#[get("/mensa?<token>")]
+pub async fn mensa(
+    conn: BususagesDBConn,
+    config: &State<Config>,
+    token: &str,
+) -> Result<Template, Status> {
+    check_token(config, token)?;
+    let drives = next_drives(&conn).await.map_err(|err| {
+        log::error!("Error while loading drives for the kiosk: {}", err);
+        Status::InternalServerError
+    })?;
+
+    Ok(Template::render("mensa", context! { drives }))
+}
+
+/// Server-Sent Events with the drives of the kiosk screen, sent once on connecting and then
+/// whenever something about them changed. The database is polled instead of being notified, so
+/// changes from anywhere show up, be it the dashboard, the API or a reminder mail.
+#[get("/mensa/events?<token>")]
+pub fn mensa_events(
+    config: &State<Config>,
+    pool: &State<ConnectionPool<BususagesDBConn, rusqlite::Connection>>,
+    token: &str,
+    mut shutdown: Shutdown,
+) -> Result<EventStream![], Status> {
+    check_token(config, token)?;
+    let pool = pool.inner().clone();
+
+    Ok(EventStream! {
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        let mut last_sent = None;
+        loop {
+            select! {
+                _ = interval.tick() => (),
+                _ = &mut shutdown => break,
+            }
+
+            // only borrow a connection for the moment, the stream may stay open for days
+            let Some(conn) = pool.get().await.map(BususagesDBConn) else {
+                log::error!("No database connection for the kiosk event stream");
+                break;
+            };
+            match next_drives(&conn).await {
+                Ok(drives) if last_sent.as_ref() != Some(&drives) => {
+                    yield Event::json(&drives).event("drives");
+                    last_sent = Some(drives);
+                }
+                Ok(_) => (),
+                Err(err) => log::error!("Error while loading drives for the kiosk: {}", err),
+            }
+        }
+    })
 }
@@ -0,0 +1,277 @@
+//! Schema migrations, embedded into the binary and applied at startup. Which of them a database
+//! already has is tracked in the `schema_version` table.
+
+use {
+    super::{settings, sql_interface},
+    chrono::Utc,
+    rusqlite::Transaction,
+    serde::Deserialize,
+    std::{
+        fs,
+        path::{Path, PathBuf},
+    },
+    thiserror::Error,
+};
+
+/// A change to the schema, written in plain SQL in `src/migrations`.
+pub struct Migration {
+    /// The file name without extension, like `0.3.0-13-api-tokens`. It's recorded in
+    /// `schema_version` once applied.
+    pub name: &'static str,
+    sql: &'static str,
+    /// A query which only compiles once the migration is applied. Databases from before
+    /// `schema_version` existed don't know which migrations they have, so they're asked this way.
+    /// Newer migrations don't need it.
+    legacy_probe: Option<&'static str>,
+}
+
+macro_rules! migration {
+    ($name:literal) => {
+        migration!($name, None)
+    };
+    ($name:literal, $probe:literal) => {
+        migration!($name, Some($probe))
+    };
+    ($name:literal, $probe:expr) => {
+        Migration {
+            name: $name,
+            sql: include_str!(concat!("migrations/", $name, ".sql")),
+            legacy_probe: $probe,
+        }
+    };
+}
+
+/// All migrations in the order they have to be applied in. `init_db.sql` is always up to date with
+/// all of them and records them as applied, so new migrations need to be added there as well.
+pub const MIGRATIONS: &[Migration] = &[
+    migration!("0.1.0-1-add-is-visible", "SELECT is_visible FROM person"),
+    migration!("0.1.0-2-settings-table", "SELECT name FROM settings"),
+    migration!("0.1.0-3-drive-deadline", "SELECT deadline FROM drive"),
+    migration!(
+        "0.2.0-1-drive-registration-cap",
+        "SELECT registration_cap FROM drive"
+    ),
+    migration!("0.3.0-1-waitlist", "SELECT id FROM waitlist"),
+    migration!("0.3.0-2-drive-labels", "SELECT label FROM drive"),
+    migration!(
+        "0.3.0-3-drive-schedules",
+        "SELECT schedule_id FROM drive_schedule"
+    ),
+    migration!("0.3.0-4-attendance", "SELECT attended FROM registration"),
+    migration!("0.3.0-5-deadline-reminders", "SELECT reminders FROM person"),
+    migration!(
+        "0.3.0-6-registration-mails",
+        "SELECT registration_mails FROM person"
+    ),
+    migration!("0.3.0-7-sessions", "SELECT session_id FROM session"),
+    migration!("0.3.0-8-login-codes", "SELECT login_code FROM person"),
+    migration!(
+        "0.3.0-9-impersonation",
+        "SELECT impersonator_id FROM session"
+    ),
+    migration!("0.3.0-10-roles", "SELECT role FROM role"),
+    migration!("0.3.0-11-groups", "SELECT group_id FROM person"),
+    migration!(
+        "0.3.0-12-calendar-token",
+        "SELECT calendar_token FROM person"
+    ),
+    migration!("0.3.0-13-api-tokens", "SELECT api_token_id FROM api_token"),
+    migration!("0.3.0-14-audit-log"),
+    migration!("0.3.0-15-calendar-token-sha256"),
+    migration!("0.3.0-16-api-token-sha256"),
+];
+
+#[derive(Debug, Error)]
+pub enum MigrationError {
+    #[error("Database error: {0}")]
+    RusqliteError(#[from] rusqlite::Error),
+    #[error("Migration {name} failed: {source}")]
+    Failed {
+        name: &'static str,
+        source: rusqlite::Error,
+    },
+    #[error("Migrations left {0} foreign key violation(s) behind")]
+    ForeignKeyViolations(usize),
+    #[error("Database has unknown migration {0} applied, it's from a newer version")]
+    UnknownMigration(String),
+    #[error("Couldn't create backup directory: {0}")]
+    BackupDirectory(#[from] std::io::Error),
+}
+
+/// Finds the migrations the database doesn't have yet. Creates `schema_version` inside of the
+/// transaction if it doesn't exist yet, filling it by probing the schema.
+fn pending_in(tx: &Transaction) -> Result<Vec<&'static Migration>, MigrationError> {
+    let exists: bool = tx.query_row(
+        "SELECT count() > 0
+        FROM sqlite_master
+        WHERE type == 'table' AND name == 'schema_version'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        tx.execute_batch(
+            "CREATE TABLE schema_version(
+                name TEXT NOT NULL,
+                applied INTEGER,
+                PRIMARY KEY (name)
+            );",
+        )?;
+        for migration in MIGRATIONS {
+            let applied = migration
+                .legacy_probe
+                .is_some_and(|probe| tx.prepare(probe).is_ok());
+            if applied {
+                // when exactly isn't known anymore
+                tx.execute(
+                    "INSERT INTO schema_version (name) VALUES (?)",
+                    [migration.name],
+                )?;
+            }
+        }
+    }
+
+    let mut statement = tx.prepare("SELECT name FROM schema_version")?;
+    let applied = statement
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(unknown) = applied
+        .iter()
+        .find(|name| MIGRATIONS.iter().all(|migration| migration.name != *name))
+    {
+        return Err(MigrationError::UnknownMigration(unknown.clone()));
+    }
+
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|migration| !applied.iter().any(|name| name == migration.name))
+        .collect())
+}
+
+/// Lists the migrations the database doesn't have yet, without changing anything.
+pub fn pending(conn: &mut rusqlite::Connection) -> Result<Vec<&'static Migration>, MigrationError> {
+    // dropping the transaction rolls back creating schema_version
+    pending_in(&conn.transaction()?)
+}
+
+/// Applies all pending migrations in one transaction, so either all or none of them are applied.
+/// With `dry_run`, the transaction is rolled back in the end, which checks whether they'd apply
+/// without changing anything. Returns the names of the applied migrations.
+pub fn migrate(
+    conn: &mut rusqlite::Connection,
+    dry_run: bool,
+) -> Result<Vec<&'static str>, MigrationError> {
+    // some migrations rebuild tables, dropping the old one mustn't cascade to other tables. This
+    // can't be changed inside of a transaction.
+    let foreign_keys: bool = conn.query_row("PRAGMA foreign_keys", [], |row| row.get(0))?;
+    conn.execute_batch("PRAGMA foreign_keys = OFF")?;
+    let result = migrate_without_foreign_keys(conn, dry_run);
+    conn.execute_batch(if foreign_keys {
+        "PRAGMA foreign_keys = ON"
+    } else {
+        "PRAGMA foreign_keys = OFF"
+    })?;
+    result
+}
+
+fn migrate_without_foreign_keys(
+    conn: &mut rusqlite::Connection,
+    dry_run: bool,
+) -> Result<Vec<&'static str>, MigrationError> {
+    let tx = conn.transaction()?;
+    let pending = pending_in(&tx)?;
+
+    let now = Utc::now().timestamp();
+    for migration in &pending {
+        tx.execute_batch(migration.sql)
+            .map_err(|source| MigrationError::Failed {
+                name: migration.name,
+                source,
+            })?;
+        tx.execute(
+            "INSERT INTO schema_version (name, applied) VALUES (?, ?)",
+            rusqlite::params![migration.name, now],
+        )?;
+    }
+
+    let violations = tx
+        .prepare("PRAGMA foreign_key_check")?
+        .query_map([], |_| Ok(()))?
+        .count();
+    if violations > 0 {
+        return Err(MigrationError::ForeignKeyViolations(violations));
+    }
+
+    if dry_run {
+        tx.rollback()?;
+    } else {
+        tx.commit()?;
+    }
+    Ok(pending.iter().map(|migration| migration.name).collect())
+}
+
+/// Writes a consistent copy of the database to `path`, which mustn't exist yet.
+pub fn backup(conn: &mut rusqlite::Connection, path: &Path) -> Result<(), rusqlite::Error> {
+    conn.execute("VACUUM INTO ?", [path.to_string_lossy()])?;
+    Ok(())
+}
+
+/// How pending migrations are applied by [`prepare_database`].
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MigrationOptions {
+    /// Only checks whether the pending migrations apply, then rolls them back.
+    pub dry_run: bool,
+    /// Where a copy of the database is stored before applying migrations. Without it, no backup is
+    /// made.
+    pub backup_directory: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum PreparedDatabase {
+    UpToDate,
+    /// The migrations were applied, in this order.
+    Migrated(Vec<&'static str>),
+    /// The migrations would apply, but weren't applied due to [`MigrationOptions::dry_run`].
+    DryRun(Vec<&'static str>),
+}
+
+/// Creates the database if it's empty and applies pending migrations as configured. Afterwards,
+/// settings added since are filled in with their defaults.
+pub fn prepare_database(
+    conn: &mut rusqlite::Connection,
+    options: &MigrationOptions,
+) -> Result<PreparedDatabase, MigrationError> {
+    if let sql_interface::DatabaseStatus::Created = sql_interface::init_db_if_necessary(conn)? {
+        log::info!("Created new database");
+    }
+
+    let pending = pending(conn)?;
+    let prepared = if pending.is_empty() {
+        PreparedDatabase::UpToDate
+    } else {
+        let names: Vec<_> = pending.iter().map(|migration| migration.name).collect();
+        log::info!("Pending migrations: {}", names.join(", "));
+
+        if options.dry_run {
+            return Ok(PreparedDatabase::DryRun(migrate(conn, true)?));
+        }
+
+        if let Some(directory) = &options.backup_directory {
+            fs::create_dir_all(directory)?;
+            let path = directory.join(format!(
+                "bususages-{}.db",
+                Utc::now().format("%Y%m%d-%H%M%S")
+            ));
+            backup(conn, &path)?;
+            log::info!("Backed up database to {}", path.display());
+        }
+
+        PreparedDatabase::Migrated(migrate(conn, false)?)
+    };
+
+    let seeded = settings::seed_defaults(conn)?;
+    if seeded > 0 {
+        log::info!("Added {} new setting(s) with their defaults", seeded);
+    }
+    Ok(prepared)
+}
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS waitlist(
+    id INTEGER,
+    person_id INTEGER NOT NULL,
+    drive_id INTEGER NOT NULL,
+    UNIQUE(person_id, drive_id),
+    FOREIGN KEY (person_id) REFERENCES person(person_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    FOREIGN KEY (drive_id) REFERENCES drive(drive_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    PRIMARY KEY (id AUTOINCREMENT)
+);
@@ -0,0 +1,14 @@
+-- every superuser becomes an admin, which is allowed to do everything a superuser could do before
+CREATE TABLE IF NOT EXISTS role(
+    person_id INTEGER NOT NULL,
+    role TEXT NOT NULL CHECK (role IN ('driver', 'registrar', 'admin')),
+    FOREIGN KEY (person_id) REFERENCES person(person_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    PRIMARY KEY (person_id, role)
+);
+INSERT INTO role (person_id, role)
+    SELECT person_id, 'admin'
+    FROM person
+    WHERE is_superuser;
+ALTER TABLE person DROP COLUMN is_superuser;
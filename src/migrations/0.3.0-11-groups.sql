@@ -0,0 +1,22 @@
+-- persons can be put into groups, drives without any groups stay open to everyone
+CREATE TABLE IF NOT EXISTS person_group(
+    group_id INTEGER,
+    name TEXT NOT NULL,
+    UNIQUE(name),
+    PRIMARY KEY (group_id AUTOINCREMENT)
+);
+ALTER TABLE person ADD COLUMN group_id INTEGER
+    REFERENCES person_group(group_id)
+    ON DELETE SET NULL
+    ON UPDATE CASCADE;
+CREATE TABLE IF NOT EXISTS drive_group(
+    drive_id INTEGER NOT NULL,
+    group_id INTEGER NOT NULL,
+    FOREIGN KEY (drive_id) REFERENCES drive(drive_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    FOREIGN KEY (group_id) REFERENCES person_group(group_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    PRIMARY KEY (drive_id, group_id)
+);
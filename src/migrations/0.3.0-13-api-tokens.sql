@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS api_token(
+    api_token_id INTEGER,
+    person_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    token TEXT NOT NULL,
+    scopes TEXT NOT NULL,
+    created INTEGER NOT NULL,
+    last_used INTEGER,
+    FOREIGN KEY (person_id) REFERENCES person(person_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    PRIMARY KEY (api_token_id AUTOINCREMENT)
+);
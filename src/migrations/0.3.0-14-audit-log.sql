@@ -0,0 +1,31 @@
+-- no foreign keys on purpose, entries stay as they are when persons or drives are deleted
+CREATE TABLE IF NOT EXISTS audit_log(
+    audit_id INTEGER,
+    happened INTEGER NOT NULL,
+    actor_id INTEGER,
+    action TEXT NOT NULL CHECK (action IN (
+        'registration', 'waitlist-promotion', 'attendance',
+        'drive-creation', 'drive-update', 'drive-group-change', 'drive-deletion',
+        'person-creation', 'person-update', 'person-import', 'person-deletion', 'role-change',
+        'group-creation', 'group-deletion', 'group-membership',
+        'session-revocation', 'impersonation-start', 'impersonation-end',
+        'setting-change'
+    )),
+    target_person_id INTEGER,
+    target_drive_id INTEGER,
+    target_group_id INTEGER,
+    target_setting TEXT,
+    before TEXT,
+    after TEXT,
+    PRIMARY KEY (audit_id AUTOINCREMENT)
+);
+CREATE TRIGGER IF NOT EXISTS audit_log_no_update
+BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+CREATE TRIGGER IF NOT EXISTS audit_log_no_delete
+BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
@@ -0,0 +1,2 @@
+-- calendar tokens are hashed with SHA-256 now instead of argon2, old links have to be created anew
+UPDATE person SET calendar_token = NULL WHERE calendar_token LIKE '$argon2%';
@@ -0,0 +1,2 @@
+-- API tokens are hashed with SHA-256 now instead of argon2, old tokens have to be created anew
+DELETE FROM api_token WHERE token LIKE '$argon2%';
@@ -0,0 +1,17 @@
+-- SQLite can't drop the UNIQUE(drivedate) constraint in place, so the table has to be rebuilt.
+-- Foreign keys need to be off for this (the default for SQLite connections), else dropping the
+-- old table would cascade to all registrations.
+CREATE TABLE drive_new(
+    drive_id INTEGER,
+    drivedate DATE NOT NULL,
+    label TEXT NOT NULL DEFAULT '',
+    deadline DATETIME,
+    registration_cap INTEGER,
+    UNIQUE(drivedate, label),
+    PRIMARY KEY (drive_id AUTOINCREMENT)
+);
+INSERT INTO drive_new (drive_id, drivedate, label, deadline, registration_cap)
+SELECT drive_id, drivedate, '', deadline, registration_cap
+FROM drive;
+DROP TABLE drive;
+ALTER TABLE drive_new RENAME TO drive;
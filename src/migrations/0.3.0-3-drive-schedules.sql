@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS drive_schedule(
+    schedule_id INTEGER,
+    weekday INTEGER NOT NULL,
+    label TEXT NOT NULL DEFAULT '',
+    generated_until DATE,
+    PRIMARY KEY (schedule_id AUTOINCREMENT)
+);
+CREATE TABLE IF NOT EXISTS schedule_exception(
+    exception_id INTEGER,
+    first_date DATE NOT NULL,
+    last_date DATE NOT NULL,
+    note TEXT NOT NULL DEFAULT '',
+    PRIMARY KEY (exception_id AUTOINCREMENT)
+);
@@ -0,0 +1,2 @@
+ALTER TABLE registration
+ADD COLUMN attended BOOLEAN DEFAULT null;
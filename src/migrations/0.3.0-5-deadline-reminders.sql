@@ -0,0 +1,21 @@
+ALTER TABLE person
+ADD COLUMN reminders TEXT NOT NULL DEFAULT 'unregistered'
+    CHECK (reminders IN ('never', 'unregistered', 'always'));
+
+ALTER TABLE drive
+ADD COLUMN reminder_sent BOOLEAN NOT NULL DEFAULT false;
+
+CREATE TABLE IF NOT EXISTS quick_registration(
+    quick_registration_id INTEGER,
+    person_id INTEGER NOT NULL,
+    drive_id INTEGER NOT NULL,
+    token TEXT NOT NULL,
+    token_expiration INTEGER NOT NULL,
+    FOREIGN KEY (person_id) REFERENCES person(person_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    FOREIGN KEY (drive_id) REFERENCES drive(drive_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    PRIMARY KEY (quick_registration_id AUTOINCREMENT)
+);
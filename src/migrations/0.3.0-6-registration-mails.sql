@@ -0,0 +1,2 @@
+ALTER TABLE person
+ADD COLUMN registration_mails BOOLEAN NOT NULL DEFAULT true;
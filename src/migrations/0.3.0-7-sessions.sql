@@ -0,0 +1,12 @@
+-- JWTs issued before this migration don't refer to a session and are invalid from now on, so
+-- everyone has to log in again once
+CREATE TABLE IF NOT EXISTS session(
+    session_id INTEGER,
+    person_id INTEGER NOT NULL,
+    created INTEGER NOT NULL,
+    expiration INTEGER NOT NULL,
+    FOREIGN KEY (person_id) REFERENCES person(person_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    PRIMARY KEY (session_id AUTOINCREMENT)
+);
@@ -0,0 +1,3 @@
+ALTER TABLE person ADD COLUMN login_code TEXT;
+ALTER TABLE person ADD COLUMN login_code_expiration INTEGER;
+ALTER TABLE person ADD COLUMN login_code_attempts INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+ALTER TABLE session ADD COLUMN impersonator_id INTEGER
+    REFERENCES person(person_id)
+        ON DELETE CASCADE
+        ON UPDATE CASCADE;
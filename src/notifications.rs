@@ -0,0 +1,181 @@
+use {
+    super::{
+        format_labeled_date,
+        mail::{MailError, Mailer},
+        sql_interface::{self, Drive, Person, SearchPersonBy},
+        BususagesDBConn,
+    },
+    rocket_sync_db_pools::rusqlite,
+    thiserror::Error,
+};
+
+/// What happened to a registration, so the person can be told about it.
+#[derive(Debug, Clone, Copy)]
+pub enum RegistrationChange {
+    /// The person registered themselves.
+    Registered,
+    /// The person unregistered themselves.
+    Unregistered,
+    /// A superuser registered the person.
+    RegisteredBySuperuser,
+    /// A superuser unregistered the person.
+    UnregisteredBySuperuser,
+    /// A seat became free and the person was the first one on the waitlist.
+    PromotedFromWaitlist,
+}
+
+impl RegistrationChange {
+    /// Which change happened, depending on the new state and on who did it.
+    pub fn new(registered: bool, by_superuser: bool) -> Self {
+        match (registered, by_superuser) {
+            (true, false) => Self::Registered,
+            (false, false) => Self::Unregistered,
+            (true, true) => Self::RegisteredBySuperuser,
+            (false, true) => Self::UnregisteredBySuperuser,
+        }
+    }
+
+    fn subject(self) -> &'static str {
+        match self {
+            Self::Registered | Self::RegisteredBySuperuser => "Angemeldet",
+            Self::Unregistered | Self::UnregisteredBySuperuser => "Abgemeldet",
+            Self::PromotedFromWaitlist => "Platz frei geworden",
+        }
+    }
+
+    fn sentence(self, pretty_date: &str) -> String {
+        match self {
+            Self::Registered => format!(
+                "du bist jetzt für die Busfahrt am {} angemeldet.",
+                pretty_date
+            ),
+            Self::Unregistered => format!(
+                "du bist jetzt von der Busfahrt am {} abgemeldet.",
+                pretty_date
+            ),
+            Self::RegisteredBySuperuser => format!(
+                "ein Superuser hat dich für die Busfahrt am {} angemeldet.",
+                pretty_date
+            ),
+            Self::UnregisteredBySuperuser => format!(
+                "ein Superuser hat dich von der Busfahrt am {} abgemeldet.",
+                pretty_date
+            ),
+            Self::PromotedFromWaitlist => format!(
+                "für die Busfahrt am {} ist ein Platz frei geworden. Da du als Nächstes auf der
+Warteliste standest, bist du jetzt angemeldet.",
+                pretty_date
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+enum NotifyError {
+    #[error("Database error: {0}")]
+    RusqliteError(#[from] rusqlite::Error),
+    #[error("Error while searching person: {0}")]
+    SearchPersonError(#[from] sql_interface::SearchPersonError),
+    #[error("Error while sending mail: {0}")]
+    MailError(#[from] MailError),
+}
+
+/// Mails the person about the changed registration, unless they opted out of that. Failures are
+/// only logged, as the registration itself went through already.
+pub async fn notify_registration_change(
+    conn: &BususagesDBConn,
+    mailer: &Mailer,
+    person_id: i64,
+    drive_id: i64,
+    change: RegistrationChange,
+) {
+    if let Err(err) = try_notify(conn, mailer, person_id, drive_id, change).await {
+        log::error!(
+            "Error while notifying {} about {:?} on drive {}: {}",
+            person_id,
+            change,
+            drive_id,
+            err
+        );
+    }
+}
+
+/// Shorthand for notifying all persons registered from the waitlist after a change.
+pub async fn notify_promoted(
+    conn: &BususagesDBConn,
+    mailer: &Mailer,
+    drive_id: i64,
+    promoted: Vec<i64>,
+) {
+    for person_id in promoted {
+        notify_registration_change(
+            conn,
+            mailer,
+            person_id,
+            drive_id,
+            RegistrationChange::PromotedFromWaitlist,
+        )
+        .await;
+    }
+}
+
+async fn try_notify(
+    conn: &BususagesDBConn,
+    mailer: &Mailer,
+    person_id: i64,
+    drive_id: i64,
+    change: RegistrationChange,
+) -> Result<(), NotifyError> {
+    let wanted = conn
+        .run(move |c| sql_interface::wants_registration_mails(c, person_id))
+        .await?;
+    if !wanted {
+        return Ok(());
+    }
+
+    let person = conn
+        .run(move |c| sql_interface::search_person(c, &SearchPersonBy::Id(person_id)))
+        .await?;
+    let Some(drive) = conn
+        .run(move |c| sql_interface::get_drive(c, drive_id))
+        .await?
+    else {
+        // deleted in the meantime, nothing left to tell
+        return Ok(());
+    };
+    send_registration_change(mailer, &person, &drive, change).await?;
+    Ok(())
+}
+
+/// Writes the mail about the changed registration, regardless of whether the person wants it.
+pub async fn send_registration_change(
+    mailer: &Mailer,
+    person: &Person,
+    drive: &Drive,
+    change: RegistrationChange,
+) -> Result<(), MailError> {
+    let pretty_date = format_labeled_date(drive.date, &drive.label);
+
+    mailer
+        .send(
+            person.email.clone(),
+            format!(
+                "[Komplett normale Busliste] {}: {}",
+                change.subject(),
+                pretty_date
+            ),
+            format!(
+                r#"Hallo {},
+
+{}
+
+Diese Emails kannst du auf deinem Dashboard abbestellen.
+
+Mit freundlichen Grüßen,
+Komplett normale Busliste"#,
+                person.prename,
+                change.sentence(&pretty_date),
+            ),
+        )
+        .await
+}
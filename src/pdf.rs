@@ -0,0 +1,216 @@
+use {
+    super::sql_interface::Registration,
+    printpdf::{BuiltinFont, IndirectFontRef, Line, Mm, PdfDocument, PdfLayerReference, Point},
+};
+
+// all in millimeters, for A4 in portrait
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 15.0;
+const ROW_HEIGHT: f32 = 8.0;
+const FONT_SIZE: f32 = 10.0;
+
+/// Roughly the average width of a Helvetica character relative to the font size in millimeters.
+/// Builtin fonts can't be measured exactly, so cells are cut a bit early rather than too late.
+const CHAR_WIDTH_FACTOR: f32 = 0.2;
+
+struct Column {
+    title: &'static str,
+    width: f32,
+}
+
+/// Renders the printable list of a drive as PDF, so it looks the same no matter which browser
+/// prints it. Every page repeats the column headers and gets a page number, the registrations are
+/// printed in the order they're given.
+pub fn drive_list(
+    pretty_date: &str,
+    generated: &str,
+    registrations: &[Registration],
+) -> Result<Vec<u8>, printpdf::Error> {
+    let title = format!("Busanmeldungen für den {}", pretty_date);
+    let (doc, page, layer) = PdfDocument::new(&title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Liste");
+    let font = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+    let bold = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
+
+    let with_groups = registrations.iter().any(|r| r.group.is_some());
+    let mut columns = vec![
+        Column {
+            title: "Vorname",
+            width: 35.0,
+        },
+        Column {
+            title: "Nachname",
+            width: 40.0,
+        },
+        Column {
+            title: "Angemeldet",
+            width: 22.0,
+        },
+        Column {
+            title: "Warteliste",
+            width: 20.0,
+        },
+        Column {
+            title: "Anwesend",
+            width: 20.0,
+        },
+        Column {
+            title: "Unterschrift",
+            width: 43.0,
+        },
+    ];
+    if with_groups {
+        columns[5].width -= 20.0;
+        columns.insert(
+            2,
+            Column {
+                title: "Gruppe",
+                width: 20.0,
+            },
+        );
+    }
+
+    let rows = registrations.iter().map(|registration| {
+        let mut cells = vec![
+            registration.person.prename.clone(),
+            registration.person.name.clone(),
+            if registration.registered {
+                "Ja"
+            } else {
+                "Nein"
+            }
+            .to_string(),
+            registration
+                .waitlist_position
+                .map(|pos| format!("Platz {}", pos))
+                .unwrap_or_default(),
+            match registration.attended {
+                Some(true) => "Ja",
+                Some(false) => "Nein",
+                None => "",
+            }
+            .to_string(),
+            String::new(),
+        ];
+        if with_groups {
+            cells.insert(2, registration.group.clone().unwrap_or_default());
+        }
+        cells
+    });
+
+    let first_layer = doc.get_page(page).get_layer(layer);
+    first_layer.use_text(
+        &title,
+        16.0,
+        Mm(MARGIN),
+        Mm(PAGE_HEIGHT - MARGIN - 6.0),
+        &bold,
+    );
+    first_layer.use_text(
+        format!("Generiert um {} UTC", generated),
+        9.0,
+        Mm(MARGIN),
+        Mm(PAGE_HEIGHT - MARGIN - 12.0),
+        &font,
+    );
+
+    let mut layers = vec![first_layer];
+    let mut y = PAGE_HEIGHT - MARGIN - 18.0;
+    draw_row(
+        &layers[0],
+        &columns,
+        y,
+        columns.iter().map(|c| c.title),
+        &bold,
+    );
+    y -= ROW_HEIGHT;
+
+    for row in rows {
+        // the bottom margin keeps room for the page number
+        if y - ROW_HEIGHT < MARGIN {
+            let (page, layer) = doc.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Liste");
+            layers.push(doc.get_page(page).get_layer(layer));
+            y = PAGE_HEIGHT - MARGIN;
+            draw_row(
+                layers.last().unwrap(),
+                &columns,
+                y,
+                columns.iter().map(|c| c.title),
+                &bold,
+            );
+            y -= ROW_HEIGHT;
+        }
+        draw_row(
+            layers.last().unwrap(),
+            &columns,
+            y,
+            row.iter().map(String::as_str),
+            &font,
+        );
+        y -= ROW_HEIGHT;
+    }
+
+    let page_count = layers.len();
+    for (i, layer) in layers.iter().enumerate() {
+        layer.use_text(
+            format!("Seite {} von {}", i + 1, page_count),
+            8.0,
+            Mm(MARGIN),
+            Mm(MARGIN - 7.0),
+            &font,
+        );
+    }
+
+    doc.save_to_bytes()
+}
+
+/// Draws one row of the table with its top edge at `y`, including the borders of all cells.
+fn draw_row<'a>(
+    layer: &PdfLayerReference,
+    columns: &[Column],
+    y: f32,
+    cells: impl Iterator<Item = &'a str>,
+    font: &IndirectFontRef,
+) {
+    let bottom = y - ROW_HEIGHT;
+    let table_width: f32 = columns.iter().map(|c| c.width).sum();
+    layer.set_outline_thickness(0.3);
+    line(layer, (MARGIN, y), (MARGIN + table_width, y));
+    line(layer, (MARGIN, bottom), (MARGIN + table_width, bottom));
+
+    let mut x = MARGIN;
+    line(layer, (x, y), (x, bottom));
+    for (column, cell) in columns.iter().zip(cells) {
+        layer.use_text(
+            fit(cell, column.width - 3.0),
+            FONT_SIZE,
+            Mm(x + 1.5),
+            Mm(bottom + 2.7),
+            font,
+        );
+        x += column.width;
+        line(layer, (x, y), (x, bottom));
+    }
+}
+
+fn line(layer: &PdfLayerReference, from: (f32, f32), to: (f32, f32)) {
+    layer.add_line(Line {
+        points: vec![
+            (Point::new(Mm(from.0), Mm(from.1)), false),
+            (Point::new(Mm(to.0), Mm(to.1)), false),
+        ],
+        is_closed: false,
+    });
+}
+
+/// Cuts the text so it fits into the given width, marking it with an ellipsis if it had to.
+fn fit(text: &str, width: f32) -> String {
+    let max_chars = (width / (FONT_SIZE * CHAR_WIDTH_FACTOR)) as usize;
+    if text.chars().count() <= max_chars {
+        text.to_string()
+    } else {
+        let mut cut: String = text.chars().take(max_chars.saturating_sub(1)).collect();
+        cut.push('…');
+        cut
+    }
+}
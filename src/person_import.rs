@@ -0,0 +1,218 @@
+use {
+    super::{
+        audit::{self, AuditAction, AuditTarget},
+        sql_interface::{self, NewPerson, Person, PersonCreationError, UpdatePerson},
+    },
+    lettre::Address,
+    rocket_sync_db_pools::rusqlite,
+    serde::Serialize,
+    serde_json::json,
+    std::collections::HashMap,
+    thiserror::Error,
+};
+
+/// One person as read from the CSV file.
+#[derive(Clone, Debug, Serialize)]
+pub struct ImportRow {
+    /// The line in the CSV file, starting at 1.
+    pub line: u64,
+    pub prename: String,
+    pub name: String,
+    pub email: Address,
+}
+
+/// A person whose email is known already, but whose name differs.
+#[derive(Debug, Serialize)]
+pub struct ImportUpdate {
+    pub old: Person,
+    pub new: ImportRow,
+}
+
+/// A line which can't be imported, together with the reason.
+#[derive(Debug, Serialize)]
+pub struct ImportConflict {
+    pub line: u64,
+    pub content: String,
+    pub reason: String,
+}
+
+/// What importing a CSV file does to the persons in the database.
+#[derive(Debug, Default, Serialize)]
+pub struct ImportPlan {
+    pub inserts: Vec<ImportRow>,
+    pub updates: Vec<ImportUpdate>,
+    /// How many persons are in the database exactly like in the file already.
+    pub unchanged: usize,
+    /// Lines which are skipped, they don't prevent the rest from being imported.
+    pub conflicts: Vec<ImportConflict>,
+}
+
+impl ImportPlan {
+    /// Whether applying the plan would change anything at all.
+    pub fn has_changes(&self) -> bool {
+        !self.inserts.is_empty() || !self.updates.is_empty()
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ImportError {
+    #[error("Error while inserting person: {0}")]
+    PersonCreationError(#[from] PersonCreationError),
+    #[error("Database or query error: {0}")]
+    RusqliteError(#[from] rusqlite::Error),
+}
+
+/// Parses a CSV file with the columns last name, first name and email, just like
+/// `scripts/csv_to_sqlite.py` takes it. A header line is skipped if its third column is `email`.
+pub fn parse_csv(source: &str) -> (Vec<ImportRow>, Vec<ImportConflict>) {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(source.as_bytes());
+
+    let mut rows = Vec::new();
+    let mut conflicts = Vec::new();
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                conflicts.push(ImportConflict {
+                    line: err.position().map_or(0, |pos| pos.line()),
+                    content: String::new(),
+                    reason: format!("Unreadable line: {}", err),
+                });
+                continue;
+            }
+        };
+        let line = record.position().map_or(0, |pos| pos.line());
+        let mut conflict = |reason: &str| {
+            conflicts.push(ImportConflict {
+                line,
+                content: record.iter().collect::<Vec<_>>().join(", "),
+                reason: reason.to_string(),
+            })
+        };
+
+        if record.len() != 3 {
+            conflict("Expected exactly three columns: last name, first name and email");
+            continue;
+        }
+        if line == 1 && record[2].eq_ignore_ascii_case("email") {
+            continue;
+        }
+        if record[0].is_empty() || record[1].is_empty() {
+            conflict("Names can't be empty");
+            continue;
+        }
+        let Ok(email) = record[2].parse::<Address>() else {
+            conflict("Invalid email");
+            continue;
+        };
+
+        rows.push(ImportRow {
+            line,
+            prename: record[1].to_string(),
+            name: record[0].to_string(),
+            email,
+        });
+    }
+
+    (rows, conflicts)
+}
+
+/// Compares the persons in the CSV file against the ones in the database by email, without
+/// changing anything. Persons missing in the file are left alone.
+pub fn plan_import(
+    conn: &rusqlite::Connection,
+    source: &str,
+) -> Result<ImportPlan, rusqlite::Error> {
+    let (rows, conflicts) = parse_csv(source);
+    let mut plan = ImportPlan {
+        conflicts,
+        ..ImportPlan::default()
+    };
+
+    let mut existing: HashMap<String, Person> =
+        sql_interface::list_all_persons(conn, sql_interface::VisibilityFilter::IncludingInvisible)?
+            .into_iter()
+            .map(|person| (person.email.to_string(), person))
+            .collect();
+    let mut seen: HashMap<String, u64> = HashMap::new();
+
+    for row in rows {
+        let email = row.email.to_string();
+        if let Some(first_line) = seen.insert(email.clone(), row.line) {
+            plan.conflicts.push(ImportConflict {
+                line: row.line,
+                content: email,
+                reason: format!("Email appears in line {} already", first_line),
+            });
+            continue;
+        }
+
+        match existing.remove(&email) {
+            Some(old) if old.prename == row.prename && old.name == row.name => plan.unchanged += 1,
+            Some(old) => plan.updates.push(ImportUpdate { old, new: row }),
+            None => plan.inserts.push(row),
+        }
+    }
+
+    plan.conflicts.sort_by_key(|conflict| conflict.line);
+    Ok(plan)
+}
+
+/// Plans the import again and applies it inside of a transaction, so either all persons are
+/// inserted and updated or none. Each inserted person and the import as a whole are recorded in the
+/// audit log as made by `actor_id`.
+pub fn apply_import(
+    conn: &mut rusqlite::Connection,
+    source: &str,
+    actor_id: Option<i64>,
+) -> Result<ImportPlan, ImportError> {
+    let transaction = conn.transaction()?;
+    let plan = plan_import(&transaction, source)?;
+    let groups = sql_interface::list_person_groups(&transaction)?;
+
+    let mut inserted = Vec::new();
+    for row in &plan.inserts {
+        inserted.push(sql_interface::insert_new_person(
+            &transaction,
+            &NewPerson {
+                prename: row.prename.clone(),
+                name: row.name.clone(),
+                email: row.email.clone(),
+            },
+            actor_id,
+        )?);
+    }
+    for ImportUpdate { old, new } in &plan.updates {
+        sql_interface::update_person(
+            &transaction,
+            &UpdatePerson {
+                id: old.id,
+                prename: new.prename.clone(),
+                name: new.name.clone(),
+                email: new.email.clone(),
+                is_visible: old.is_visible,
+                group_id: groups.get(&old.id).copied(),
+            },
+            actor_id,
+        )?;
+    }
+
+    if !inserted.is_empty() || !plan.updates.is_empty() {
+        let updated: Vec<_> = plan.updates.iter().map(|update| update.old.id).collect();
+        audit::record(
+            &transaction,
+            actor_id,
+            AuditAction::PersonImport,
+            AuditTarget::Nothing,
+            serde_json::Value::Null,
+            json!({ "inserted": inserted, "updated": updated }),
+        )?;
+    }
+
+    transaction.commit()?;
+    Ok(plan)
+}
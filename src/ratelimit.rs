@@ -0,0 +1,84 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    hash::Hash,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Returned when a key used up all of its attempts, until the oldest one leaves the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lockout {
+    pub retry_after: Duration,
+}
+
+impl fmt::Display for Lockout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // rounded up, "in 0 Minuten" would be a lie
+        let minutes = self.retry_after.as_secs().div_ceil(60);
+        write!(
+            f,
+            "Zu viele Anmeldeversuche. Bitte versuche es in {} Minute(n) erneut.",
+            minutes.max(1)
+        )
+    }
+}
+
+/// Allows each key at most `limit` attempts within a sliding window. Only held in memory, so a
+/// restart resets all limits, which is fine for throttling login attempts.
+pub struct RateLimiter<K> {
+    limit: u32,
+    window: Duration,
+    attempts: Mutex<HashMap<K, VecDeque<Instant>>>,
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            attempts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Uses up an attempt of the key at `now`, if it has one left. Checking and recording happen
+    /// under the same lock, so concurrent requests can't all slip through before any is recorded.
+    /// Also drops all keys without recent attempts, so the map doesn't grow forever.
+    pub fn attempt(&self, key: K, now: Instant) -> Result<(), Lockout> {
+        let mut attempts = self.attempts.lock().unwrap();
+        attempts.retain(|_, recent| {
+            self.forget_old(recent, now);
+            !recent.is_empty()
+        });
+
+        let recent = attempts.entry(key).or_default();
+        if recent.len() >= self.limit as usize {
+            return Err(Lockout {
+                retry_after: recent.front().map_or(self.window, |&oldest| {
+                    self.window.saturating_sub(now.duration_since(oldest))
+                }),
+            });
+        }
+        recent.push_back(now);
+        Ok(())
+    }
+
+    /// Takes back the attempt the key made at `at`, for attempts which turned out not to count.
+    pub fn undo(&self, key: &K, at: Instant) {
+        let mut attempts = self.attempts.lock().unwrap();
+        if let Some(recent) = attempts.get_mut(key) {
+            if let Some(position) = recent.iter().rposition(|&attempt| attempt == at) {
+                recent.remove(position);
+            }
+        }
+    }
+
+    fn forget_old(&self, recent: &mut VecDeque<Instant>, now: Instant) {
+        while let Some(&oldest) = recent.front() {
+            if now.duration_since(oldest) < self.window {
+                break;
+            }
+            recent.pop_front();
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! The instance-specific options in the `settings` table, each described once here. The
+//! descriptions drive validating new values, the form on the settings page and which defaults a
+//! database starts with.
+
+use {
+    rocket_sync_db_pools::rusqlite,
+    rusqlite::{
+        named_params,
+        types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, Type, Value, ValueRef},
+        ToSql,
+    },
+    thiserror::Error,
+};
+
+/// What kind of values a setting holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SettingType {
+    /// Any text, including an empty one.
+    Text,
+    /// A whole number within the bounds, both inclusive.
+    Integer { min: i64, max: i64 },
+    /// A weekday with Monday being 0, or none at all.
+    OptionalWeekday,
+}
+
+/// A setting in the registry.
+#[derive(Debug)]
+pub struct Setting {
+    /// The key in the `settings` table.
+    pub name: &'static str,
+    pub title: &'static str,
+    pub description: &'static str,
+    pub kind: SettingType,
+    /// The value a new database starts with, written as it'd be entered on the settings page.
+    pub default: &'static str,
+    /// Whether the setting can be changed on the settings page. Hidden ones can only be changed in
+    /// the database itself.
+    pub user_visible: bool,
+}
+
+pub const LOGIN_MESSAGE: Setting = Setting {
+    name: "login-message",
+    title: "Login message",
+    description: "Which custom message to display on the login front page.",
+    kind: SettingType::Text,
+    default: "Falls du nicht reinkommen solltest, du hast dich vermutlich vertippt oder die falsche Email angegeben.",
+    user_visible: true,
+};
+
+pub const DEFAULT_DEADLINE: Setting = Setting {
+    name: "default-deadline",
+    title: "Default deadline",
+    description: "At the end of what weekday the default deadline is on. None means there is no \"regular\" deadline, registrations are possible until the day of the drive.",
+    kind: SettingType::OptionalWeekday,
+    default: "2",
+    user_visible: true,
+};
+
+pub const DEFAULT_REGISTRATION_CAP: Setting = Setting {
+    name: "default-registration-cap",
+    title: "Default registration cap",
+    description: "With how many registrations already added to a drive it becomes impossible to add any more. Bypassed if a superuser (you) changes the registration of a person.",
+    kind: SettingType::Integer {
+        min: 0,
+        max: u32::MAX as i64,
+    },
+    default: "50",
+    user_visible: true,
+};
+
+pub const SCHEDULE_WEEKS_AHEAD: Setting = Setting {
+    name: "schedule-weeks-ahead",
+    title: "Schedule look-ahead",
+    description: "For how many weeks into the future drives are generated from the recurring drive schedules, see the drives panel.",
+    kind: SettingType::Integer { min: 1, max: 52 },
+    default: "4",
+    user_visible: true,
+};
+
+pub const REMINDER_HOURS: Setting = Setting {
+    name: "reminder-hours",
+    title: "Deadline reminders",
+    description: "How many hours before a drive's deadline persons get a reminder mail, depending on their own preference. 0 disables reminders.",
+    kind: SettingType::Integer { min: 0, max: 336 },
+    default: "24",
+    user_visible: true,
+};
+
+/// All settings, in the order they're shown on the settings page. New settings only need to be
+/// added here, they're put into existing databases at startup.
+pub const SETTINGS: &[Setting] = &[
+    LOGIN_MESSAGE,
+    DEFAULT_DEADLINE,
+    DEFAULT_REGISTRATION_CAP,
+    SCHEDULE_WEEKS_AHEAD,
+    REMINDER_HOURS,
+];
+
+/// A value of a setting, as stored in the database.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SettingValue {
+    None,
+    Integer(i64),
+    Text(String),
+}
+
+impl ToSql for SettingValue {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            Self::None => ToSqlOutput::Owned(Value::Null),
+            Self::Integer(number) => ToSqlOutput::Owned(Value::Integer(*number)),
+            Self::Text(text) => ToSqlOutput::Borrowed(ValueRef::Text(text.as_bytes())),
+        })
+    }
+}
+
+impl FromSql for SettingValue {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Null => Ok(Self::None),
+            ValueRef::Integer(number) => Ok(Self::Integer(number)),
+            ValueRef::Text(_) => Ok(Self::Text(String::column_result(value)?)),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum InvalidSetting {
+    #[error("Das ist keine ganze Zahl.")]
+    NotAnInteger,
+    #[error("Die Zahl muss zwischen {min} und {max} liegen.")]
+    OutOfBounds { min: i64, max: i64 },
+    #[error("Das ist kein Wochentag.")]
+    NotAWeekday,
+}
+
+impl Setting {
+    /// Checks a value as entered on the settings page and converts it into what's stored.
+    pub fn parse(&self, input: &str) -> Result<SettingValue, InvalidSetting> {
+        match self.kind {
+            SettingType::Text => Ok(SettingValue::Text(input.to_string())),
+            SettingType::Integer { min, max } => {
+                let number = input
+                    .trim()
+                    .parse::<i64>()
+                    .map_err(|_| InvalidSetting::NotAnInteger)?;
+                if (min..=max).contains(&number) {
+                    Ok(SettingValue::Integer(number))
+                } else {
+                    Err(InvalidSetting::OutOfBounds { min, max })
+                }
+            }
+            SettingType::OptionalWeekday => match input.trim() {
+                "" => Ok(SettingValue::None),
+                day => match day.parse() {
+                    Ok(day @ 0..=6) => Ok(SettingValue::Integer(day)),
+                    _ => Err(InvalidSetting::NotAWeekday),
+                },
+            },
+        }
+    }
+
+    /// Reads the setting as a number, [`Option::None`] if it's unset.
+    pub fn get_integer(&self, conn: &rusqlite::Connection) -> Result<Option<i64>, rusqlite::Error> {
+        match self.get(conn)? {
+            SettingValue::Integer(number) => Ok(Some(number)),
+            SettingValue::None => Ok(None),
+            SettingValue::Text(_) => Err(rusqlite::Error::InvalidColumnType(
+                0,
+                self.name.to_string(),
+                Type::Text,
+            )),
+        }
+    }
+
+    /// Reads the setting as text, numbers are formatted.
+    pub fn get_text(&self, conn: &rusqlite::Connection) -> Result<String, rusqlite::Error> {
+        Ok(match self.get(conn)? {
+            SettingValue::Text(text) => text,
+            SettingValue::Integer(number) => number.to_string(),
+            SettingValue::None => String::new(),
+        })
+    }
+
+    fn get(&self, conn: &rusqlite::Connection) -> Result<SettingValue, rusqlite::Error> {
+        conn.query_row(
+            "SELECT value
+            FROM settings
+            WHERE name == :name",
+            named_params! { ":name": self.name },
+            |row| row.get(0),
+        )
+    }
+}
+
+/// Inserts the default of every setting the database doesn't have yet. Returns how many were
+/// inserted.
+pub fn seed_defaults(conn: &rusqlite::Connection) -> Result<usize, rusqlite::Error> {
+    let mut statement = conn.prepare(
+        "INSERT OR IGNORE INTO settings (name, value)
+        VALUES (:name, :value)",
+    )?;
+    let mut inserted = 0;
+    for setting in SETTINGS {
+        let value = setting
+            .parse(setting.default)
+            .unwrap_or_else(|err| panic!("invalid default for '{}': {}", setting.name, err));
+        inserted += statement.execute(named_params! {
+            ":name": setting.name,
+            ":value": value,
+        })?;
+    }
+    Ok(inserted)
+}
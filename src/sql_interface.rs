@@ -1,10 +1,14 @@
 use {
-    super::relative_to_absolute,
-    chrono::Utc,
+    super::{
+        audit::{self, AuditAction, AuditTarget},
+        figure_out_exact_deadline, relative_to_absolute, settings,
+    },
+    chrono::{Datelike, Utc},
     lettre::Address,
     rocket_sync_db_pools::rusqlite,
-    rusqlite::{named_params, types::Type, types::Value, ToSql},
+    rusqlite::{named_params, types::Type, types::Value, OptionalExtension, ToSql},
     serde::{Deserialize, Serialize},
+    serde_json::json,
     std::{collections::BTreeMap, fmt, time::Duration},
     thiserror::Error,
 };
@@ -41,12 +45,12 @@ pub struct Person {
     /// accepted anymore.
     pub token_expiration: Option<i64>,
 
-    /// If the person has elevated previliges, like being allowed to see registration entries and
+    /// Which elevated privileges the person has, like being allowed to see registration entries or
     /// create new drive dates.
     ///
-    /// Automatically set to false if it is not needed for the current query, like querying
-    /// registrations themselves.
-    pub is_superuser: bool,
+    /// Automatically empty if it is not needed for the current query, like querying registrations
+    /// themselves.
+    pub roles: Vec<Role>,
 
     /// Whether or not the person shows up in the registration list. They can log in regardless of this.
     pub is_visible: bool,
@@ -65,7 +69,7 @@ fn row_to_full_person(row: &rusqlite::Row) -> rusqlite::Result<Person> {
             .expect("Invalid email in database!"),
         token: row.get(4)?,
         token_expiration: row.get(5)?,
-        is_superuser: row.get(6)?,
+        roles: parse_roles(row.get(6)?),
         is_visible: row.get(7)?,
     })
 }
@@ -83,16 +87,110 @@ fn row_to_person(row: &rusqlite::Row) -> rusqlite::Result<Person> {
             .expect("Invalid email in database!"),
         token: None,
         token_expiration: None,
-        is_superuser: false,
+        roles: Vec::new(),
         is_visible: row.get(4)?,
     })
 }
 
+/// What a person is allowed to do apart from registering themselves. A person can have any number
+/// of roles, an admin can do everything the other roles can.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Sees the lists of registered persons and marks their attendance.
+    Driver,
+    /// Registers and unregisters other persons.
+    Registrar,
+    /// Manages persons, drives and settings.
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Driver, Role::Registrar, Role::Admin];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Driver => "driver",
+            Self::Registrar => "registrar",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "driver" => Ok(Self::Driver),
+            "registrar" => Ok(Self::Registrar),
+            "admin" => Ok(Self::Admin),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Parses the comma-separated output of `group_concat(role)`. Unknown roles can't be in the
+/// database due to the check constraint, so they're just skipped.
+fn parse_roles(concatenated: Option<String>) -> Vec<Role> {
+    let mut roles: Vec<Role> = concatenated
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|role| role.parse().ok())
+        .collect();
+    roles.sort();
+    roles
+}
+
+/// What a personal API token may be used for in the JSON API, see `SCOPE_ROUTES` in `authflow`.
+/// Unlike roles, scopes only ever restrict, the person owning the token still needs the roles for
+/// whatever the token is used on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Reading one's own data, registrations and the drives, but not the person list or exports.
+    Read,
+    /// Registering and unregistering oneself, including the waitlist.
+    Register,
+    /// Creating drives.
+    Drives,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [Scope::Read, Scope::Register, Scope::Drives];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Register => "register",
+            Self::Drives => "drives",
+        }
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "read" => Ok(Self::Read),
+            "register" => Ok(Self::Register),
+            "drives" => Ok(Self::Drives),
+            _ => Err(()),
+        }
+    }
+}
+
 /// A drive a user can register for and a registration then refers to.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Drive {
     pub id: i64,
     pub date: chrono::NaiveDate,
+
+    /// Distinguishes multiple drives on the same date, like the outbound and the return leg. Empty
+    /// if there's nothing to distinguish.
+    pub label: String,
+
     pub deadline: Option<chrono::NaiveDateTime>,
     pub registration_cap: Option<u32>,
     pub already_registered_count: u32,
@@ -111,12 +209,22 @@ pub struct Registration {
     /// Whether or not this registration denotes that the person drives. False means that
     /// this person doesn't drive on that day.
     pub registered: bool,
+
+    /// The position of the person on the waitlist of the drive, starting at 1. [`Option::None`]
+    /// if the person isn't waiting for a free seat.
+    pub waitlist_position: Option<u32>,
+
+    /// Whether the person actually was on the bus. [`Option::None`] if nobody checked yet.
+    pub attended: Option<bool>,
+
+    /// The name of the group the person is in, if any.
+    pub group: Option<String>,
 }
 
 /// Parameters needed to update a specific registration.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RegistrationUpdate {
-    pub date: chrono::NaiveDate,
+    pub drive_id: i64,
     pub person_id: i64,
     pub registered: bool,
 }
@@ -136,7 +244,6 @@ pub enum DatabaseStatus {
     Created,
 }
 
-#[allow(unused)]
 pub fn init_db_if_necessary(
     conn: &mut rusqlite::Connection,
 ) -> Result<DatabaseStatus, rusqlite::Error> {
@@ -153,12 +260,33 @@ pub fn init_db_if_necessary(
         .is_err()
     {
         conn.execute_batch(include_str!("./init_db.sql"))?;
+        settings::seed_defaults(conn)?;
         Ok(DatabaseStatus::Created)
     } else {
         Ok(DatabaseStatus::AlreadyExistent)
     }
 }
 
+/// SQL condition whether `drive` is meant for `person`, which is the case if the drive isn't
+/// restricted to any groups or the person is in one of them. Persons who registered, attended or
+/// wait for a seat anyways are always included, so nobody vanishes from a list when a restriction
+/// changes.
+const DRIVE_MEANT_FOR_PERSON: &str = "(
+    NOT EXISTS (
+        SELECT group_id
+        FROM drive_group
+        WHERE drive_group.drive_id == drive.drive_id
+    )
+    OR person.group_id IN (
+        SELECT group_id
+        FROM drive_group
+        WHERE drive_group.drive_id == drive.drive_id
+    )
+    OR registration.registered
+    OR registration.attended
+    OR waitlist.id IS NOT NULL
+)";
+
 #[derive(Copy, Clone, Debug)]
 pub enum DriveFilter {
     OnlyPast,
@@ -167,72 +295,103 @@ pub enum DriveFilter {
 }
 
 pub enum SearchRegistrationsBy {
-    /// Searches the registrations by date. All persons are included, regardless of whether they
-    /// registered or not.
-    Date(chrono::NaiveDate),
+    /// Searches the registrations of one drive. All persons the drive is meant for are included,
+    /// regardless of whether they registered or not. Results in no registrations at all if the
+    /// drive doesn't exist.
+    DriveId(i64),
 
-    /// Searches the registrations by person id.
+    /// Searches the registrations by person id, leaving out drives restricted to groups the person
+    /// isn't in.
     PersonId { id: i64, filter: DriveFilter },
 }
 
 /// Creates a vector of [`Registration`]s filtered by the given criteria.
 ///
-/// Note that all registrations are sorted in ascending order by last name.
+/// Note that registrations of a drive are sorted by group first, persons without group last, and
+/// then in ascending order by last name.
 pub fn search_registrations(
     conn: &mut rusqlite::Connection,
     by: &SearchRegistrationsBy,
 ) -> Result<Vec<Registration>, rusqlite::Error> {
     let mut statement = match by {
-        SearchRegistrationsBy::Date(_) => conn.prepare(
+        SearchRegistrationsBy::DriveId(_) => conn.prepare(&format!(
             "SELECT person.person_id, person.prename, person.name, person.email, person.is_visible,
-                drive.drive_id, :date, drive.deadline, drive.registration_cap,
+                drive.drive_id, drive.drivedate, drive.label, drive.deadline, drive.registration_cap,
                 (
                     SELECT count()
-                    FROM drive AS drive_subquery
-                    NATURAL JOIN registration
-                    WHERE registered AND drive_subquery.drivedate == drive.drivedate
+                    FROM registration AS registration_subquery
+                    WHERE registered AND registration_subquery.drive_id == drive.drive_id
                 ) AS already_registered_count,
-                registration.registered
+                registration.registered,
+                (
+                    SELECT count()
+                    FROM waitlist AS waitlist_ahead
+                    WHERE waitlist_ahead.drive_id == waitlist.drive_id
+                        AND waitlist_ahead.id <= waitlist.id
+                ) AS waitlist_position,
+                registration.attended,
+                person_group.name
             FROM person
-            LEFT OUTER JOIN drive ON (drive.drivedate == :date)
+            INNER JOIN drive ON (drive.drive_id == :drive_id)
             LEFT OUTER JOIN registration ON (
                 registration.person_id == person.person_id AND
                 registration.drive_id == drive.drive_id
             )
-            WHERE person.is_visible
-            ORDER BY person.name",
-        ),
+            LEFT OUTER JOIN waitlist ON (
+                waitlist.person_id == person.person_id AND
+                waitlist.drive_id == drive.drive_id
+            )
+            LEFT OUTER JOIN person_group ON (person_group.group_id == person.group_id)
+            WHERE person.is_visible AND {}
+            ORDER BY person_group.name IS NULL, person_group.name, person.name",
+            DRIVE_MEANT_FOR_PERSON,
+        )),
         SearchRegistrationsBy::PersonId { filter, .. } => conn.prepare(&format!(
             "SELECT person.person_id, person.prename, person.name, person.email, person.is_visible,
-                drive.drive_id, drive.drivedate, drive.deadline, drive.registration_cap,
+                drive.drive_id, drive.drivedate, drive.label, drive.deadline, drive.registration_cap,
                 (
                     SELECT count()
-                    FROM drive AS drive_subquery
-                    NATURAL JOIN registration
-                    WHERE registered AND drive_subquery.drivedate == drive.drivedate
-                    ORDER BY drive.drive_id DESC
+                    FROM registration AS registration_subquery
+                    WHERE registered AND registration_subquery.drive_id == drive.drive_id
                 ) AS already_registered_count,
-                registration.registered
+                registration.registered,
+                (
+                    SELECT count()
+                    FROM waitlist AS waitlist_ahead
+                    WHERE waitlist_ahead.drive_id == waitlist.drive_id
+                        AND waitlist_ahead.id <= waitlist.id
+                ) AS waitlist_position,
+                registration.attended,
+                person_group.name
             FROM drive
             LEFT OUTER JOIN person ON (person.person_id == :id)
             LEFT OUTER JOIN registration ON (
                 registration.drive_id == drive.drive_id
                 AND registration.person_id == person.person_id
             )
+            LEFT OUTER JOIN waitlist ON (
+                waitlist.drive_id == drive.drive_id
+                AND waitlist.person_id == person.person_id
+            )
+            LEFT OUTER JOIN person_group ON (person_group.group_id == person.group_id)
+            WHERE {}
             {}",
+            DRIVE_MEANT_FOR_PERSON,
             match filter {
                 DriveFilter::OnlyFuture =>
-                    "WHERE :now < drive.deadline
-                    ORDER BY drive.drivedate ASC",
+                    "AND :now < drive.deadline
+                    ORDER BY drive.drivedate ASC, drive.label ASC",
                 DriveFilter::OnlyPast =>
-                    "WHERE drive.deadline <= :now
-                    ORDER BY drive.drivedate DESC",
-                DriveFilter::ListAll => "ORDER BY drive.drivedate ASC",
+                    "AND drive.deadline <= :now
+                    ORDER BY drive.drivedate DESC, drive.label DESC",
+                DriveFilter::ListAll => "ORDER BY drive.drivedate ASC, drive.label ASC",
             },
         )),
     }?;
     let rows = match by {
-        SearchRegistrationsBy::Date(date) => statement.query(named_params! { ":date": date }),
+        SearchRegistrationsBy::DriveId(drive_id) => {
+            statement.query(named_params! { ":drive_id": drive_id })
+        }
         SearchRegistrationsBy::PersonId {
             id,
             filter: DriveFilter::OnlyPast | DriveFilter::OnlyFuture,
@@ -252,11 +411,15 @@ pub fn search_registrations(
                 drive: Drive {
                     id: row.get(5)?,
                     date: row.get(6)?,
-                    deadline: row.get(7)?,
-                    registration_cap: row.get(8)?,
-                    already_registered_count: row.get(9)?,
+                    label: row.get(7)?,
+                    deadline: row.get(8)?,
+                    registration_cap: row.get(9)?,
+                    already_registered_count: row.get(10)?,
                 },
-                registered: false_if_null(row.get(10))?,
+                registered: false_if_null(row.get(11))?,
+                waitlist_position: row.get::<_, Option<u32>>(12)?.filter(|&pos| pos > 0),
+                attended: row.get(13)?,
+                group: row.get(14)?,
             })
         })
         .map(Result::unwrap)
@@ -265,18 +428,30 @@ pub fn search_registrations(
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CountedRegistrations {
-    persons: Vec<PersonWithRegistrations>,
-    sum: i64,
+    pub persons: Vec<PersonWithRegistrations>,
+    pub sum: i64,
+    pub no_shows_sum: i64,
+    pub unregistered_shows_sum: i64,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PersonWithRegistrations {
-    person: Person,
-    count: i64,
+    pub person: Person,
+    pub count: i64,
+
+    /// How often the person was registered, but wasn't on the bus.
+    pub no_shows: i64,
+
+    /// How often the person was on the bus without being registered.
+    pub unregistered_shows: i64,
+
+    /// How many of the registrations got checked on the bus at all, which is the base for a
+    /// no-show rate.
+    pub checked: i64,
 }
 
-/// Counts all registrations between the given dates, [`None`] meaning infinity in either
-/// direction.
+/// Counts all registrations and attendances between the given dates, [`None`] meaning infinity in
+/// either direction. Persons who neither registered nor showed up are left out.
 pub fn list_persons_counted_registrations(
     conn: &mut rusqlite::Connection,
     from: Option<chrono::NaiveDate>,
@@ -284,16 +459,17 @@ pub fn list_persons_counted_registrations(
 ) -> Result<CountedRegistrations, rusqlite::Error> {
     let statement = format!(
         "SELECT person.person_id, person.prename, person.name, person.email, person.is_visible,
-          COUNT(registration.person_id)
-        FROM drive, person
-        LEFT OUTER JOIN registration ON (
-          registration.drive_id == drive.drive_id
-          AND registration.person_id == person.person_id
-        )
-        WHERE registration.registered == true
+            SUM(registration.registered),
+            SUM(registration.registered AND registration.attended IS false),
+            SUM(NOT registration.registered AND registration.attended IS true),
+            SUM(registration.registered AND registration.attended IS NOT NULL)
+        FROM registration
+        INNER JOIN person ON person.person_id == registration.person_id
+        INNER JOIN drive ON drive.drive_id == registration.drive_id
+        WHERE (registration.registered OR registration.attended IS true)
             {}
             {}
-        GROUP BY registration.person_id
+        GROUP BY person.person_id
         ORDER BY person.name",
         from.map(|_| "AND :from <= drive.drivedate").unwrap_or(""),
         to.map(|_| "AND drive.drivedate <= :to").unwrap_or(""),
@@ -311,6 +487,9 @@ pub fn list_persons_counted_registrations(
             Ok(PersonWithRegistrations {
                 person: row_to_person(row)?,
                 count: row.get(5)?,
+                no_shows: row.get(6)?,
+                unregistered_shows: row.get(7)?,
+                checked: row.get(8)?,
             })
         })
         .map(Result::unwrap)
@@ -318,60 +497,410 @@ pub fn list_persons_counted_registrations(
             CountedRegistrations {
                 persons: Vec::new(),
                 sum: 0,
+                no_shows_sum: 0,
+                unregistered_shows_sum: 0,
             },
             |mut state, person| {
-                let person = person;
                 state.sum += person.count;
+                state.no_shows_sum += person.no_shows;
+                state.unregistered_shows_sum += person.unregistered_shows;
                 state.persons.push(person);
                 state
             },
         ))
 }
 
+/// The attendance on a single drive compared to its registrations.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DriveAttendance {
+    pub drive: Drive,
+
+    /// Registered persons who weren't on the bus.
+    pub no_shows: u32,
+
+    /// Persons who were on the bus without being registered.
+    pub unregistered_shows: u32,
+
+    /// Registered persons whose attendance got recorded at all.
+    pub checked: u32,
+}
+
+/// Lists all drives between the given dates with their attendance, [`None`] meaning infinity in
+/// either direction.
+pub fn list_drives_attendance(
+    conn: &mut rusqlite::Connection,
+    from: Option<chrono::NaiveDate>,
+    to: Option<chrono::NaiveDate>,
+) -> Result<Vec<DriveAttendance>, rusqlite::Error> {
+    let statement = format!(
+        "SELECT drive.drive_id, drive.drivedate, drive.label, drive.deadline,
+            drive.registration_cap,
+            IFNULL(SUM(registration.registered), 0),
+            IFNULL(SUM(registration.registered AND registration.attended IS false), 0),
+            IFNULL(SUM(NOT registration.registered AND registration.attended IS true), 0),
+            IFNULL(SUM(registration.registered AND registration.attended IS NOT NULL), 0)
+        FROM drive
+        LEFT OUTER JOIN registration ON registration.drive_id == drive.drive_id
+        WHERE true
+            {}
+            {}
+        GROUP BY drive.drive_id
+        ORDER BY drive.drivedate ASC, drive.label ASC",
+        from.map(|_| "AND :from <= drive.drivedate").unwrap_or(""),
+        to.map(|_| "AND drive.drivedate <= :to").unwrap_or(""),
+    );
+    let mut statement = conn.prepare(&statement)?;
+    let rows = match (from, to) {
+        (None, None) => statement.query([]),
+        (Some(from), None) => statement.query(named_params! { ":from": from }),
+        (None, Some(to)) => statement.query(named_params! { ":to": to }),
+        (Some(from), Some(to)) => statement.query(named_params! { ":from": from, ":to": to }),
+    }?;
+
+    rows.mapped(|row| {
+        Ok(DriveAttendance {
+            drive: Drive {
+                id: row.get(0)?,
+                date: row.get(1)?,
+                label: row.get(2)?,
+                deadline: row.get(3)?,
+                registration_cap: row.get(4)?,
+                already_registered_count: row.get(5)?,
+            },
+            no_shows: row.get(6)?,
+            unregistered_shows: row.get(7)?,
+            checked: row.get(8)?,
+        })
+    })
+    .collect()
+}
+
 #[derive(Debug, Error)]
 pub enum ApplyRegistrationError {
     #[error("Database or query error: {0}")]
     RusqliteError(#[from] rusqlite::Error),
-    #[error("Unknown drive date")]
-    UnknownDriveDate,
+    #[error("Unknown drive")]
+    UnknownDrive,
+    #[error("Drive is restricted to groups the person isn't in")]
+    NotForPerson,
+}
+
+/// What [`update_registration`] did, so the affected persons can be notified.
+#[derive(Debug, Default)]
+pub struct AppliedRegistration {
+    /// Whether the registration state actually changed, as it might have been the same before.
+    pub changed: bool,
+
+    /// The persons which got registered from the waitlist in turn.
+    pub promoted: Vec<i64>,
 }
 
 /// Creates a registration entry with the given registration and usage, overwriting it if it
 /// previously existed.
+///
+/// Registering removes the person from the waitlist of that drive. Unregistering frees a seat,
+/// which is then handed to the waitlist in FIFO order (see [`promote_from_waitlist`]). Changes are
+/// recorded in the audit log as made by `actor_id`.
 pub fn update_registration(
     conn: &mut rusqlite::Connection,
     registration: &RegistrationUpdate,
+    actor_id: Option<i64>,
+) -> Result<AppliedRegistration, ApplyRegistrationError> {
+    let tx = conn.transaction()?;
+
+    let drive_id = registration.drive_id;
+    if !drive_exists(&tx, drive_id)? {
+        return Err(ApplyRegistrationError::UnknownDrive);
+    }
+
+    let previously_registered = is_registered(&tx, registration.person_id, drive_id)?;
+    if registration.registered
+        && !previously_registered
+        && !drive_open_to(&tx, registration.person_id, drive_id)?
+    {
+        return Err(ApplyRegistrationError::NotForPerson);
+    }
+
+    tx.execute(
+        "INSERT INTO registration (person_id, drive_id, registered)
+        VALUES (:person_id, :drive_id, :registered)
+        ON CONFLICT(person_id, drive_id)
+        DO UPDATE SET registered=:registered",
+        named_params! {
+            ":person_id": registration.person_id,
+            ":drive_id": drive_id,
+            ":registered": registration.registered,
+        },
+    )?;
+
+    let changed = previously_registered != registration.registered;
+    if changed {
+        audit::record(
+            &tx,
+            actor_id,
+            AuditAction::Registration,
+            AuditTarget::Registration {
+                person_id: registration.person_id,
+                drive_id,
+            },
+            json!({ "registered": previously_registered }),
+            json!({ "registered": registration.registered }),
+        )?;
+    }
+
+    let promoted = if registration.registered {
+        tx.execute(
+            "DELETE FROM waitlist
+            WHERE person_id == :person_id AND drive_id == :drive_id",
+            named_params! {
+                ":person_id": registration.person_id,
+                ":drive_id": drive_id,
+            },
+        )?;
+        Vec::new()
+    } else {
+        promote_from_waitlist(&tx, drive_id, actor_id)?
+    };
+
+    tx.commit()?;
+    Ok(AppliedRegistration { changed, promoted })
+}
+
+/// Whether a drive with the given ID exists.
+#[doc(hidden)]
+fn drive_exists(conn: &rusqlite::Connection, drive_id: i64) -> Result<bool, rusqlite::Error> {
+    conn.query_row(
+        "SELECT EXISTS(
+            SELECT drive_id
+            FROM drive
+            WHERE drive_id == :drive_id
+        )",
+        named_params! { ":drive_id": drive_id },
+        |row| row.get(0),
+    )
+}
+
+/// Whether the drive isn't restricted to any groups or the person is in one of them. Unlike in
+/// [`search_registrations`], existing registrations don't count.
+pub fn drive_open_to(
+    conn: &rusqlite::Connection,
+    person_id: i64,
+    drive_id: i64,
+) -> Result<bool, rusqlite::Error> {
+    conn.query_row(
+        "SELECT NOT EXISTS (
+                SELECT group_id
+                FROM drive_group
+                WHERE drive_id == :drive_id
+            )
+            OR EXISTS (
+                SELECT person_id
+                FROM person
+                INNER JOIN drive_group ON (drive_group.group_id == person.group_id)
+                WHERE person.person_id == :person_id AND drive_group.drive_id == :drive_id
+            )",
+        named_params! {
+            ":person_id": person_id,
+            ":drive_id": drive_id,
+        },
+        |row| row.get(0),
+    )
+}
+
+/// Records whether the person was on the bus for the given drive, [`Option::None`] meaning that
+/// it's unknown. Works for persons who didn't register as well, their registration stays as is.
+/// Changes are recorded in the audit log as made by `actor_id`.
+pub fn update_attendance(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+    drive_id: i64,
+    attended: Option<bool>,
+    actor_id: Option<i64>,
 ) -> Result<(), ApplyRegistrationError> {
-    match_constraint_violation!(
+    let tx = conn.transaction()?;
+    if !drive_exists(&tx, drive_id)? {
+        return Err(ApplyRegistrationError::UnknownDrive);
+    }
+
+    let before: Option<bool> = tx
+        .query_row(
+            "SELECT attended
+            FROM registration
+            WHERE person_id == :person_id AND drive_id == :drive_id",
+            named_params! {
+                ":person_id": person_id,
+                ":drive_id": drive_id,
+            },
+            |row| row.get(0),
+        )
+        .optional()?
+        .flatten();
+    tx.execute(
+        "INSERT INTO registration (person_id, drive_id, registered, attended)
+        VALUES (:person_id, :drive_id, false, :attended)
+        ON CONFLICT(person_id, drive_id)
+        DO UPDATE SET attended=:attended",
+        named_params! {
+            ":person_id": person_id,
+            ":drive_id": drive_id,
+            ":attended": attended,
+        },
+    )?;
+
+    if before != attended {
+        audit::record(
+            &tx,
+            actor_id,
+            AuditAction::Attendance,
+            AuditTarget::Registration {
+                person_id,
+                drive_id,
+            },
+            json!({ "attended": before }),
+            json!({ "attended": attended }),
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Registers as many persons from the waitlist of the given drive as there are free seats, in the
+/// order they joined the waitlist. A drive without registration cap takes everyone waiting.
+///
+/// Returns the IDs of the persons who got promoted. Meant to be called inside of a transaction
+/// which changed the registrations or the cap of the drive, `actor_id` being who made that change.
+pub fn promote_from_waitlist(
+    conn: &rusqlite::Connection,
+    drive_id: i64,
+    actor_id: Option<i64>,
+) -> Result<Vec<i64>, rusqlite::Error> {
+    let free_seats: Option<i64> = match conn
+        .query_row(
+            "SELECT registration_cap - (
+                    SELECT count()
+                    FROM registration
+                    WHERE registered AND registration.drive_id == drive.drive_id
+                )
+            FROM drive
+            WHERE drive_id == :drive_id",
+            named_params! { ":drive_id": drive_id },
+            |row| row.get(0),
+        )
+        .optional()?
+    {
+        Some(free_seats) => free_seats,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut statement = conn.prepare(
+        "SELECT person_id
+        FROM waitlist
+        WHERE drive_id == :drive_id
+        ORDER BY id ASC
+        LIMIT :limit",
+    )?;
+    let promoted = statement
+        .query_map(
+            named_params! {
+                ":drive_id": drive_id,
+                // a negative limit means no limit at all in SQLite
+                ":limit": free_seats.map_or(-1, |free| free.max(0)),
+            },
+            |row| row.get(0),
+        )?
+        .collect::<Result<Vec<i64>, _>>()?;
+
+    for person_id in &promoted {
         conn.execute(
             "INSERT INTO registration (person_id, drive_id, registered)
+            VALUES (:person_id, :drive_id, true)
+            ON CONFLICT(person_id, drive_id)
+            DO UPDATE SET registered=true",
+            named_params! {
+                ":person_id": person_id,
+                ":drive_id": drive_id,
+            },
+        )?;
+        conn.execute(
+            "DELETE FROM waitlist
+            WHERE person_id == :person_id AND drive_id == :drive_id",
+            named_params! {
+                ":person_id": person_id,
+                ":drive_id": drive_id,
+            },
+        )?;
+        audit::record(
+            conn,
+            actor_id,
+            AuditAction::WaitlistPromotion,
+            AuditTarget::Registration {
+                person_id: *person_id,
+                drive_id,
+            },
+            json!({ "registered": false }),
+            json!({ "registered": true }),
+        )?;
+    }
+
+    Ok(promoted)
+}
+
+/// Puts the person at the end of the waitlist of the given drive. A no-op if the person is
+/// already waiting for that drive.
+pub fn join_waitlist(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+    drive_id: i64,
+) -> Result<(), ApplyRegistrationError> {
+    if !drive_open_to(conn, person_id, drive_id)? {
+        return Err(ApplyRegistrationError::NotForPerson);
+    }
+
+    match_constraint_violation!(
+        conn.execute(
+            "INSERT INTO waitlist (person_id, drive_id)
             VALUES (
                 :person_id,
                 (
                     SELECT drive_id
                     FROM drive
-                    WHERE drivedate == :date
-                ),
-                :registered
+                    WHERE drive_id == :drive_id
+                )
             )
             ON CONFLICT(person_id, drive_id)
-            DO UPDATE SET registered=:registered",
+            DO NOTHING",
             named_params! {
-                ":person_id": registration.person_id,
-                ":date": registration.date,
-                ":registered": registration.registered,
+                ":person_id": person_id,
+                ":drive_id": drive_id,
             },
         ),
-        ApplyRegistrationError::UnknownDriveDate
+        ApplyRegistrationError::UnknownDrive
     )
 }
 
+/// Removes the person from the waitlist of the given drive, if they were on it.
+pub fn leave_waitlist(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+    drive_id: i64,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "DELETE FROM waitlist
+        WHERE person_id == :person_id AND drive_id == :drive_id",
+        named_params! {
+            ":person_id": person_id,
+            ":drive_id": drive_id,
+        },
+    )?;
+    Ok(())
+}
+
 /// Checks whether the person is registered for the drive. Does NOT check for validity of the
 /// person ID, just returns `false` if invalid.
 pub fn is_registered(
-    conn: &mut rusqlite::Connection,
+    conn: &rusqlite::Connection,
     person_id: i64,
-    drivedate: chrono::NaiveDate,
+    drive_id: i64,
 ) -> Result<bool, rusqlite::Error> {
     let mut statement = conn.prepare(
         "SELECT
@@ -379,14 +908,13 @@ pub fn is_registered(
                 WHEN registered IS NULL THEN false
                 ELSE registered
             END
-        FROM drive
-        NATURAL JOIN registration
-        WHERE registration.person_id == :id AND drive.drivedate == :date",
+        FROM registration
+        WHERE registration.person_id == :id AND registration.drive_id == :drive_id",
     )?;
     let mut query = statement.query_map(
         named_params! {
             ":id": person_id,
-            ":date": drivedate,
+            ":drive_id": drive_id,
         },
         |row| row.get(0),
     )?;
@@ -396,11 +924,11 @@ pub fn is_registered(
 
 pub enum SearchPersonBy {
     /// Searches by email. Returns only one result because emails are supposed to be unique.
-    /// Token, token expiration and superuser state are **not** included.
+    /// Token, token expiration and roles are **not** included.
     Email(String),
 
     /// Searches by person ID. Note that this is the only search method which includes token, token
-    /// expiration and superuser state.
+    /// expiration and roles.
     Id(i64),
 }
 
@@ -427,7 +955,13 @@ pub fn search_person(
             WHERE email == :email",
         ),
         SearchPersonBy::Id(_) => conn.prepare(
-            "SELECT person_id, prename, name, email, token, token_expiration, is_superuser, is_visible
+            "SELECT person_id, prename, name, email, token, token_expiration,
+                (
+                    SELECT group_concat(role)
+                    FROM role
+                    WHERE role.person_id == person.person_id
+                ),
+                is_visible
             FROM person
             WHERE person_id == :id",
         ),
@@ -460,9 +994,9 @@ pub enum VisibilityFilter {
 
 /// Lists all persons, optionally also invisible ones.
 ///
-/// Doesn't include token, token expiration and superuser state (you know it anyways).
+/// Doesn't include token, token expiration and roles (see [`list_roles`] for the latter).
 pub fn list_all_persons(
-    conn: &mut rusqlite::Connection,
+    conn: &rusqlite::Connection,
     filter: VisibilityFilter,
 ) -> rusqlite::Result<Vec<Person>> {
     let mut statement = conn.prepare(&format!(
@@ -507,78 +1041,428 @@ pub fn update_token(
     Ok(())
 }
 
-pub struct DriveOverview {
-    pub past: Vec<Drive>,
-    pub future: Vec<Drive>,
+/// A short one-time code sent along with the login link, for when the link can't be opened on the
+/// device the person wants to log in on.
+#[derive(Debug, Clone)]
+pub struct LoginCode {
+    /// The argon2 hash of the code.
+    pub hash: String,
+    /// A UNIX timestamp in seconds after which the code isn't accepted anymore.
+    pub expiration: i64,
+    /// How many attempts were used up since the code was set, see [`reserve_login_code_attempt`].
+    pub attempts: u32,
 }
 
-pub fn list_drives(conn: &mut rusqlite::Connection) -> Result<DriveOverview, rusqlite::Error> {
-    let now = Utc::now().naive_local().date();
-    let time_slices = ["drivedate < :now", ":now <= drivedate"].map(|condition| {
-        let mut statement = conn.prepare(&format!(
-            "SELECT drive_id, drivedate, deadline, registration_cap,
-                (
-                    SELECT count()
-                    FROM drive AS drive_inner
-                    NATURAL JOIN registration
-                    WHERE registered AND drive_inner.drive_id == drive.drive_id
-                ) AS already_registered_count
-            FROM drive
-            WHERE {}",
-            condition,
-        ))?;
-
-        let rows = statement.query_map(
-            named_params! {
-                ":now": now,
-            },
-            |row| {
-                Ok(Drive {
-                    id: row.get(0)?,
-                    date: row.get(1)?,
-                    deadline: row.get(2)?,
-                    registration_cap: row.get(3)?,
-                    already_registered_count: row.get(4)?,
-                })
-            },
-        )?;
+/// Sets the hashed login code of a person, valid for ten minutes from now, or removes it if
+/// `new_code` is [`Option::None`]. Resets the failed attempts in both cases.
+pub fn set_login_code(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+    new_code: Option<String>,
+) -> Result<(), rusqlite::Error> {
+    let expiration = new_code
+        .as_ref()
+        .map(|_| relative_to_absolute(Duration::from_secs(10 * 60)));
 
-        rows.collect()
-    });
+    conn.execute(
+        "UPDATE person
+        SET login_code = :new_code,
+            login_code_expiration = :expiration,
+            login_code_attempts = 0
+        WHERE person_id == :person_id",
+        named_params! {
+            ":person_id": person_id,
+            ":new_code": new_code,
+            ":expiration": expiration,
+        },
+    )?;
+    Ok(())
+}
 
-    let [past, future]: [Result<_, _>; 2] = time_slices;
-    Ok(DriveOverview {
-        past: past?,
-        future: future?,
-    })
+pub fn get_login_code(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+) -> Result<Option<LoginCode>, rusqlite::Error> {
+    conn.query_row(
+        "SELECT login_code, login_code_expiration, login_code_attempts
+        FROM person
+        WHERE person_id == :person_id AND login_code IS NOT NULL",
+        named_params! { ":person_id": person_id },
+        |row| {
+            Ok(LoginCode {
+                hash: row.get(0)?,
+                expiration: row.get(1)?,
+                attempts: row.get(2)?,
+            })
+        },
+    )
+    .optional()
 }
 
-pub fn get_drive(
+/// Uses up one of the `max_attempts` the person has for entering their code and returns the
+/// hashed code to check against. Returns [`Option::None`] if there's no code, it expired at `now`
+/// or all attempts are used up. Counting and checking happen in one statement, so parallel requests
+/// can't try more codes than allowed. The attempts are reset when the code is.
+pub fn reserve_login_code_attempt(
     conn: &mut rusqlite::Connection,
-    date: chrono::NaiveDate,
-) -> Result<Option<Drive>, rusqlite::Error> {
-    let mut statement = conn.prepare(
-        "SELECT drive_id, drivedate, deadline, registration_cap,
+    person_id: i64,
+    max_attempts: u32,
+    now: i64,
+) -> Result<Option<String>, rusqlite::Error> {
+    conn.query_row(
+        "UPDATE person
+        SET login_code_attempts = login_code_attempts + 1
+        WHERE person_id == :person_id
+            AND login_code IS NOT NULL
+            AND :now <= login_code_expiration
+            AND login_code_attempts < :max_attempts
+        RETURNING login_code",
+        named_params! {
+            ":person_id": person_id,
+            ":max_attempts": max_attempts,
+            ":now": now,
+        },
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// Starts a new session for the person, which is valid until the given UNIX timestamp or until
+/// it's deleted. If a superuser views the site as that person, `impersonator_id` is their ID, and
+/// that's recorded in the audit log. Returns the session ID.
+pub fn create_session(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+    expiration: i64,
+    impersonator_id: Option<i64>,
+) -> Result<i64, rusqlite::Error> {
+    let tx = conn.transaction()?;
+    tx.execute(
+        "INSERT INTO session (person_id, created, expiration, impersonator_id)
+        VALUES (:person_id, :created, :expiration, :impersonator_id)",
+        named_params! {
+            ":person_id": person_id,
+            ":created": Utc::now().timestamp(),
+            ":expiration": expiration,
+            ":impersonator_id": impersonator_id,
+        },
+    )?;
+    let session_id = tx.last_insert_rowid();
+
+    if impersonator_id.is_some() {
+        audit::record(
+            &tx,
+            impersonator_id,
+            AuditAction::ImpersonationStart,
+            AuditTarget::Person(person_id),
+            serde_json::Value::Null,
+            json!({ "session_id": session_id, "expiration": expiration }),
+        )?;
+    }
+    tx.commit()?;
+    Ok(session_id)
+}
+
+/// Whether the session exists, belongs to the person, that person still exists and the session
+/// didn't expire before `now`.
+pub fn session_valid(
+    conn: &mut rusqlite::Connection,
+    session_id: i64,
+    person_id: i64,
+    now: i64,
+) -> Result<bool, rusqlite::Error> {
+    conn.query_row(
+        "SELECT EXISTS(
+            SELECT session_id
+            FROM session
+            INNER JOIN person ON person.person_id == session.person_id
+            WHERE session_id == :session_id
+                AND session.person_id == :person_id
+                AND :now <= expiration
+        )",
+        named_params! {
+            ":session_id": session_id,
+            ":person_id": person_id,
+            ":now": now,
+        },
+        |row| row.get(0),
+    )
+}
+
+/// Ends the session. If a superuser was viewing the site as someone else in it, that's recorded in
+/// the audit log.
+pub fn delete_session(
+    conn: &mut rusqlite::Connection,
+    session_id: i64,
+) -> Result<(), rusqlite::Error> {
+    let tx = conn.transaction()?;
+    let impersonation: Option<(i64, i64)> = tx
+        .query_row(
+            "SELECT person_id, impersonator_id
+            FROM session
+            WHERE session_id == :session_id AND impersonator_id IS NOT NULL",
+            named_params! { ":session_id": session_id },
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    tx.execute(
+        "DELETE FROM session
+        WHERE session_id == :session_id",
+        named_params! { ":session_id": session_id },
+    )?;
+    if let Some((person_id, impersonator_id)) = impersonation {
+        audit::record(
+            &tx,
+            Some(impersonator_id),
+            AuditAction::ImpersonationEnd,
+            AuditTarget::Person(person_id),
+            json!({ "session_id": session_id }),
+            serde_json::Value::Null,
+        )?;
+    }
+    tx.commit()
+}
+
+/// Logs the person out everywhere. Returns how many sessions were deleted. Sessions of
+/// superusers viewing the site as that person are ended too. This is recorded in the audit log as
+/// done by `actor_id`, unless there were no sessions.
+pub fn delete_sessions_of_person(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+    actor_id: Option<i64>,
+) -> Result<usize, rusqlite::Error> {
+    let tx = conn.transaction()?;
+    let deleted = tx.execute(
+        "DELETE FROM session
+        WHERE person_id == :person_id OR impersonator_id == :person_id",
+        named_params! { ":person_id": person_id },
+    )?;
+    if deleted > 0 {
+        audit::record(
+            &tx,
+            actor_id,
+            AuditAction::SessionRevocation,
+            AuditTarget::Person(person_id),
+            json!({ "sessions": deleted }),
+            json!({ "sessions": 0 }),
+        )?;
+    }
+    tx.commit()?;
+    Ok(deleted)
+}
+
+/// Deletes all sessions which expired before the given UNIX timestamp.
+pub fn delete_expired_sessions(
+    conn: &mut rusqlite::Connection,
+    now: i64,
+) -> Result<usize, rusqlite::Error> {
+    conn.execute(
+        "DELETE FROM session
+        WHERE expiration < :now",
+        named_params! { ":now": now },
+    )
+}
+
+/// A personal API token, used instead of logging in by scripts. Handed out as
+/// `knb_<id>_<secret>`, of which only the hash of the secret is stored.
+#[derive(Debug, Serialize)]
+pub struct ApiToken {
+    pub id: i64,
+    pub person_id: i64,
+    pub name: String,
+    #[serde(skip)]
+    pub hash: String,
+    pub scopes: Vec<Scope>,
+    /// UNIX timestamp in seconds.
+    pub created: i64,
+    /// UNIX timestamp in seconds, [`Option::None`] if the token was never used.
+    pub last_used: Option<i64>,
+}
+
+// See note of row_to_full_person
+#[doc(hidden)]
+fn row_to_api_token(row: &rusqlite::Row) -> rusqlite::Result<ApiToken> {
+    Ok(ApiToken {
+        id: row.get(0)?,
+        person_id: row.get(1)?,
+        name: row.get(2)?,
+        hash: row.get(3)?,
+        scopes: row
+            .get::<_, String>(4)?
+            .split(',')
+            .filter_map(|scope| scope.parse().ok())
+            .collect(),
+        created: row.get(5)?,
+        last_used: row.get(6)?,
+    })
+}
+
+/// Stores a new API token for the person and returns its ID.
+pub fn insert_api_token(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+    name: &str,
+    hash: &str,
+    scopes: &[Scope],
+    created: i64,
+) -> Result<i64, rusqlite::Error> {
+    let scopes: Vec<&str> = scopes.iter().map(|scope| scope.as_str()).collect();
+    conn.execute(
+        "INSERT INTO api_token (person_id, name, token, scopes, created)
+        VALUES (:person_id, :name, :token, :scopes, :created)",
+        named_params! {
+            ":person_id": person_id,
+            ":name": name,
+            ":token": hash,
+            ":scopes": scopes.join(","),
+            ":created": created,
+        },
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn get_api_token(
+    conn: &mut rusqlite::Connection,
+    id: i64,
+) -> Result<Option<ApiToken>, rusqlite::Error> {
+    conn.query_row(
+        "SELECT api_token_id, person_id, name, token, scopes, created, last_used
+        FROM api_token
+        WHERE api_token_id == :id",
+        named_params! { ":id": id },
+        row_to_api_token,
+    )
+    .optional()
+}
+
+/// Lists the API tokens of the person, the newest first.
+pub fn list_api_tokens(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+) -> Result<Vec<ApiToken>, rusqlite::Error> {
+    let mut statement = conn.prepare(
+        "SELECT api_token_id, person_id, name, token, scopes, created, last_used
+        FROM api_token
+        WHERE person_id == :person_id
+        ORDER BY created DESC, api_token_id DESC",
+    )?;
+    let tokens = statement
+        .query_map(named_params! { ":person_id": person_id }, row_to_api_token)?
+        .collect();
+    tokens
+}
+
+/// Notes down when the token was last used, so forgotten tokens can be spotted.
+pub fn touch_api_token(
+    conn: &mut rusqlite::Connection,
+    id: i64,
+    now: i64,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "UPDATE api_token
+        SET last_used = :now
+        WHERE api_token_id == :id",
+        named_params! {
+            ":id": id,
+            ":now": now,
+        },
+    )?;
+    Ok(())
+}
+
+/// Revokes an API token. Only the owner can do so, returns whether there was such a token.
+pub fn delete_api_token(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+    id: i64,
+) -> Result<bool, rusqlite::Error> {
+    let deleted = conn.execute(
+        "DELETE FROM api_token
+        WHERE api_token_id == :id AND person_id == :person_id",
+        named_params! {
+            ":id": id,
+            ":person_id": person_id,
+        },
+    )?;
+    Ok(deleted > 0)
+}
+
+#[derive(Debug, Serialize)]
+pub struct DriveOverview {
+    pub past: Vec<Drive>,
+    pub future: Vec<Drive>,
+}
+
+pub fn list_drives(conn: &mut rusqlite::Connection) -> Result<DriveOverview, rusqlite::Error> {
+    let now = Utc::now().naive_local().date();
+    let time_slices = ["drivedate < :now", ":now <= drivedate"].map(|condition| {
+        let mut statement = conn.prepare(&format!(
+            "SELECT drive_id, drivedate, label, deadline, registration_cap,
+                (
+                    SELECT count()
+                    FROM drive AS drive_inner
+                    NATURAL JOIN registration
+                    WHERE registered AND drive_inner.drive_id == drive.drive_id
+                ) AS already_registered_count
+            FROM drive
+            WHERE {}
+            ORDER BY drivedate ASC, label ASC",
+            condition,
+        ))?;
+
+        let rows = statement.query_map(
+            named_params! {
+                ":now": now,
+            },
+            |row| {
+                Ok(Drive {
+                    id: row.get(0)?,
+                    date: row.get(1)?,
+                    label: row.get(2)?,
+                    deadline: row.get(3)?,
+                    registration_cap: row.get(4)?,
+                    already_registered_count: row.get(5)?,
+                })
+            },
+        )?;
+
+        rows.collect()
+    });
+
+    let [past, future]: [Result<_, _>; 2] = time_slices;
+    Ok(DriveOverview {
+        past: past?,
+        future: future?,
+    })
+}
+
+pub fn get_drive(
+    conn: &mut rusqlite::Connection,
+    id: i64,
+) -> Result<Option<Drive>, rusqlite::Error> {
+    let mut statement = conn.prepare(
+        "SELECT drive_id, drivedate, label, deadline, registration_cap,
             (
                 SELECT count()
-                FROM drive
-                NATURAL JOIN registration
-                WHERE registered AND drivedate == :date
+                FROM registration
+                WHERE registered AND drive_id == :id
             ) AS already_registered_count
         FROM drive
-        WHERE drivedate == :date",
+        WHERE drive_id == :id",
     )?;
     let mut query = statement.query_map(
         named_params! {
-            ":date": date
+            ":id": id
         },
         |row| {
             Ok(Drive {
                 id: row.get(0)?,
                 date: row.get(1)?,
-                deadline: row.get(2)?,
-                registration_cap: row.get(3)?,
-                already_registered_count: row.get(4)?,
+                label: row.get(2)?,
+                deadline: row.get(3)?,
+                registration_cap: row.get(4)?,
+                already_registered_count: row.get(5)?,
             })
         },
     )?;
@@ -595,172 +1479,1165 @@ pub enum InsertDriveError {
 }
 
 /// Inserts a new drive entry in the DB. You should check the return result for
-/// [`InsertDriveError`]`::AlreadyExists`.
+/// [`InsertDriveError`]`::AlreadyExists`, which is the case if there's already a drive with the
+/// same date and label.
+///
+/// The drive is recorded in the audit log as created by `actor_id`. Meant to be called inside of a
+/// transaction, so there's no drive without its entry.
 pub fn insert_new_drive(
-    conn: &mut rusqlite::Connection,
+    conn: &rusqlite::Connection,
     date: chrono::NaiveDate,
+    label: &str,
     deadline: Option<chrono::NaiveDateTime>,
+    actor_id: Option<i64>,
 ) -> Result<(), InsertDriveError> {
-    match_constraint_violation!(
+    let inserted: Result<(), InsertDriveError> = match_constraint_violation!(
         conn.execute(
-            "INSERT INTO drive (drivedate, deadline, registration_cap)
-            SELECT :date, :deadline, value
+            "INSERT INTO drive (drivedate, label, deadline, registration_cap)
+            SELECT :date, :label, :deadline, value
             FROM settings
             WHERE name == 'default-registration-cap'",
             named_params! {
                 ":date": date,
+                ":label": label,
                 ":deadline": deadline,
             },
         ),
         InsertDriveError::AlreadyExists
-    )
+    );
+    inserted?;
+
+    let drive_id = conn.last_insert_rowid();
+    audit::record(
+        conn,
+        actor_id,
+        AuditAction::DriveCreation,
+        AuditTarget::Drive(drive_id),
+        serde_json::Value::Null,
+        drive_details(conn, drive_id)?,
+    )?;
+    Ok(())
 }
 
-/// Deletes a drive by ID and all linked registrations. **This action is irreversible.**
-pub fn delete_drive(conn: &mut rusqlite::Connection, id: i64) -> Result<(), rusqlite::Error> {
-    conn.execute(
+/// Deletes a drive by ID and all linked registrations. **This action is irreversible.** Only the
+/// audit log, where the deletion is recorded as made by `actor_id`, keeps its details.
+pub fn delete_drive(
+    conn: &mut rusqlite::Connection,
+    id: i64,
+    actor_id: Option<i64>,
+) -> Result<(), rusqlite::Error> {
+    let tx = conn.transaction()?;
+    let before = drive_details(&tx, id)?;
+    if before.is_null() {
+        return Ok(());
+    }
+
+    tx.execute(
         "DELETE FROM drive
         WHERE drive_id == :id",
         named_params! {
             ":id": id,
         },
     )?;
-    Ok(())
+    audit::record(
+        &tx,
+        actor_id,
+        AuditAction::DriveDeletion,
+        AuditTarget::Drive(id),
+        before,
+        serde_json::Value::Null,
+    )?;
+    tx.commit()
 }
 
 #[derive(Debug, Error)]
 pub enum UpdateDriveError {
     #[error("Database or query error: {0}")]
     RusqliteError(#[from] rusqlite::Error),
-    #[error("Duplicated drive date")]
-    DateAlreadyExists,
+    #[error("Duplicated drive date and label")]
+    AlreadyExists,
 }
 
-/// Updates a drive's details, based on the ID.
+/// Updates a drive's details, based on the ID. A raised registration cap is filled up from the
+/// waitlist right away, the persons registered that way are returned. A moved deadline means the
+/// reminder for it is sent again. Changes are recorded in the audit log as made by `actor_id`.
 pub fn update_drive_deadline(
     conn: &mut rusqlite::Connection,
     update: Drive,
-) -> Result<(), UpdateDriveError> {
-    match_constraint_violation!(
-        conn.execute(
+    actor_id: Option<i64>,
+) -> Result<Vec<i64>, UpdateDriveError> {
+    let tx = conn.transaction()?;
+    let before = drive_details(&tx, update.id)?;
+    let result: Result<(), UpdateDriveError> = match_constraint_violation!(
+        tx.execute(
             "UPDATE drive
             SET drivedate = :date,
+                label = :label,
+                reminder_sent = (reminder_sent AND deadline IS :deadline),
                 deadline = :deadline,
                 registration_cap = :registration_cap
             WHERE drive_id == :id",
             named_params! {
                 ":date": update.date,
+                ":label": update.label,
                 ":deadline": update.deadline,
                 ":registration_cap": update.registration_cap,
                 ":id": update.id,
             },
         )
         .map(|_| ()),
-        UpdateDriveError::DateAlreadyExists
-    )
-}
+        UpdateDriveError::AlreadyExists
+    );
+    result?;
 
-#[derive(Debug, Error)]
-pub enum PersonCreationError {
-    #[error("Email is already used")]
-    EmailAlreadyInUse,
-    #[error("Database or query error: {0}")]
-    RusqliteError(#[from] rusqlite::Error),
-}
+    let after = drive_details(&tx, update.id)?;
+    if before != after {
+        audit::record(
+            &tx,
+            actor_id,
+            AuditAction::DriveUpdate,
+            AuditTarget::Drive(update.id),
+            before,
+            after,
+        )?;
+    }
 
-#[derive(Clone, Debug)]
-pub struct NewPerson {
-    pub prename: String,
-    pub name: String,
-    pub email: Address,
+    let promoted = promote_from_waitlist(&tx, update.id, actor_id)?;
+    tx.commit()?;
+    Ok(promoted)
 }
 
-/// Inserts a new person into the database. The email is not checked for validity.
-pub fn insert_new_person(
-    conn: &mut rusqlite::Connection,
-    person: &NewPerson,
-) -> Result<(), PersonCreationError> {
-    match_constraint_violation!(
-        conn.execute(
-            "INSERT INTO person (prename, name, email, is_superuser, is_visible)
-            VALUES (:prename, :name, :email, false, true)",
-            named_params! {
-                ":prename": person.prename,
-                ":name": person.name,
-                ":email": person.email.to_string(),
+/// What can be changed about a drive, as JSON for the audit log. Null if the drive doesn't exist.
+#[doc(hidden)]
+fn drive_details(
+    conn: &rusqlite::Connection,
+    drive_id: i64,
+) -> Result<serde_json::Value, rusqlite::Error> {
+    let details = conn
+        .query_row(
+            "SELECT drivedate, label, deadline, registration_cap
+            FROM drive
+            WHERE drive_id == :drive_id",
+            named_params! { ":drive_id": drive_id },
+            |row| {
+                Ok(json!({
+                    "date": row.get::<_, chrono::NaiveDate>(0)?,
+                    "label": row.get::<_, String>(1)?,
+                    "deadline": row.get::<_, Option<chrono::NaiveDateTime>>(2)?,
+                    "registration_cap": row.get::<_, Option<u32>>(3)?,
+                }))
             },
-        ),
-        PersonCreationError::EmailAlreadyInUse
-    )
+        )
+        .optional()?;
+    Ok(details.unwrap_or_default())
 }
 
-#[derive(Clone, Debug)]
-pub struct UpdatePerson {
+/// Figures out the deadline of a new drive on the given date, based on the `default-deadline`
+/// setting. [`Option::None`] if there is no default deadline.
+pub fn default_deadline(
+    conn: &rusqlite::Connection,
+    drive_date: chrono::NaiveDate,
+) -> Result<Option<chrono::NaiveDateTime>, rusqlite::Error> {
+    Ok(settings::DEFAULT_DEADLINE
+        .get_integer(conn)?
+        .map(|deadline_weekday| figure_out_exact_deadline(deadline_weekday as u32, drive_date)))
+}
+
+/// A rule saying that the bus drives every week on the same weekday.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DriveSchedule {
     pub id: i64,
-    pub prename: String,
-    pub name: String,
-    pub email: Address,
-    pub is_visible: bool,
+
+    /// The weekday of the drives, 0 being Monday and 6 being Sunday (just like the
+    /// `default-deadline` setting).
+    pub weekday: u32,
+
+    /// The label all generated drives get.
+    pub label: String,
+
+    /// Up to which date (inclusive) drives have been generated already. Dates before that are
+    /// never generated again, so drives deleted by hand stay deleted.
+    pub generated_until: Option<chrono::NaiveDate>,
 }
 
-/// Updates a person entry by ID. The email is not checked for validity.
-pub fn update_person(
+pub fn list_drive_schedules(
+    conn: &rusqlite::Connection,
+) -> Result<Vec<DriveSchedule>, rusqlite::Error> {
+    let mut statement = conn.prepare(
+        "SELECT schedule_id, weekday, label, generated_until
+        FROM drive_schedule
+        ORDER BY weekday ASC, label ASC",
+    )?;
+    let schedules = statement
+        .query_map([], |row| {
+            Ok(DriveSchedule {
+                id: row.get(0)?,
+                weekday: row.get(1)?,
+                label: row.get(2)?,
+                generated_until: row.get(3)?,
+            })
+        })?
+        .collect();
+    schedules
+}
+
+pub fn insert_drive_schedule(
     conn: &mut rusqlite::Connection,
-    person: &UpdatePerson,
+    weekday: u32,
+    label: &str,
 ) -> Result<(), rusqlite::Error> {
-    // theoretically possible to check if the person ID actually matched an entry, but just
-    // omitting here
     conn.execute(
-        "UPDATE person
-        SET prename = :prename, name = :name, email = :email, is_visible = :is_visible
-        WHERE person_id = :id",
+        "INSERT INTO drive_schedule (weekday, label)
+        VALUES (:weekday, :label)",
         named_params! {
-            ":id": person.id,
-            ":prename": person.prename,
-            ":name": person.name,
-            ":email": person.email.to_string(),
-            ":is_visible": person.is_visible,
+            ":weekday": weekday,
+            ":label": label,
         },
     )?;
     Ok(())
 }
 
-/// Deletes a person entry by ID and all linked registrations. **This action is irreversible.**
-pub fn delete_person(
+/// Deletes a schedule by ID. Drives generated by it are kept.
+pub fn delete_drive_schedule(
     conn: &mut rusqlite::Connection,
-    person_id: i64,
+    id: i64,
 ) -> Result<(), rusqlite::Error> {
     conn.execute(
-        "DELETE FROM person
-        WHERE person_id == :id",
+        "DELETE FROM drive_schedule
+        WHERE schedule_id == :id",
         named_params! {
-            ":id": person_id,
+            ":id": id,
         },
     )?;
     Ok(())
 }
 
-/// Lists _all_ settings currently held, and uses [`stringify_value`] the values.
-pub fn all_settings(
-    conn: &mut rusqlite::Connection,
-) -> Result<BTreeMap<String, String>, rusqlite::Error> {
-    let mut statement = conn.prepare(
-        "SELECT name, value
-        FROM settings",
-    )?;
+/// A range of dates in which no drives are generated from schedules, like school holidays.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ScheduleException {
+    pub id: i64,
 
-    let settings = statement
-        .query_map([], |row| Ok((row.get(0)?, stringify_value(row.get(1)?))))?
-        .collect();
+    /// First date of the range, inclusive.
+    pub first_date: chrono::NaiveDate,
 
-    settings
+    /// Last date of the range, inclusive.
+    pub last_date: chrono::NaiveDate,
+
+    /// A note on why there are no drives, purely informational.
+    pub note: String,
 }
 
-/// Retrieves a setting stored in the database.
-pub fn get_setting(
-    conn: &mut rusqlite::Connection,
-    name: impl AsRef<str>,
-) -> Result<Value, rusqlite::Error> {
+pub fn list_schedule_exceptions(
+    conn: &rusqlite::Connection,
+) -> Result<Vec<ScheduleException>, rusqlite::Error> {
+    let mut statement = conn.prepare(
+        "SELECT exception_id, first_date, last_date, note
+        FROM schedule_exception
+        ORDER BY first_date ASC",
+    )?;
+    let exceptions = statement
+        .query_map([], |row| {
+            Ok(ScheduleException {
+                id: row.get(0)?,
+                first_date: row.get(1)?,
+                last_date: row.get(2)?,
+                note: row.get(3)?,
+            })
+        })?
+        .collect();
+    exceptions
+}
+
+pub fn insert_schedule_exception(
+    conn: &mut rusqlite::Connection,
+    first_date: chrono::NaiveDate,
+    last_date: chrono::NaiveDate,
+    note: &str,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO schedule_exception (first_date, last_date, note)
+        VALUES (:first_date, :last_date, :note)",
+        named_params! {
+            ":first_date": first_date,
+            ":last_date": last_date,
+            ":note": note,
+        },
+    )?;
+    Ok(())
+}
+
+pub fn delete_schedule_exception(
+    conn: &mut rusqlite::Connection,
+    id: i64,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "DELETE FROM schedule_exception
+        WHERE exception_id == :id",
+        named_params! {
+            ":id": id,
+        },
+    )?;
+    Ok(())
+}
+
+/// Creates the drives of all schedules from `today` up to `schedule-weeks-ahead` weeks in the
+/// future. Dates inside of a [`ScheduleException`] are skipped, as are drives which exist already.
+/// Generated drives get the default deadline and registration cap, just like drives created by
+/// hand.
+///
+/// Returns how many drives have been created.
+pub fn generate_scheduled_drives(
+    conn: &mut rusqlite::Connection,
+    today: chrono::NaiveDate,
+) -> Result<usize, rusqlite::Error> {
+    let tx = conn.transaction()?;
+
+    let weeks_ahead = settings::SCHEDULE_WEEKS_AHEAD
+        .get_integer(&tx)?
+        .unwrap_or_default()
+        .max(0) as u64;
+    let until = today + chrono::Days::new(7 * weeks_ahead);
+    let exceptions = list_schedule_exceptions(&tx)?;
+
+    let mut created = 0;
+    for schedule in list_drive_schedules(&tx)? {
+        let mut date = schedule
+            .generated_until
+            .and_then(|last| last.succ_opt())
+            .map_or(today, |next| next.max(today));
+
+        while date <= until {
+            let skipped = date.weekday().num_days_from_monday() != schedule.weekday
+                || exceptions
+                    .iter()
+                    .any(|exception| exception.first_date <= date && date <= exception.last_date);
+
+            if !skipped {
+                let deadline = default_deadline(&tx, date)?;
+                match insert_new_drive(&tx, date, &schedule.label, deadline, None) {
+                    Ok(()) => created += 1,
+                    Err(InsertDriveError::AlreadyExists) => (),
+                    Err(InsertDriveError::RusqliteError(err)) => return Err(err),
+                }
+            }
+
+            date = date.succ_opt().expect("reached the end of time");
+        }
+
+        tx.execute(
+            "UPDATE drive_schedule
+            SET generated_until = :until
+            WHERE schedule_id == :id
+                AND (generated_until IS NULL OR generated_until < :until)",
+            named_params! {
+                ":until": until,
+                ":id": schedule.id,
+            },
+        )?;
+    }
+
+    tx.commit()?;
+    Ok(created)
+}
+
+#[derive(Debug, Error)]
+pub enum PersonCreationError {
+    #[error("Email is already used")]
+    EmailAlreadyInUse,
+    #[error("Database or query error: {0}")]
+    RusqliteError(#[from] rusqlite::Error),
+}
+
+#[derive(Clone, Debug)]
+pub struct NewPerson {
+    pub prename: String,
+    pub name: String,
+    pub email: Address,
+}
+
+/// Inserts a new person into the database and returns their ID. The email is not checked for
+/// validity.
+///
+/// The person is recorded in the audit log as created by `actor_id`. Meant to be called inside of
+/// a transaction, so there's no person without their entry.
+pub fn insert_new_person(
+    conn: &rusqlite::Connection,
+    person: &NewPerson,
+    actor_id: Option<i64>,
+) -> Result<i64, PersonCreationError> {
+    let inserted: Result<(), PersonCreationError> = match_constraint_violation!(
+        conn.execute(
+            "INSERT INTO person (prename, name, email, is_visible)
+            VALUES (:prename, :name, :email, true)",
+            named_params! {
+                ":prename": person.prename,
+                ":name": person.name,
+                ":email": person.email.to_string(),
+            },
+        ),
+        PersonCreationError::EmailAlreadyInUse
+    );
+    inserted?;
+
+    let person_id = conn.last_insert_rowid();
+    audit::record(
+        conn,
+        actor_id,
+        AuditAction::PersonCreation,
+        AuditTarget::Person(person_id),
+        serde_json::Value::Null,
+        json!({
+            "prename": person.prename,
+            "name": person.name,
+            "email": person.email.to_string(),
+        }),
+    )?;
+    Ok(person_id)
+}
+
+#[derive(Clone, Debug)]
+pub struct UpdatePerson {
+    pub id: i64,
+    pub prename: String,
+    pub name: String,
+    pub email: Address,
+    pub is_visible: bool,
+    pub group_id: Option<i64>,
+}
+
+/// Updates a person entry by ID. The email is not checked for validity.
+///
+/// Changes are recorded in the audit log as made by `actor_id`, moving the person to another group
+/// separately from the rest. Meant to be called inside of a transaction, so there's no change
+/// without its entry.
+pub fn update_person(
+    conn: &rusqlite::Connection,
+    person: &UpdatePerson,
+    actor_id: Option<i64>,
+) -> Result<(), rusqlite::Error> {
+    let group_before: Option<Option<i64>> = conn
+        .query_row(
+            "SELECT group_id
+            FROM person
+            WHERE person_id == :id",
+            named_params! { ":id": person.id },
+            |row| row.get(0),
+        )
+        .optional()?;
+    // nothing to update if the person doesn't exist
+    let Some(group_before) = group_before else {
+        return Ok(());
+    };
+    let before = person_details(conn, person.id)?;
+
+    conn.execute(
+        "UPDATE person
+        SET prename = :prename, name = :name, email = :email, is_visible = :is_visible,
+            group_id = :group_id
+        WHERE person_id = :id",
+        named_params! {
+            ":id": person.id,
+            ":prename": person.prename,
+            ":name": person.name,
+            ":email": person.email.to_string(),
+            ":is_visible": person.is_visible,
+            ":group_id": person.group_id,
+        },
+    )?;
+
+    let after = person_details(conn, person.id)?;
+    if before != after {
+        audit::record(
+            conn,
+            actor_id,
+            AuditAction::PersonUpdate,
+            AuditTarget::Person(person.id),
+            before,
+            after,
+        )?;
+    }
+    if group_before != person.group_id {
+        audit::record(
+            conn,
+            actor_id,
+            AuditAction::GroupMembership,
+            AuditTarget::Person(person.id),
+            json!({ "group_id": group_before }),
+            json!({ "group_id": person.group_id }),
+        )?;
+    }
+    Ok(())
+}
+
+/// What can be changed about a person besides their group, as JSON for the audit log. Null if the
+/// person doesn't exist.
+fn person_details(
+    conn: &rusqlite::Connection,
+    person_id: i64,
+) -> Result<serde_json::Value, rusqlite::Error> {
+    let details = conn
+        .query_row(
+            "SELECT prename, name, email, is_visible
+            FROM person
+            WHERE person_id == :id",
+            named_params! { ":id": person_id },
+            |row| {
+                Ok(json!({
+                    "prename": row.get::<_, String>(0)?,
+                    "name": row.get::<_, String>(1)?,
+                    "email": row.get::<_, String>(2)?,
+                    "is_visible": row.get::<_, bool>(3)?,
+                }))
+            },
+        )
+        .optional()?;
+    Ok(details.unwrap_or_default())
+}
+
+/// Deletes a person entry by ID and all linked registrations. **This action is irreversible.**
+/// Only the audit log, where the deletion is recorded as made by `actor_id`, keeps their name.
+pub fn delete_person(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+    actor_id: Option<i64>,
+) -> Result<(), rusqlite::Error> {
+    let tx = conn.transaction()?;
+    let before = tx
+        .query_row(
+            "SELECT prename, name, email
+            FROM person
+            WHERE person_id == :id",
+            named_params! { ":id": person_id },
+            |row| {
+                Ok(json!({
+                    "prename": row.get::<_, String>(0)?,
+                    "name": row.get::<_, String>(1)?,
+                    "email": row.get::<_, String>(2)?,
+                }))
+            },
+        )
+        .optional()?;
+    let Some(before) = before else {
+        return Ok(());
+    };
+
+    tx.execute(
+        "DELETE FROM person
+        WHERE person_id == :id",
+        named_params! {
+            ":id": person_id,
+        },
+    )?;
+    audit::record(
+        &tx,
+        actor_id,
+        AuditAction::PersonDeletion,
+        AuditTarget::Person(person_id),
+        before,
+        serde_json::Value::Null,
+    )?;
+    tx.commit()
+}
+
+pub fn get_roles(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+) -> Result<Vec<Role>, rusqlite::Error> {
+    roles_of(conn, person_id)
+}
+
+fn roles_of(conn: &rusqlite::Connection, person_id: i64) -> Result<Vec<Role>, rusqlite::Error> {
+    let concatenated = conn.query_row(
+        "SELECT group_concat(role)
+        FROM role
+        WHERE person_id == :person_id",
+        named_params! { ":person_id": person_id },
+        |row| row.get(0),
+    )?;
+    Ok(parse_roles(concatenated))
+}
+
+/// The roles of all persons who have any, by person ID.
+pub fn list_roles(
+    conn: &mut rusqlite::Connection,
+) -> Result<BTreeMap<i64, Vec<Role>>, rusqlite::Error> {
+    let mut statement = conn.prepare(
+        "SELECT person_id, group_concat(role)
+        FROM role
+        GROUP BY person_id",
+    )?;
+    let roles = statement
+        .query_map([], |row| Ok((row.get(0)?, parse_roles(row.get(1)?))))?
+        .collect::<Result<_, _>>()?;
+    Ok(roles)
+}
+
+/// Replaces all roles of the person with the given ones. Changes are recorded in the audit log as
+/// made by `actor_id`.
+pub fn set_roles(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+    roles: &[Role],
+    actor_id: Option<i64>,
+) -> Result<(), rusqlite::Error> {
+    let transaction = conn.transaction()?;
+    replace_roles(&transaction, person_id, roles, actor_id)?;
+    transaction.commit()
+}
+
+/// Like [`set_roles`], but as part of a transaction of the caller, like when creating a person
+/// together with their roles.
+pub fn replace_roles(
+    transaction: &rusqlite::Transaction,
+    person_id: i64,
+    roles: &[Role],
+    actor_id: Option<i64>,
+) -> Result<(), rusqlite::Error> {
+    let before = roles_of(transaction, person_id)?;
+    transaction.execute(
+        "DELETE FROM role
+        WHERE person_id == :person_id",
+        named_params! { ":person_id": person_id },
+    )?;
+    for role in roles {
+        transaction.execute(
+            "INSERT OR IGNORE INTO role (person_id, role)
+            VALUES (:person_id, :role)",
+            named_params! {
+                ":person_id": person_id,
+                ":role": role.as_str(),
+            },
+        )?;
+    }
+
+    let after = roles_of(transaction, person_id)?;
+    if before != after {
+        audit::record(
+            transaction,
+            actor_id,
+            AuditAction::RoleChange,
+            AuditTarget::Person(person_id),
+            json!({ "roles": before }),
+            json!({ "roles": after }),
+        )?;
+    }
+    Ok(())
+}
+
+/// A group of persons, like a class or a dormitory. Drives can be restricted to some groups.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Group {
+    pub id: i64,
+    pub name: String,
+}
+
+pub fn list_groups(conn: &mut rusqlite::Connection) -> Result<Vec<Group>, rusqlite::Error> {
+    let mut statement = conn.prepare(
+        "SELECT group_id, name
+        FROM person_group
+        ORDER BY name",
+    )?;
+    let groups = statement
+        .query_map([], |row| {
+            Ok(Group {
+                id: row.get(0)?,
+                name: row.get(1)?,
+            })
+        })?
+        .collect::<Result<_, _>>()?;
+    Ok(groups)
+}
+
+#[derive(Debug, Error)]
+pub enum InsertGroupError {
+    #[error("Group name is already used")]
+    NameAlreadyInUse,
+    #[error("Database or query error: {0}")]
+    RusqliteError(#[from] rusqlite::Error),
+}
+
+/// Creates a group, recording it in the audit log as created by `actor_id`.
+pub fn insert_group(
+    conn: &mut rusqlite::Connection,
+    name: &str,
+    actor_id: Option<i64>,
+) -> Result<(), InsertGroupError> {
+    let tx = conn.transaction()?;
+    let inserted: Result<(), InsertGroupError> = match_constraint_violation!(
+        tx.execute(
+            "INSERT INTO person_group (name)
+            VALUES (:name)",
+            named_params! { ":name": name },
+        ),
+        InsertGroupError::NameAlreadyInUse
+    );
+    inserted?;
+
+    audit::record(
+        &tx,
+        actor_id,
+        AuditAction::GroupCreation,
+        AuditTarget::Group(tx.last_insert_rowid()),
+        serde_json::Value::Null,
+        json!({ "name": name }),
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// Deletes a group. Its persons are left without group, and drives restricted to it aren't anymore,
+/// which opens them to everyone if it was the only group. The deletion is recorded in the audit log
+/// as made by `actor_id`.
+pub fn delete_group(
+    conn: &mut rusqlite::Connection,
+    group_id: i64,
+    actor_id: Option<i64>,
+) -> Result<(), rusqlite::Error> {
+    let tx = conn.transaction()?;
+    let name: Option<String> = tx
+        .query_row(
+            "SELECT name
+            FROM person_group
+            WHERE group_id == :group_id",
+            named_params! { ":group_id": group_id },
+            |row| row.get(0),
+        )
+        .optional()?;
+    let Some(name) = name else {
+        return Ok(());
+    };
+
+    tx.execute(
+        "DELETE FROM person_group
+        WHERE group_id == :group_id",
+        named_params! { ":group_id": group_id },
+    )?;
+    audit::record(
+        &tx,
+        actor_id,
+        AuditAction::GroupDeletion,
+        AuditTarget::Group(group_id),
+        json!({ "name": name }),
+        serde_json::Value::Null,
+    )?;
+    tx.commit()
+}
+
+/// The group of all persons who are in one, by person ID.
+pub fn list_person_groups(
+    conn: &rusqlite::Connection,
+) -> Result<BTreeMap<i64, i64>, rusqlite::Error> {
+    let mut statement = conn.prepare(
+        "SELECT person_id, group_id
+        FROM person
+        WHERE group_id IS NOT NULL",
+    )?;
+    let groups = statement
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    Ok(groups)
+}
+
+/// The groups of all drives which are restricted to any, by drive ID.
+pub fn list_drive_groups(
+    conn: &mut rusqlite::Connection,
+) -> Result<BTreeMap<i64, Vec<i64>>, rusqlite::Error> {
+    let mut statement = conn.prepare(
+        "SELECT drive_id, group_id
+        FROM drive_group
+        ORDER BY drive_id",
+    )?;
+    let mut groups: BTreeMap<i64, Vec<i64>> = BTreeMap::new();
+    for row in statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))? {
+        let (drive_id, group_id) = row?;
+        groups.entry(drive_id).or_default().push(group_id);
+    }
+    Ok(groups)
+}
+
+/// Restricts the drive to the given groups, no groups at all open it to everyone again. Changes are
+/// recorded in the audit log as made by `actor_id`.
+pub fn set_drive_groups(
+    conn: &mut rusqlite::Connection,
+    drive_id: i64,
+    group_ids: &[i64],
+    actor_id: Option<i64>,
+) -> Result<(), rusqlite::Error> {
+    let transaction = conn.transaction()?;
+    let before = groups_of_drive(&transaction, drive_id)?;
+    transaction.execute(
+        "DELETE FROM drive_group
+        WHERE drive_id == :drive_id",
+        named_params! { ":drive_id": drive_id },
+    )?;
+    for group_id in group_ids {
+        transaction.execute(
+            "INSERT OR IGNORE INTO drive_group (drive_id, group_id)
+            VALUES (:drive_id, :group_id)",
+            named_params! {
+                ":drive_id": drive_id,
+                ":group_id": group_id,
+            },
+        )?;
+    }
+
+    let after = groups_of_drive(&transaction, drive_id)?;
+    if before != after {
+        audit::record(
+            &transaction,
+            actor_id,
+            AuditAction::DriveGroupChange,
+            AuditTarget::Drive(drive_id),
+            json!({ "groups": before }),
+            json!({ "groups": after }),
+        )?;
+    }
+    transaction.commit()
+}
+
+/// The IDs of the groups the drive is restricted to, in ascending order.
+fn groups_of_drive(
+    conn: &rusqlite::Connection,
+    drive_id: i64,
+) -> Result<Vec<i64>, rusqlite::Error> {
+    let mut statement = conn.prepare(
+        "SELECT group_id
+        FROM drive_group
+        WHERE drive_id == :drive_id
+        ORDER BY group_id",
+    )?;
+    let groups = statement
+        .query_map(named_params! { ":drive_id": drive_id }, |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    Ok(groups)
+}
+
+/// When a person wants to get a reminder mail before a registration deadline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReminderPreference {
+    Never,
+    /// Only if the person isn't registered for the drive yet. This is the default.
+    Unregistered,
+    Always,
+}
+
+impl ReminderPreference {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Never => "never",
+            Self::Unregistered => "unregistered",
+            Self::Always => "always",
+        }
+    }
+}
+
+impl std::str::FromStr for ReminderPreference {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "never" => Ok(Self::Never),
+            "unregistered" => Ok(Self::Unregistered),
+            "always" => Ok(Self::Always),
+            _ => Err(()),
+        }
+    }
+}
+
+pub fn get_reminder_preference(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+) -> Result<ReminderPreference, rusqlite::Error> {
+    let name: String = conn.query_row(
+        "SELECT reminders
+        FROM person
+        WHERE person_id == :person_id",
+        named_params! { ":person_id": person_id },
+        |row| row.get(0),
+    )?;
+    Ok(name
+        .parse()
+        .expect("Invalid reminder preference in database!"))
+}
+
+pub fn set_reminder_preference(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+    preference: ReminderPreference,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "UPDATE person
+        SET reminders = :reminders
+        WHERE person_id == :person_id",
+        named_params! {
+            ":person_id": person_id,
+            ":reminders": preference.as_str(),
+        },
+    )?;
+    Ok(())
+}
+
+/// Whether the person wants to get a mail whenever one of their registrations changes.
+pub fn wants_registration_mails(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+) -> Result<bool, rusqlite::Error> {
+    conn.query_row(
+        "SELECT registration_mails
+        FROM person
+        WHERE person_id == :person_id",
+        named_params! { ":person_id": person_id },
+        |row| row.get(0),
+    )
+}
+
+pub fn set_registration_mails(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+    enabled: bool,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "UPDATE person
+        SET registration_mails = :enabled
+        WHERE person_id == :person_id",
+        named_params! {
+            ":person_id": person_id,
+            ":enabled": enabled,
+        },
+    )?;
+    Ok(())
+}
+
+/// Returns the hash of the token for the calendar feed of the person, if they created one.
+pub fn get_calendar_token(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+) -> Result<Option<String>, rusqlite::Error> {
+    conn.query_row(
+        "SELECT calendar_token
+        FROM person
+        WHERE person_id == :person_id",
+        named_params! { ":person_id": person_id },
+        |row| row.get(0),
+    )
+}
+
+/// Replaces the hash of the calendar feed token, invalidating the old link. [`Option::None`] turns
+/// the feed off.
+pub fn set_calendar_token(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+    new_token: Option<String>,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "UPDATE person
+        SET calendar_token = :new_token
+        WHERE person_id == :person_id",
+        named_params! {
+            ":person_id": person_id,
+            ":new_token": new_token,
+        },
+    )?;
+    Ok(())
+}
+
+/// Lists all drives whose deadline is at most `reminder-hours` ahead of `now`, but not yet over,
+/// and for which no reminder has been sent yet. Empty if reminders are disabled.
+pub fn drives_due_for_reminder(
+    conn: &mut rusqlite::Connection,
+    now: chrono::NaiveDateTime,
+) -> Result<Vec<Drive>, rusqlite::Error> {
+    let hours = match settings::REMINDER_HOURS.get_integer(conn)? {
+        Some(hours) if hours > 0 => hours,
+        _ => return Ok(Vec::new()),
+    };
+    let until = now + chrono::Duration::hours(hours);
+
+    let mut statement = conn.prepare(
+        "SELECT drive_id, drivedate, label, deadline, registration_cap,
+            (
+                SELECT count()
+                FROM registration
+                WHERE registered AND registration.drive_id == drive.drive_id
+            ) AS already_registered_count
+        FROM drive
+        WHERE NOT reminder_sent
+            AND :now < deadline
+            AND deadline <= :until
+        ORDER BY deadline ASC",
+    )?;
+    let rows = statement.query_map(
+        named_params! {
+            ":now": now,
+            ":until": until,
+        },
+        |row| {
+            Ok(Drive {
+                id: row.get(0)?,
+                date: row.get(1)?,
+                label: row.get(2)?,
+                deadline: row.get(3)?,
+                registration_cap: row.get(4)?,
+                already_registered_count: row.get(5)?,
+            })
+        },
+    )?;
+    rows.collect()
+}
+
+/// Lists all visible persons the drive is meant for who want a reminder for it according to their
+/// [`ReminderPreference`], together with whether they're registered already.
+pub fn reminder_recipients(
+    conn: &mut rusqlite::Connection,
+    drive_id: i64,
+) -> Result<Vec<(Person, bool)>, rusqlite::Error> {
+    let mut statement = conn.prepare(&format!(
+        "SELECT person.person_id, person.prename, person.name, person.email, person.is_visible,
+            IFNULL(registration.registered, false)
+        FROM person
+        INNER JOIN drive ON (drive.drive_id == :drive_id)
+        LEFT OUTER JOIN registration ON (
+            registration.person_id == person.person_id
+            AND registration.drive_id == drive.drive_id
+        )
+        LEFT OUTER JOIN waitlist ON (
+            waitlist.person_id == person.person_id
+            AND waitlist.drive_id == drive.drive_id
+        )
+        WHERE person.is_visible
+            AND {}
+            AND (
+                person.reminders == 'always'
+                OR (person.reminders == 'unregistered' AND NOT IFNULL(registration.registered, false))
+            )
+        ORDER BY person.name",
+        DRIVE_MEANT_FOR_PERSON,
+    ))?;
+    let rows = statement.query_map(named_params! { ":drive_id": drive_id }, |row| {
+        Ok((row_to_person(row)?, row.get(5)?))
+    })?;
+    rows.collect()
+}
+
+pub fn mark_reminder_sent(
+    conn: &mut rusqlite::Connection,
+    drive_id: i64,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "UPDATE drive
+        SET reminder_sent = true
+        WHERE drive_id == :drive_id",
+        named_params! { ":drive_id": drive_id },
+    )?;
+    Ok(())
+}
+
+/// A one-use token allowing a person to log in and register for one specific drive at once, sent
+/// out with reminders.
+#[derive(Debug)]
+pub struct QuickRegistration {
+    pub id: i64,
+    pub person_id: i64,
+    pub drive_id: i64,
+
+    /// The hashed token.
+    pub token: String,
+
+    /// UNIX timestamp after which the token is invalid.
+    pub token_expiration: i64,
+}
+
+/// Stores a new quick registration token and returns its ID.
+pub fn insert_quick_registration(
+    conn: &mut rusqlite::Connection,
+    person_id: i64,
+    drive_id: i64,
+    hashed_token: String,
+    token_expiration: i64,
+) -> Result<i64, rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO quick_registration (person_id, drive_id, token, token_expiration)
+        VALUES (:person_id, :drive_id, :token, :token_expiration)",
+        named_params! {
+            ":person_id": person_id,
+            ":drive_id": drive_id,
+            ":token": hashed_token,
+            ":token_expiration": token_expiration,
+        },
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn get_quick_registration(
+    conn: &mut rusqlite::Connection,
+    id: i64,
+) -> Result<Option<QuickRegistration>, rusqlite::Error> {
+    conn.query_row(
+        "SELECT quick_registration_id, person_id, drive_id, token, token_expiration
+        FROM quick_registration
+        WHERE quick_registration_id == :id",
+        named_params! { ":id": id },
+        |row| {
+            Ok(QuickRegistration {
+                id: row.get(0)?,
+                person_id: row.get(1)?,
+                drive_id: row.get(2)?,
+                token: row.get(3)?,
+                token_expiration: row.get(4)?,
+            })
+        },
+    )
+    .optional()
+}
+
+/// Invalidates a quick registration token. Returns whether it still existed, which only ever is
+/// the case for one caller, so a token can't be used twice.
+pub fn delete_quick_registration(
+    conn: &mut rusqlite::Connection,
+    id: i64,
+) -> Result<bool, rusqlite::Error> {
+    let deleted = conn.execute(
+        "DELETE FROM quick_registration
+        WHERE quick_registration_id == :id",
+        named_params! { ":id": id },
+    )?;
+    Ok(deleted > 0)
+}
+
+/// Deletes all quick registration tokens which expired before the given UNIX timestamp.
+pub fn delete_expired_quick_registrations(
+    conn: &mut rusqlite::Connection,
+    now: i64,
+) -> Result<usize, rusqlite::Error> {
+    conn.execute(
+        "DELETE FROM quick_registration
+        WHERE token_expiration < :now",
+        named_params! { ":now": now },
+    )
+}
+
+/// Lists _all_ settings currently held, and uses [`stringify_value`] the values.
+pub fn all_settings(
+    conn: &mut rusqlite::Connection,
+) -> Result<BTreeMap<String, String>, rusqlite::Error> {
+    let mut statement = conn.prepare(
+        "SELECT name, value
+        FROM settings",
+    )?;
+
+    let settings = statement
+        .query_map([], |row| Ok((row.get(0)?, stringify_value(row.get(1)?))))?
+        .collect();
+
+    settings
+}
+
+/// Retrieves a setting stored in the database.
+pub fn get_setting(
+    conn: &rusqlite::Connection,
+    name: impl AsRef<str>,
+) -> Result<Value, rusqlite::Error> {
     let mut statement = conn.prepare(
         "SELECT value
         FROM settings
@@ -781,32 +2658,50 @@ pub fn get_setting(
     row.get(0)
 }
 
-/// Updates a setting stored in the database.
+/// Updates a setting stored in the database, recording the change in the audit log as made by
+/// `actor_id`.
 pub fn set_setting(
     conn: &mut rusqlite::Connection,
     name: impl AsRef<str>,
     value: impl ToSql + fmt::Debug,
+    actor_id: Option<i64>,
 ) -> Result<(), rusqlite::Error> {
-    let mut statement = conn.prepare(
+    let tx = conn.transaction()?;
+    // panics if the setting doesn't exist, so the update below always hits a row
+    let before = get_setting(&tx, name.as_ref())?;
+    tx.execute(
         "UPDATE settings
         SET value = :value
-        WHERE name == :name
-        RETURNING true", // dummy value to retrieve whether the update happened or we were lied to
-                         // TODO: .execute returns a usize saying how many rows have been modified,
-                         // could use that instead
+        WHERE name == :name",
+        named_params! {
+            ":name": name.as_ref(),
+            ":value": value,
+        },
     )?;
-    let mut query = statement.query(named_params! {
-        ":name": name.as_ref(),
-        ":value": value,
-    })?;
 
-    match query.next()? {
-        None => panic!(
-            "expected '{:?}' to exist to insert '{:?}', found nothing in database",
-            name.as_ref(),
-            value,
-        ),
-        Some(_) => Ok(()),
+    let after = get_setting(&tx, name.as_ref())?;
+    if before != after {
+        audit::record(
+            &tx,
+            actor_id,
+            AuditAction::SettingChange,
+            AuditTarget::Setting(name.as_ref()),
+            value_to_json(before),
+            value_to_json(after),
+        )?;
+    }
+    tx.commit()
+}
+
+/// Converts a value of the database to JSON, for the audit log.
+#[doc(hidden)]
+fn value_to_json(value: Value) -> serde_json::Value {
+    match value {
+        Value::Null => serde_json::Value::Null,
+        Value::Integer(number) => number.into(),
+        Value::Real(number) => number.into(),
+        Value::Text(text) => text.into(),
+        Value::Blob(blob) => String::from_utf8_lossy(&blob).into(),
     }
 }
 
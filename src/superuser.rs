@@ -1,51 +1,108 @@
 use {
     super::{
-        authflow::Superuser,
-        date_helpers::{figure_out_exact_deadline, time_to_chrono_date, time_to_chrono_datetime},
-        format_date, server_error,
+        audit::{self, AuditAction, AuditEntry, AuditFilter},
+        authflow::{self, Driver, Registrar, Staff, Superuser},
+        config::Config,
+        date_helpers::{german_weekday, time_to_chrono_date, time_to_chrono_datetime},
+        export::{self, ExportFormat, Table},
+        format_labeled_date,
+        mail::Mailer,
+        notifications::{notify_promoted, notify_registration_change, RegistrationChange},
+        pdf,
+        person_import::{self, ImportError},
+        server_error,
+        settings::{Setting, SettingType, SETTINGS},
         sql_interface::{
-            self, DriveFilter, InsertDriveError, Person, Registration, SearchPersonBy,
-            SearchRegistrationsBy, UpdateDriveError, VisibilityFilter,
+            self, ApplyRegistrationError, DriveFilter, Group, InsertDriveError, InsertGroupError,
+            Person, PersonCreationError, Role, SearchPersonBy, SearchRegistrationsBy,
+            UpdateDriveError, VisibilityFilter,
         },
         BususagesDBConn,
     },
-    chrono::Utc,
+    chrono::{Datelike, Utc},
     rocket::{
         form::{Form, Lenient, Strict},
+        fs::TempFile,
+        http::{ContentType, CookieJar, Header},
         request::FlashMessage,
         response::{Flash, Redirect},
+        tokio::fs,
+        State,
     },
     rocket_dyn_templates::{context, Template},
-    rusqlite::types::Value,
     serde::Serialize,
 };
 
 #[get("/superuser")]
-pub async fn panel(flash: Option<FlashMessage<'_>>, _superuser: Superuser) -> Template {
+pub async fn panel(flash: Option<FlashMessage<'_>>, staff: Staff) -> Template {
     #[derive(Debug, Serialize)]
     struct Context {
         flash: Option<String>,
+        is_driver: bool,
+        is_registrar: bool,
+        is_admin: bool,
     }
 
     Template::render(
         "superuser-panel",
         &Context {
             flash: flash.map(|flash| flash.message().to_string()),
+            is_driver: staff.has_role(Role::Driver),
+            is_registrar: staff.has_role(Role::Registrar),
+            is_admin: staff.has_role(Role::Admin),
         },
     )
 }
 
+/// English weekday names, indexed like the `weekday` of a [`sql_interface::DriveSchedule`].
+const WEEKDAYS: [&str; 7] = [
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+    "Sunday",
+];
+
+/// A group to choose in a select, with whether it's selected already.
+#[derive(Debug, Serialize)]
+struct GroupChoice {
+    id: i64,
+    name: String,
+    selected: bool,
+}
+
+fn group_choices(groups: &[Group], selected: impl Fn(i64) -> bool) -> Vec<GroupChoice> {
+    groups
+        .iter()
+        .map(|group| GroupChoice {
+            id: group.id,
+            name: group.name.clone(),
+            selected: selected(group.id),
+        })
+        .collect()
+}
+
 #[get("/drives")]
 pub async fn drives_panel(
     conn: BususagesDBConn,
     flash: Option<FlashMessage<'_>>,
-    _superuser: Superuser,
+    driver: Driver,
 ) -> Result<Template, Flash<Redirect>> {
-    #[derive(Clone, Debug, Serialize)]
+    #[derive(Debug, Serialize)]
+    struct TemplateSchedule {
+        weekday_name: &'static str,
+        schedule: sql_interface::DriveSchedule,
+    }
+
+    #[derive(Debug, Serialize)]
     struct TemplateDrive {
-        date: chrono::NaiveDate,
-        deadline: Option<chrono::NaiveDateTime>,
-        id: i64,
+        #[serde(flatten)]
+        drive: sql_interface::Drive,
+        groups: Vec<GroupChoice>,
+        /// The names of the groups the drive is restricted to, empty if it's open to everyone.
+        restricted_to: String,
     }
 
     let drives = conn.run(sql_interface::list_drives).await.map_err(|err| {
@@ -55,70 +112,434 @@ pub async fn drives_panel(
         )
     })?;
 
+    let (groups, mut drive_groups) = conn
+        .run(|c| {
+            Ok::<_, rusqlite::Error>((
+                sql_interface::list_groups(c)?,
+                sql_interface::list_drive_groups(c)?,
+            ))
+        })
+        .await
+        .map_err(|err| {
+            server_error(
+                format!("Error while listing groups of drives: {}", err),
+                "an error occured while listing groups of drives",
+            )
+        })?;
+    let mut to_template = |drives: Vec<sql_interface::Drive>| -> Vec<TemplateDrive> {
+        drives
+            .into_iter()
+            .map(|drive| {
+                let group_ids = drive_groups.remove(&drive.id).unwrap_or_default();
+                let groups = group_choices(&groups, |id| group_ids.contains(&id));
+                let restricted_to = groups
+                    .iter()
+                    .filter(|group| group.selected)
+                    .map(|group| group.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                TemplateDrive {
+                    drive,
+                    groups,
+                    restricted_to,
+                }
+            })
+            .collect()
+    };
+    let future_drives = to_template(drives.future);
+    let past_drives = to_template(drives.past);
+
+    let (schedules, exceptions) = conn
+        .run(|c| {
+            Ok::<_, rusqlite::Error>((
+                sql_interface::list_drive_schedules(c)?,
+                sql_interface::list_schedule_exceptions(c)?,
+            ))
+        })
+        .await
+        .map_err(|err| {
+            server_error(
+                format!("Error while listing drive schedules: {}", err),
+                "an error occured while listing drive schedules",
+            )
+        })?;
+    let schedules: Vec<_> = schedules
+        .into_iter()
+        .map(|schedule| TemplateSchedule {
+            weekday_name: WEEKDAYS[schedule.weekday as usize % 7],
+            schedule,
+        })
+        .collect();
+
     Ok(Template::render(
         "drives-panel",
         context! {
             flash: flash.map(|flash| flash.message().to_string()),
-            future_drives: drives.future,
-            past_drives: drives.past,
+            future_drives,
+            past_drives,
+            has_groups: !groups.is_empty(),
+            schedules,
+            exceptions,
+            is_admin: driver.has_role(Role::Admin),
         },
     ))
 }
 
-#[get("/drive/list?<date>")]
+/// The printable list of a drive. With `split`, every group gets its own table on its own page.
+#[get("/drive/list?<id>&<split>")]
 pub async fn introspect_drive(
     conn: BususagesDBConn,
-    date: time::Date,
-    _superuser: Superuser,
+    id: i64,
+    split: Option<bool>,
+    _driver: Driver,
 ) -> Result<Template, Flash<Redirect>> {
+    #[derive(Debug, Serialize)]
+    struct TemplateGroup {
+        name: Option<String>,
+        registrations: Vec<TemplateRegistration>,
+    }
+
     #[derive(Debug, Serialize)]
     struct Context {
-        registrations: Vec<Registration>,
+        groups: Vec<TemplateGroup>,
+        drive_id: i64,
+        split: bool,
+        has_groups: bool,
         pretty_date: String,
         now: String,
     }
 
-    let pretty_date = super::format_date(time_to_chrono_date(date));
-    let registrations = conn
-        .run(move |c| {
-            sql_interface::search_registrations(
-                c,
-                &SearchRegistrationsBy::Date(time_to_chrono_date(date)),
+    let drive = conn
+        .run(move |c| sql_interface::get_drive(c, id))
+        .await
+        .map_err(|err| {
+            server_error(
+                &format!("Error querying drive {}: {}", id, err),
+                "an error occured while listing registrations",
             )
-        })
+        })?
+        .ok_or_else(|| Flash::error(Redirect::to(uri!(drives_panel)), "Unknown drive."))?;
+    let pretty_date = format_labeled_date(drive.date, &drive.label);
+
+    let registrations = conn
+        .run(move |c| sql_interface::search_registrations(c, &SearchRegistrationsBy::DriveId(id)))
         .await
         .map_err(|err| {
             server_error(
-                &format!("Error listing registrations for date {}: {}", date, err),
+                &format!("Error listing registrations for drive {}: {}", id, err),
                 "an error occured while listing registrations",
             )
         })?;
+    let has_groups = registrations.iter().any(|r| r.group.is_some());
+    let split = split.unwrap_or(false) && has_groups;
+
+    // registrations are sorted by group already, so every group is one run of them
+    let mut groups: Vec<TemplateGroup> = Vec::new();
+    for registration in registrations {
+        match groups.last_mut() {
+            Some(group) if !split || group.name == registration.group => {
+                group.registrations.push(registration.into())
+            }
+            _ => groups.push(TemplateGroup {
+                name: registration.group.clone().filter(|_| split),
+                registrations: vec![registration.into()],
+            }),
+        }
+    }
 
     Ok(Template::render(
         "list",
         &Context {
-            registrations,
+            groups,
+            drive_id: id,
+            split,
+            has_groups,
             pretty_date,
             now: Utc::now().format("%A, %d.%m.%Y %H:%M:%S").to_string(),
         },
     ))
 }
 
+/// A file which is downloaded instead of shown in the browser.
+#[derive(Responder)]
+pub struct Download {
+    data: Vec<u8>,
+    content_type: ContentType,
+    disposition: Header<'static>,
+}
+
+impl Download {
+    /// `name` is the filename without extension, everything but ASCII letters and digits is
+    /// replaced so it's safe in a header.
+    pub fn new(data: Vec<u8>, content_type: ContentType, name: &str, extension: &str) -> Self {
+        let name: String = name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+            .collect();
+        Self {
+            data,
+            content_type,
+            disposition: Header::new(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}.{}\"", name, extension),
+            ),
+        }
+    }
+}
+
+/// Writes the table in the wanted format and offers it as download.
+fn export_table(
+    table: Table,
+    format: ExportFormat,
+    name: &str,
+) -> Result<Download, Flash<Redirect>> {
+    let data = table.export(format, name).map_err(|err| {
+        server_error(
+            format!("Error while exporting {}: {}", name, err),
+            "an error occured while exporting the list",
+        )
+    })?;
+    let content_type = match format {
+        ExportFormat::Csv => ContentType::CSV,
+        ExportFormat::Xlsx => ContentType::new(
+            "application",
+            "vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        ),
+        ExportFormat::Ods => ContentType::new("application", "vnd.oasis.opendocument.spreadsheet"),
+    };
+    Ok(Download::new(data, content_type, name, format.extension()))
+}
+
+/// The list of a drive as CSV or spreadsheet, e.g. for billing.
+#[get("/drive/list/export?<id>&<format>")]
+pub async fn export_drive(
+    conn: BususagesDBConn,
+    id: i64,
+    format: ExportFormat,
+    _driver: Driver,
+) -> Result<Download, Flash<Redirect>> {
+    let drive = conn
+        .run(move |c| sql_interface::get_drive(c, id))
+        .await
+        .map_err(|err| {
+            server_error(
+                &format!("Error querying drive {}: {}", id, err),
+                "an error occured while exporting registrations",
+            )
+        })?
+        .ok_or_else(|| Flash::error(Redirect::to(uri!(drives_panel)), "Unknown drive."))?;
+
+    let registrations = conn
+        .run(move |c| sql_interface::search_registrations(c, &SearchRegistrationsBy::DriveId(id)))
+        .await
+        .map_err(|err| {
+            server_error(
+                &format!("Error listing registrations for drive {}: {}", id, err),
+                "an error occured while exporting registrations",
+            )
+        })?;
+
+    let name = match drive.label.as_str() {
+        "" => format!("busliste-{}", drive.date),
+        label => format!("busliste-{}-{}", drive.date, label),
+    };
+    export_table(export::drive_table(&registrations), format, &name)
+}
+
+/// The list of a drive as PDF, so drivers get the same printout from every browser.
+#[get("/drive/list/pdf?<id>")]
+pub async fn export_drive_pdf(
+    conn: BususagesDBConn,
+    id: i64,
+    _driver: Driver,
+) -> Result<Download, Flash<Redirect>> {
+    let drive = conn
+        .run(move |c| sql_interface::get_drive(c, id))
+        .await
+        .map_err(|err| {
+            server_error(
+                &format!("Error querying drive {}: {}", id, err),
+                "an error occured while rendering the list",
+            )
+        })?
+        .ok_or_else(|| Flash::error(Redirect::to(uri!(drives_panel)), "Unknown drive."))?;
+
+    let registrations = conn
+        .run(move |c| sql_interface::search_registrations(c, &SearchRegistrationsBy::DriveId(id)))
+        .await
+        .map_err(|err| {
+            server_error(
+                &format!("Error listing registrations for drive {}: {}", id, err),
+                "an error occured while rendering the list",
+            )
+        })?;
+
+    // the PDF says "Generiert um …", so the weekday needs to be German as well
+    let now = Utc::now();
+    let generated = format!(
+        "{}, {}",
+        german_weekday(now.weekday()),
+        now.format("%d.%m.%Y %H:%M:%S")
+    );
+    let data = pdf::drive_list(
+        &format_labeled_date(drive.date, &drive.label),
+        &generated,
+        &registrations,
+    )
+    .map_err(|err| {
+        server_error(
+            format!("Error while rendering PDF for drive {}: {}", id, err),
+            "an error occured while rendering the list",
+        )
+    })?;
+
+    let name = match drive.label.as_str() {
+        "" => format!("busliste-{}", drive.date),
+        label => format!("busliste-{}-{}", drive.date, label),
+    };
+    Ok(Download::new(data, ContentType::PDF, &name, "pdf"))
+}
+
 #[derive(Debug, FromForm)]
 pub struct NewDrive {
     date: time::Date,
+    label: String,
+}
+
+/// A registration together with its recorded attendance in a form handlebars can tell apart.
+#[derive(Debug, Serialize)]
+struct TemplateRegistration {
+    attendance: &'static str,
+    registration: sql_interface::Registration,
+}
+
+impl From<sql_interface::Registration> for TemplateRegistration {
+    fn from(registration: sql_interface::Registration) -> Self {
+        Self {
+            attendance: match registration.attended {
+                Some(true) => "Ja",
+                Some(false) => "Nein",
+                None => "",
+            },
+            registration,
+        }
+    }
+}
+
+#[get("/drive/attendance?<id>")]
+pub async fn attendance_panel(
+    conn: BususagesDBConn,
+    id: i64,
+    flash: Option<FlashMessage<'_>>,
+    _driver: Driver,
+) -> Result<Template, Flash<Redirect>> {
+    let drive = conn
+        .run(move |c| sql_interface::get_drive(c, id))
+        .await
+        .map_err(|err| {
+            server_error(
+                &format!("Error querying drive {}: {}", id, err),
+                "an error occured while loading the check-in",
+            )
+        })?
+        .ok_or_else(|| Flash::error(Redirect::to(uri!(drives_panel)), "Unknown drive."))?;
+
+    let registrations: Vec<TemplateRegistration> = conn
+        .run(move |c| sql_interface::search_registrations(c, &SearchRegistrationsBy::DriveId(id)))
+        .await
+        .map_err(|err| {
+            server_error(
+                &format!("Error listing registrations for drive {}: {}", id, err),
+                "an error occured while loading the check-in",
+            )
+        })?
+        .into_iter()
+        .map(TemplateRegistration::from)
+        .collect();
+
+    let present_count = registrations
+        .iter()
+        .filter(|r| r.registration.attended == Some(true))
+        .count();
+
+    Ok(Template::render(
+        "attendance",
+        context! {
+            flash: flash.map(|msg| msg.message().to_string()),
+            drive_id: id,
+            pretty_date: format_labeled_date(drive.date, &drive.label),
+            registered_count: drive.already_registered_count,
+            present_count,
+            registrations,
+        },
+    ))
+}
+
+/// What got observed on the bus for one person.
+#[derive(FromFormField, Debug, Clone, Copy)]
+pub enum AttendanceState {
+    Present,
+    Absent,
+    Unknown,
+}
+
+#[derive(FromForm, Debug, Clone)]
+pub struct AttendanceForm {
+    drive_id: i64,
+    person_id: i64,
+    state: AttendanceState,
+}
+
+#[post("/drive/attendance", data = "<form>")]
+pub async fn update_attendance(
+    conn: BususagesDBConn,
+    form: Form<Strict<AttendanceForm>>,
+    driver: Driver,
+) -> Result<Redirect, Flash<Redirect>> {
+    let AttendanceForm {
+        drive_id,
+        person_id,
+        state,
+    } = form.into_inner().into_inner();
+    let attended = match state {
+        AttendanceState::Present => Some(true),
+        AttendanceState::Absent => Some(false),
+        AttendanceState::Unknown => None,
+    };
+
+    let actor_id = driver.person_id();
+    match conn
+        .run(move |c| {
+            sql_interface::update_attendance(c, person_id, drive_id, attended, Some(actor_id))
+        })
+        .await
+    {
+        Err(ApplyRegistrationError::UnknownDrive) => Err(Flash::error(
+            Redirect::to(uri!(drives_panel)),
+            "Unknown drive, maybe it just got deleted.",
+        )),
+        Err(err) => Err(server_error(
+            format!(
+                "Error while recording attendance of {} on drive {}: {}",
+                person_id, drive_id, err
+            ),
+            "an error occured while recording the attendance",
+        )),
+        Ok(()) => Ok(Redirect::to(uri!(attendance_panel(id = drive_id)))),
+    }
 }
 
 #[post("/drive/new", data = "<form>")]
 pub async fn create_new_drive(
     conn: BususagesDBConn,
     form: Form<Strict<NewDrive>>,
-    _superuser: Superuser,
+    superuser: Superuser,
 ) -> Result<Redirect, Flash<Redirect>> {
     let drive_date = time_to_chrono_date(form.date);
+    let label = form.label.trim().to_string();
 
-    let default_deadline = conn
-        .run(|c| sql_interface::get_setting(c, "default-deadline"))
+    let deadline = conn
+        .run(move |c| sql_interface::default_deadline(c, drive_date))
         .await
         .map_err(|err| {
             server_error(
@@ -126,25 +547,20 @@ pub async fn create_new_drive(
                 "ein Fehler trat auf, während ich nach den Einstellungen geschaut habe",
             )
         })?;
-    let deadline = match default_deadline {
-        Value::Integer(deadline_weekday) => Some(figure_out_exact_deadline(
-            deadline_weekday as u32,
-            drive_date,
-        )),
-        Value::Null => None,
-        _ => unreachable!(
-            "validation messed up, contained '{:?}' which is not an integer nor null",
-            default_deadline
-        ),
-    };
 
+    let actor_id = superuser.person_id();
     match conn
-        .run(move |c| sql_interface::insert_new_drive(c, drive_date, deadline))
+        .run(move |c| {
+            let tx = c.transaction()?;
+            sql_interface::insert_new_drive(&tx, drive_date, &label, deadline, Some(actor_id))?;
+            tx.commit()?;
+            Ok::<_, InsertDriveError>(())
+        })
         .await
     {
         Err(InsertDriveError::AlreadyExists) => Err(Flash::error(
             Redirect::to(uri!(drives_panel)),
-            "Bus drive already exists!",
+            "Bus drive already exists! Use a different label to add another drive on the same date.",
         )),
         Err(err) => {
             return Err(server_error(
@@ -165,10 +581,11 @@ pub struct DeleteDrive {
 pub async fn delete_drive(
     conn: BususagesDBConn,
     form: Form<Strict<DeleteDrive>>,
-    _superuser: Superuser,
+    superuser: Superuser,
 ) -> Result<Redirect, Flash<Redirect>> {
     let drive_id = form.id;
-    conn.run(move |c| sql_interface::delete_drive(c, drive_id))
+    let actor_id = superuser.person_id();
+    conn.run(move |c| sql_interface::delete_drive(c, drive_id, Some(actor_id)))
         .await
         .map(|_| Redirect::to(uri!(drives_panel)))
         .map_err(|err| {
@@ -186,6 +603,7 @@ pub async fn delete_drive(
 pub struct UpdateDrive {
     id: i64,
     date: time::Date,
+    label: String,
     deadline: time::PrimitiveDateTime,
     registration_cap: Option<u32>,
 }
@@ -193,101 +611,622 @@ pub struct UpdateDrive {
 #[post("/drive/update", data = "<update>")]
 pub async fn update_deadline(
     conn: BususagesDBConn,
+    mailer: &State<Mailer>,
     update: Option<Form<Strict<UpdateDrive>>>,
-    _superuser: Superuser,
+    superuser: Superuser,
 ) -> Result<Flash<Redirect>, Flash<Redirect>> {
     let Some(update) = update else {
         return Err(Flash::error(Redirect::to(uri!(drives_panel)), "Please fill all fields."));
     };
 
-    let update = sql_interface::Drive {
-        id: update.id,
-        date: time_to_chrono_date(update.date),
-        deadline: Some(time_to_chrono_datetime(update.deadline)),
-        registration_cap: update.registration_cap,
-        already_registered_count: 0,
-    };
+    let update = sql_interface::Drive {
+        id: update.id,
+        date: time_to_chrono_date(update.date),
+        label: update.label.trim().to_string(),
+        deadline: Some(time_to_chrono_datetime(update.deadline)),
+        registration_cap: update.registration_cap,
+        already_registered_count: 0,
+    };
+
+    let closure_update = update.clone();
+    let actor_id = superuser.person_id();
+    let promoted = conn
+        .run(move |c| sql_interface::update_drive_deadline(c, closure_update, Some(actor_id)))
+        .await
+        .map_err(|err| match err {
+            UpdateDriveError::AlreadyExists => Flash::error(
+                Redirect::to(uri!(drives_panel)),
+                "Es existiert bereits ein Drive mit diesem Datum und Label, nichts geändert.",
+            ),
+            UpdateDriveError::RusqliteError(err) => server_error(
+                format!(
+                    "Error while updating drive {} to deadline {:?}: {}",
+                    update.id, update.deadline, err,
+                ),
+                "ein Fehler trat während der Aktualisierung der Deadline auf",
+            ),
+        })?;
+    notify_promoted(&conn, mailer, update.id, promoted).await;
+
+    Ok(Flash::success(
+        Redirect::to(uri!(drives_panel)),
+        "Änderungen angewandt.",
+    ))
+}
+
+#[derive(Debug, FromForm)]
+pub struct NewSchedule {
+    #[field(validate = range(0..7))]
+    weekday: u32,
+    label: String,
+}
+
+#[post("/drive/schedule/new", data = "<form>")]
+pub async fn create_new_schedule(
+    conn: BususagesDBConn,
+    form: Form<Strict<NewSchedule>>,
+    _superuser: Superuser,
+) -> Result<Redirect, Flash<Redirect>> {
+    let weekday = form.weekday;
+    let label = form.label.trim().to_string();
+    conn.run(move |c| sql_interface::insert_drive_schedule(c, weekday, &label))
+        .await
+        .map(|_| Redirect::to(uri!(drives_panel)))
+        .map_err(|err| {
+            server_error(
+                format!("Error inserting new drive schedule: {}", err),
+                "an error occured while inserting a new drive schedule",
+            )
+        })
+}
+
+#[post("/drive/schedule/delete", data = "<form>")]
+pub async fn delete_schedule(
+    conn: BususagesDBConn,
+    form: Form<Strict<DeleteDrive>>,
+    _superuser: Superuser,
+) -> Result<Redirect, Flash<Redirect>> {
+    let schedule_id = form.id;
+    conn.run(move |c| sql_interface::delete_drive_schedule(c, schedule_id))
+        .await
+        .map(|_| Redirect::to(uri!(drives_panel)))
+        .map_err(|err| {
+            server_error(
+                format!(
+                    "Error while deleting drive schedule: {}\nSchedule ID: {}",
+                    err, schedule_id
+                ),
+                "an error occured while deleting drive schedule",
+            )
+        })
+}
+
+#[derive(Debug, FromForm)]
+pub struct NewScheduleException {
+    first_date: time::Date,
+    last_date: time::Date,
+    note: String,
+}
+
+#[post("/drive/exception/new", data = "<form>")]
+pub async fn create_new_schedule_exception(
+    conn: BususagesDBConn,
+    form: Form<Strict<NewScheduleException>>,
+    _superuser: Superuser,
+) -> Result<Redirect, Flash<Redirect>> {
+    let first_date = time_to_chrono_date(form.first_date);
+    let last_date = time_to_chrono_date(form.last_date);
+    if last_date < first_date {
+        return Err(Flash::error(
+            Redirect::to(uri!(drives_panel)),
+            "The exception has to end after it starts.",
+        ));
+    }
+
+    let note = form.note.trim().to_string();
+    conn.run(move |c| sql_interface::insert_schedule_exception(c, first_date, last_date, &note))
+        .await
+        .map(|_| Redirect::to(uri!(drives_panel)))
+        .map_err(|err| {
+            server_error(
+                format!("Error inserting new schedule exception: {}", err),
+                "an error occured while inserting a new schedule exception",
+            )
+        })
+}
+
+#[post("/drive/exception/delete", data = "<form>")]
+pub async fn delete_schedule_exception(
+    conn: BususagesDBConn,
+    form: Form<Strict<DeleteDrive>>,
+    _superuser: Superuser,
+) -> Result<Redirect, Flash<Redirect>> {
+    let exception_id = form.id;
+    conn.run(move |c| sql_interface::delete_schedule_exception(c, exception_id))
+        .await
+        .map(|_| Redirect::to(uri!(drives_panel)))
+        .map_err(|err| {
+            server_error(
+                format!(
+                    "Error while deleting schedule exception: {}\nException ID: {}",
+                    err, exception_id
+                ),
+                "an error occured while deleting schedule exception",
+            )
+        })
+}
+
+#[post("/drive/schedule/generate")]
+pub async fn generate_scheduled_drives(
+    conn: BususagesDBConn,
+    _superuser: Superuser,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    let today = Utc::now().naive_utc().date();
+    let created = conn
+        .run(move |c| sql_interface::generate_scheduled_drives(c, today))
+        .await
+        .map_err(|err| {
+            server_error(
+                format!("Error while generating scheduled drives: {}", err),
+                "an error occured while generating scheduled drives",
+            )
+        })?;
+
+    Ok(Flash::success(
+        Redirect::to(uri!(drives_panel)),
+        format!("Generated {} new drive(s).", created),
+    ))
+}
+
+#[derive(Debug, FromForm)]
+pub struct DriveGroups {
+    id: i64,
+    group_ids: Lenient<Vec<i64>>,
+}
+
+/// Restricts a drive to the selected groups, or opens it to everyone if none is selected.
+#[post("/drive/groups", data = "<form>")]
+pub async fn update_drive_groups(
+    conn: BususagesDBConn,
+    form: Form<Strict<DriveGroups>>,
+    superuser: Superuser,
+) -> Result<Redirect, Flash<Redirect>> {
+    let DriveGroups { id, group_ids } = form.into_inner().into_inner();
+    let actor_id = superuser.person_id();
+    conn.run(move |c| sql_interface::set_drive_groups(c, id, &group_ids, Some(actor_id)))
+        .await
+        .map(|_| Redirect::to(uri!(drives_panel)))
+        .map_err(|err| {
+            server_error(
+                format!("Error while setting groups of drive {}: {}", id, err),
+                "an error occured while setting the groups of the drive",
+            )
+        })
+}
+
+/// Just a shorthand for an error flash containing a redirect.
+#[inline]
+fn flash_error(message: &str) -> Flash<Redirect> {
+    Flash::error(Redirect::to(uri!(person_panel)), message)
+}
+
+#[get("/person")]
+pub async fn person_panel(
+    conn: BususagesDBConn,
+    registrar: Registrar,
+    flash: Option<FlashMessage<'_>>,
+) -> Result<Template, Flash<Redirect>> {
+    #[derive(Debug, Serialize)]
+    struct TemplatePerson {
+        #[serde(flatten)]
+        person: Person,
+        driver: bool,
+        registrar: bool,
+        admin: bool,
+        group: Option<String>,
+        groups: Vec<GroupChoice>,
+    }
+
+    #[derive(Debug, Serialize)]
+    struct Context {
+        flash: Option<String>,
+        persons: Vec<TemplatePerson>,
+        groups: Vec<Group>,
+        is_admin: bool,
+    }
+
+    let (persons, mut roles, groups, person_groups) = conn
+        .run(|c| {
+            Ok::<_, rusqlite::Error>((
+                sql_interface::list_all_persons(c, VisibilityFilter::IncludingInvisible)?,
+                sql_interface::list_roles(c)?,
+                sql_interface::list_groups(c)?,
+                sql_interface::list_person_groups(c)?,
+            ))
+        })
+        .await
+        .map_err(|err| {
+            server_error(
+                &format!("Error while listing persons: {}", err),
+                "an error occurred while loading persons",
+            )
+        })?;
+    let persons = persons
+        .into_iter()
+        .map(|person| {
+            let roles = roles.remove(&person.id).unwrap_or_default();
+            let group_id = person_groups.get(&person.id).copied();
+            TemplatePerson {
+                driver: roles.contains(&Role::Driver),
+                registrar: roles.contains(&Role::Registrar),
+                admin: roles.contains(&Role::Admin),
+                group: groups
+                    .iter()
+                    .find(|group| Some(group.id) == group_id)
+                    .map(|group| group.name.clone()),
+                groups: group_choices(&groups, |id| Some(id) == group_id),
+                person,
+            }
+        })
+        .collect();
+
+    Ok(Template::render(
+        "personcontrol",
+        &Context {
+            flash: flash.map(|flash| flash.message().to_string()),
+            persons,
+            groups,
+            is_admin: registrar.has_role(Role::Admin),
+        },
+    ))
+}
+
+#[get("/registrations?<from>&<to>")]
+pub async fn registrations_panel(
+    conn: BususagesDBConn,
+    _driver: Driver,
+    from: Option<time::Date>,
+    to: Option<time::Date>,
+) -> Result<Template, Flash<Redirect>> {
+    #[derive(Debug, Serialize)]
+    struct Context {
+        #[serde(flatten)]
+        counted: sql_interface::CountedRegistrations,
+        from: Option<String>,
+        to: Option<String>,
+    }
+
+    let persons_with_counts = conn
+        .run(move |c| {
+            sql_interface::list_persons_counted_registrations(
+                c,
+                from.map(time_to_chrono_date),
+                to.map(time_to_chrono_date),
+            )
+        })
+        .await
+        .map_err(|err| {
+            server_error(
+                &format!("Error while counting registrations: {}", err),
+                "an error occurred while loading persons",
+            )
+        })?;
+
+    Ok(Template::render(
+        "registrations-panel",
+        Context {
+            counted: persons_with_counts,
+            from: from.map(|date| date.to_string()),
+            to: to.map(|date| date.to_string()),
+        },
+    ))
+}
+
+/// The counted registrations of the registrations panel as CSV or spreadsheet.
+#[get("/registrations/export?<from>&<to>&<format>")]
+pub async fn export_registrations(
+    conn: BususagesDBConn,
+    _driver: Driver,
+    from: Option<time::Date>,
+    to: Option<time::Date>,
+    format: ExportFormat,
+) -> Result<Download, Flash<Redirect>> {
+    let counted = conn
+        .run(move |c| {
+            sql_interface::list_persons_counted_registrations(
+                c,
+                from.map(time_to_chrono_date),
+                to.map(time_to_chrono_date),
+            )
+        })
+        .await
+        .map_err(|err| {
+            server_error(
+                &format!("Error while counting registrations: {}", err),
+                "an error occurred while exporting registrations",
+            )
+        })?;
+
+    let name = format!(
+        "registrations-{}-to-{}",
+        from.map_or("start".to_string(), |date| date.to_string()),
+        to.map_or("end".to_string(), |date| date.to_string()),
+    );
+    export_table(export::registrations_table(&counted), format, &name)
+}
+
+/// Formats the share of no-shows among checked registrations, if anything got checked at all.
+fn no_show_rate(no_shows: i64, checked: i64) -> Option<String> {
+    (checked > 0).then(|| format!("{:.0} %", no_shows as f64 / checked as f64 * 100.0))
+}
+
+#[get("/noshows?<from>&<to>")]
+pub async fn no_shows_panel(
+    conn: BususagesDBConn,
+    _superuser: Superuser,
+    from: Option<time::Date>,
+    to: Option<time::Date>,
+) -> Result<Template, Flash<Redirect>> {
+    #[derive(Debug, Serialize)]
+    struct TemplatePerson {
+        rate: Option<String>,
+        #[serde(flatten)]
+        counts: sql_interface::PersonWithRegistrations,
+    }
+
+    #[derive(Debug, Serialize)]
+    struct TemplateDrive {
+        pretty_date: String,
+        rate: Option<String>,
+        #[serde(flatten)]
+        attendance: sql_interface::DriveAttendance,
+    }
+
+    let (from, to) = (from.map(time_to_chrono_date), to.map(time_to_chrono_date));
+
+    let counted = conn
+        .run(move |c| sql_interface::list_persons_counted_registrations(c, from, to))
+        .await
+        .map_err(|err| {
+            server_error(
+                &format!("Error while counting no-shows per person: {}", err),
+                "an error occurred while loading the no-show report",
+            )
+        })?;
+
+    let drives = conn
+        .run(move |c| sql_interface::list_drives_attendance(c, from, to))
+        .await
+        .map_err(|err| {
+            server_error(
+                &format!("Error while counting no-shows per drive: {}", err),
+                "an error occurred while loading the no-show report",
+            )
+        })?;
+
+    let persons: Vec<_> = counted
+        .persons
+        .into_iter()
+        .map(|counts| TemplatePerson {
+            rate: no_show_rate(counts.no_shows, counts.checked),
+            counts,
+        })
+        .collect();
+    let drives: Vec<_> = drives
+        .into_iter()
+        .map(|attendance| TemplateDrive {
+            pretty_date: format_labeled_date(attendance.drive.date, &attendance.drive.label),
+            rate: no_show_rate(attendance.no_shows.into(), attendance.checked.into()),
+            attendance,
+        })
+        .collect();
+
+    Ok(Template::render(
+        "noshows-panel",
+        context! {
+            from: from.map(|date| date.to_string()),
+            to: to.map(|date| date.to_string()),
+            no_shows_sum: counted.no_shows_sum,
+            unregistered_shows_sum: counted.unregistered_shows_sum,
+            persons,
+            drives,
+        },
+    ))
+}
+
+/// How many entries the audit panel shows at most, the export has all of them.
+const AUDIT_PANEL_LIMIT: u32 = 500;
 
-    let closure_update = update.clone();
-    conn.run(move |c| sql_interface::update_drive_deadline(c, closure_update))
-        .await
-        .map_err(|err| match err {
-            UpdateDriveError::DateAlreadyExists => Flash::error(
-                Redirect::to(uri!(drives_panel)),
-                "Es existiert bereits ein Drive mit diesem Datum, nichts geändert.",
-            ),
-            UpdateDriveError::RusqliteError(err) => server_error(
-                format!(
-                    "Error while updating drive {} to deadline {:?}: {}",
-                    update.id, update.deadline, err,
-                ),
-                "ein Fehler trat während der Aktualisierung der Deadline auf",
-            ),
-        })
-        .map(|_| Flash::success(Redirect::to(uri!(drives_panel)), "Änderungen angewandt."))
+/// Turns the filter parameters of the audit panel into an [`AuditFilter`]. `to` is inclusive, so
+/// entries until the end of that day are included.
+fn audit_filter(
+    action: Option<&str>,
+    person_id: Option<i64>,
+    drive_id: Option<i64>,
+    from: Option<time::Date>,
+    to: Option<time::Date>,
+) -> AuditFilter {
+    let start_of = |date: chrono::NaiveDate| date.and_hms_opt(0, 0, 0).unwrap().timestamp();
+    AuditFilter {
+        action: action.and_then(|action| action.parse().ok()),
+        person_id,
+        drive_id,
+        since: from.map(|date| start_of(time_to_chrono_date(date))),
+        until: to
+            .and_then(|date| time_to_chrono_date(date).succ_opt())
+            .map(start_of),
+        limit: None,
+    }
 }
 
-/// Just a shorthand for an error flash containing a redirect.
-#[inline]
-fn flash_error(message: &str) -> Flash<Redirect> {
-    Flash::error(Redirect::to(uri!(person_panel)), message)
+/// Who made a change, for showing it.
+fn audit_actor(entry: &AuditEntry) -> String {
+    match (&entry.actor_name, entry.actor_id) {
+        (Some(name), _) => name.clone(),
+        (None, Some(id)) => format!("#{} (deleted)", id),
+        (None, None) => "command line".to_string(),
+    }
 }
 
-#[get("/person")]
-pub async fn person_panel(
+/// What a change was made to, for showing it.
+fn audit_target(entry: &AuditEntry) -> String {
+    let person = match (&entry.target_person_name, entry.target_person_id) {
+        (Some(name), _) => Some(name.clone()),
+        (None, id) => id.map(|id| format!("#{} (deleted)", id)),
+    };
+    let drive = match (entry.target_drive_date, entry.target_drive_id) {
+        (Some(date), _) => Some(format!(
+            "drive on {}",
+            format_labeled_date(date, entry.target_drive_label.as_deref().unwrap_or(""))
+        )),
+        (None, id) => id.map(|id| format!("drive #{} (deleted)", id)),
+    };
+    let group = match (&entry.target_group_name, entry.target_group_id) {
+        (Some(name), _) => Some(format!("group {}", name)),
+        (None, id) => id.map(|id| format!("group #{} (deleted)", id)),
+    };
+    let setting = entry
+        .target_setting
+        .as_ref()
+        .map(|name| format!("setting {}", name));
+    [person, drive, group, setting]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[get("/audit?<action>&<person_id>&<drive_id>&<from>&<to>")]
+pub async fn audit_panel(
     conn: BususagesDBConn,
     _superuser: Superuser,
-    flash: Option<FlashMessage<'_>>,
+    action: Option<&str>,
+    person_id: Option<i64>,
+    drive_id: Option<i64>,
+    from: Option<time::Date>,
+    to: Option<time::Date>,
 ) -> Result<Template, Flash<Redirect>> {
     #[derive(Debug, Serialize)]
-    struct Context {
-        flash: Option<String>,
-        persons: Vec<Person>,
+    struct TemplateEntry {
+        pretty_time: String,
+        actor: String,
+        target: String,
+        #[serde(flatten)]
+        entry: AuditEntry,
     }
 
-    let persons = conn
-        .run(|c| sql_interface::list_all_persons(c, VisibilityFilter::IncludingInvisible))
+    #[derive(Debug, Serialize)]
+    struct FilterOption {
+        value: String,
+        title: String,
+        selected: bool,
+    }
+
+    let filter = AuditFilter {
+        limit: Some(AUDIT_PANEL_LIMIT),
+        ..audit_filter(action, person_id, drive_id, from, to)
+    };
+    let selected_action = filter.action;
+
+    let (entries, persons, drives) = conn
+        .run(move |c| {
+            Ok::<_, rusqlite::Error>((
+                audit::list(c, &filter)?,
+                sql_interface::list_all_persons(c, VisibilityFilter::IncludingInvisible)?,
+                sql_interface::list_drives(c)?,
+            ))
+        })
         .await
         .map_err(|err| {
             server_error(
-                &format!("Error while listing persons: {}", err),
-                "an error occurred while loading persons",
+                &format!("Error while listing the audit log: {}", err),
+                "an error occurred while loading the audit log",
             )
         })?;
 
+    let truncated = entries.len() == AUDIT_PANEL_LIMIT as usize;
+    let entries: Vec<_> = entries
+        .into_iter()
+        .map(|entry| TemplateEntry {
+            pretty_time: chrono::NaiveDateTime::from_timestamp_opt(entry.happened, 0)
+                .map(|time| time.format("%d.%m.%Y %H:%M:%S").to_string())
+                .unwrap_or_default(),
+            actor: audit_actor(&entry),
+            target: audit_target(&entry),
+            entry,
+        })
+        .collect();
+
+    let actions: Vec<_> = AuditAction::ALL
+        .into_iter()
+        .map(|option| FilterOption {
+            value: option.as_str().to_string(),
+            title: option.as_str().to_string(),
+            selected: selected_action == Some(option),
+        })
+        .collect();
+    let persons: Vec<_> = persons
+        .into_iter()
+        .map(|person| FilterOption {
+            value: person.id.to_string(),
+            title: format!("{} {}", person.prename, person.name),
+            selected: person_id == Some(person.id),
+        })
+        .collect();
+    let drives: Vec<_> = drives
+        .future
+        .into_iter()
+        .rev()
+        .chain(drives.past)
+        .map(|drive| FilterOption {
+            value: drive.id.to_string(),
+            title: format_labeled_date(drive.date, &drive.label),
+            selected: drive_id == Some(drive.id),
+        })
+        .collect();
+
     Ok(Template::render(
-        "personcontrol",
-        &Context {
-            flash: flash.map(|flash| flash.message().to_string()),
+        "audit-panel",
+        context! {
+            from: from.map(|date| date.to_string()),
+            to: to.map(|date| date.to_string()),
+            limit: AUDIT_PANEL_LIMIT,
+            truncated,
+            entries,
+            actions,
             persons,
+            drives,
         },
     ))
 }
 
-#[get("/registrations?<from>&<to>")]
-pub async fn registrations_panel(
+/// The whole audit log matching the filter as CSV, without the limit of the panel.
+#[get("/audit/export?<action>&<person_id>&<drive_id>&<from>&<to>")]
+pub async fn export_audit_log(
     conn: BususagesDBConn,
     _superuser: Superuser,
+    action: Option<&str>,
+    person_id: Option<i64>,
+    drive_id: Option<i64>,
     from: Option<time::Date>,
     to: Option<time::Date>,
-) -> Result<Template, Flash<Redirect>> {
-    let persons_with_counts = conn
-        .run(move |c| {
-            sql_interface::list_persons_counted_registrations(
-                c,
-                from.map(time_to_chrono_date),
-                to.map(time_to_chrono_date),
-            )
-        })
+) -> Result<Download, Flash<Redirect>> {
+    let filter = audit_filter(action, person_id, drive_id, from, to);
+    let entries = conn
+        .run(move |c| audit::list(c, &filter))
         .await
         .map_err(|err| {
             server_error(
-                &format!("Error while counting registrations: {}", err),
-                "an error occurred while loading persons",
+                &format!("Error while exporting the audit log: {}", err),
+                "an error occurred while exporting the audit log",
             )
         })?;
 
-    Ok(Template::render("registrations-panel", persons_with_counts))
+    export_table(
+        export::audit_table(&entries),
+        ExportFormat::Csv,
+        "audit-log",
+    )
 }
 
 #[derive(Debug, FromForm)]
@@ -313,7 +1252,7 @@ impl TryFrom<NewPerson> for sql_interface::NewPerson {
 pub async fn create_new_person(
     conn: BususagesDBConn,
     form: Form<Strict<NewPerson>>,
-    _superuser: Superuser,
+    superuser: Superuser,
 ) -> Result<Redirect, Flash<Redirect>> {
     let new_person: sql_interface::NewPerson = form
         .into_inner()
@@ -321,8 +1260,14 @@ pub async fn create_new_person(
         .try_into()
         .map_err(|_| flash_error("Invalid email!"))?;
     let debug = new_person.clone();
+    let actor_id = superuser.person_id();
     match conn
-        .run(move |c| sql_interface::insert_new_person(c, &new_person))
+        .run(move |c| {
+            let tx = c.transaction()?;
+            sql_interface::insert_new_person(&tx, &new_person, Some(actor_id))?;
+            tx.commit()?;
+            Ok::<_, PersonCreationError>(())
+        })
         .await
     {
         Err(sql_interface::PersonCreationError::EmailAlreadyInUse) => Err(flash_error(
@@ -343,6 +1288,7 @@ pub struct UpdatePerson {
     name: String,
     email: String,
     is_visible: Lenient<bool>,
+    group_id: Option<i64>,
 }
 
 impl TryFrom<UpdatePerson> for sql_interface::UpdatePerson {
@@ -355,6 +1301,7 @@ impl TryFrom<UpdatePerson> for sql_interface::UpdatePerson {
             name: source.name,
             email: source.email.parse()?,
             is_visible: source.is_visible.into_inner(),
+            group_id: source.group_id,
         })
     }
 }
@@ -363,7 +1310,7 @@ impl TryFrom<UpdatePerson> for sql_interface::UpdatePerson {
 pub async fn update_person(
     conn: BususagesDBConn,
     form: Form<Strict<UpdatePerson>>,
-    _superuser: Superuser,
+    superuser: Superuser,
 ) -> Result<Redirect, Flash<Redirect>> {
     let update_person: sql_interface::UpdatePerson = form
         .into_inner()
@@ -371,13 +1318,169 @@ pub async fn update_person(
         .try_into()
         .map_err(|_| flash_error("Invalid email!"))?;
     let debug = update_person.clone();
-    conn.run(move |c| sql_interface::update_person(c, &update_person))
+    let actor_id = superuser.person_id();
+    conn.run(move |c| {
+        let tx = c.transaction()?;
+        sql_interface::update_person(&tx, &update_person, Some(actor_id))?;
+        tx.commit()
+    })
+    .await
+    .map(|_| Redirect::to(uri!(person_panel)))
+    .map_err(|err| {
+        server_error(
+            &format!("Error while updating person: {}\n{:#?}", err, debug),
+            "an error occured while updating person",
+        )
+    })
+}
+
+#[derive(Debug, FromForm)]
+pub struct ImportForm<'r> {
+    /// Either the uploaded file or, when applying, the content shown in the preview.
+    csv: TempFile<'r>,
+}
+
+async fn read_csv(file: &TempFile<'_>) -> Result<String, Flash<Redirect>> {
+    let path = match file {
+        // the content sent back from the preview is a plain text field, which is kept in memory
+        TempFile::Buffered { content } => return Ok(content.to_string()),
+        TempFile::File { .. } => file.path().expect("uploaded files are stored on disk"),
+    };
+    let csv = fs::read(path).await.map_err(|err| {
+        server_error(
+            format!("Error while opening uploaded CSV file: {}", err),
+            "an error occured while reading the uploaded file",
+        )
+    })?;
+    String::from_utf8(csv)
+        .map_err(|_| flash_error("The file needs to be a UTF-8 encoded CSV file."))
+}
+
+/// Shows what importing the uploaded CSV file would do, without changing anything yet.
+#[post("/person/import", data = "<form>")]
+pub async fn preview_import(
+    conn: BususagesDBConn,
+    form: Form<Strict<ImportForm<'_>>>,
+    _superuser: Superuser,
+) -> Result<Template, Flash<Redirect>> {
+    let csv = read_csv(&form.csv).await?;
+    let source = csv.clone();
+    let plan = conn
+        .run(move |c| person_import::plan_import(c, &source))
+        .await
+        .map_err(|err| {
+            server_error(
+                format!("Error while planning import of persons: {}", err),
+                "an error occured while comparing the file with the database",
+            )
+        })?;
+
+    Ok(Template::render(
+        "person-import",
+        context! {
+            insert_count: plan.inserts.len(),
+            update_count: plan.updates.len(),
+            conflict_count: plan.conflicts.len(),
+            has_changes: plan.has_changes(),
+            plan,
+            csv,
+        },
+    ))
+}
+
+#[post("/person/import/apply", data = "<form>")]
+pub async fn apply_import(
+    conn: BususagesDBConn,
+    form: Form<Strict<ImportForm<'_>>>,
+    superuser: Superuser,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    let csv = read_csv(&form.csv).await?;
+    let actor_id = superuser.person_id();
+    let plan = match conn
+        .run(move |c| person_import::apply_import(c, &csv, Some(actor_id)))
+        .await
+    {
+        Err(ImportError::PersonCreationError(PersonCreationError::EmailAlreadyInUse)) => {
+            return Err(flash_error(
+                "One of the emails got used meanwhile, nothing was imported. Please upload the file again.",
+            ))
+        }
+        Err(err) => {
+            return Err(server_error(
+                format!("Error while importing persons: {}", err),
+                "an error occured while importing persons",
+            ))
+        }
+        Ok(plan) => plan,
+    };
+
+    log::info!(
+        "Superuser {} imported {} new and updated {} existing person(s)",
+        superuser.person_id(),
+        plan.inserts.len(),
+        plan.updates.len()
+    );
+    Ok(Flash::success(
+        Redirect::to(uri!(person_panel)),
+        format!(
+            "Imported {} new and updated {} existing person(s), skipped {} line(s).",
+            plan.inserts.len(),
+            plan.updates.len(),
+            plan.conflicts.len()
+        ),
+    ))
+}
+
+#[derive(Debug, FromForm)]
+pub struct NewGroup {
+    name: String,
+}
+
+#[post("/group/new", data = "<form>")]
+pub async fn create_new_group(
+    conn: BususagesDBConn,
+    form: Form<Strict<NewGroup>>,
+    superuser: Superuser,
+) -> Result<Redirect, Flash<Redirect>> {
+    let name = form.name.trim().to_string();
+    if name.is_empty() {
+        return Err(flash_error("Please enter a name for the group."));
+    }
+
+    let actor_id = superuser.person_id();
+    match conn
+        .run(move |c| sql_interface::insert_group(c, &name, Some(actor_id)))
+        .await
+    {
+        Err(InsertGroupError::NameAlreadyInUse) => {
+            Err(flash_error("A group with this name exists already."))
+        }
+        Err(err) => Err(server_error(
+            format!("Error while inserting new group: {}", err),
+            "an error occured while inserting the new group",
+        )),
+        Ok(()) => Ok(Redirect::to(uri!(person_panel))),
+    }
+}
+
+#[post("/group/delete", data = "<form>")]
+pub async fn delete_group(
+    conn: BususagesDBConn,
+    form: Form<Strict<DeletePerson>>,
+    superuser: Superuser,
+) -> Result<Redirect, Flash<Redirect>> {
+    let group_id = form.id;
+    let actor_id = superuser.person_id();
+    conn.run(move |c| sql_interface::delete_group(c, group_id, Some(actor_id)))
         .await
         .map(|_| Redirect::to(uri!(person_panel)))
         .map_err(|err| {
             server_error(
-                &format!("Error while updating person: {}\n{:#?}", err, debug),
-                "an error occured while updating person",
+                format!(
+                    "Error while deleting group: {}\nGroup ID: {}",
+                    err, group_id
+                ),
+                "an error occured while deleting group",
             )
         })
 }
@@ -387,14 +1490,57 @@ pub struct DeletePerson {
     id: i64,
 }
 
+#[derive(Debug, FromForm)]
+pub struct UpdateRoles {
+    id: i64,
+    driver: Lenient<bool>,
+    registrar: Lenient<bool>,
+    admin: Lenient<bool>,
+}
+
+#[post("/person/roles", data = "<form>")]
+pub async fn update_roles(
+    conn: BususagesDBConn,
+    form: Form<Strict<UpdateRoles>>,
+    superuser: Superuser,
+) -> Result<Redirect, Flash<Redirect>> {
+    let person_id = form.id;
+    let roles: Vec<Role> = [
+        (Role::Driver, *form.driver),
+        (Role::Registrar, *form.registrar),
+        (Role::Admin, *form.admin),
+    ]
+    .into_iter()
+    .filter_map(|(role, granted)| granted.then_some(role))
+    .collect();
+
+    // otherwise it'd be easy to lock everyone out
+    if person_id == superuser.person_id() && !roles.contains(&Role::Admin) {
+        return Err(flash_error("You can't remove your own admin role."));
+    }
+
+    let debug = format!("{:?}", form);
+    let actor_id = superuser.person_id();
+    conn.run(move |c| sql_interface::set_roles(c, person_id, &roles, Some(actor_id)))
+        .await
+        .map(|_| Redirect::to(uri!(person_panel)))
+        .map_err(|err| {
+            server_error(
+                &format!("Error while updating roles: {}\n{}", err, debug),
+                "an error occured while updating roles",
+            )
+        })
+}
+
 #[post("/person/delete", data = "<form>")]
 pub async fn delete_person(
     conn: BususagesDBConn,
     form: Form<Strict<DeletePerson>>,
-    _superuser: Superuser,
+    superuser: Superuser,
 ) -> Result<Redirect, Flash<Redirect>> {
     let person_id = form.id;
-    conn.run(move |c| sql_interface::delete_person(c, person_id))
+    let actor_id = superuser.person_id();
+    conn.run(move |c| sql_interface::delete_person(c, person_id, Some(actor_id)))
         .await
         .map(|_| Redirect::to(uri!(person_panel)))
         .map_err(|err| {
@@ -408,16 +1554,88 @@ pub async fn delete_person(
         })
 }
 
+/// Logs the person out on all devices, e.g. after a device got lost.
+#[post("/person/sessions/revoke", data = "<form>")]
+pub async fn revoke_sessions(
+    conn: BususagesDBConn,
+    form: Form<Strict<DeletePerson>>,
+    superuser: Superuser,
+) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    let person_id = form.id;
+    let actor_id = superuser.person_id();
+    conn.run(move |c| sql_interface::delete_sessions_of_person(c, person_id, Some(actor_id)))
+        .await
+        .map(|revoked| {
+            Flash::success(
+                Redirect::to(uri!(person_panel)),
+                format!("Revoked {} session(s).", revoked),
+            )
+        })
+        .map_err(|err| {
+            server_error(
+                &format!(
+                    "Error while revoking sessions: {}\nPerson ID: {}",
+                    err, person_id
+                ),
+                "an error occured while revoking sessions",
+            )
+        })
+}
+
+/// Shows the dashboard exactly as the person sees it, in a separate read-only session. The
+/// superuser can return to their own session from there.
+#[post("/person/impersonate", data = "<form>")]
+pub async fn impersonate_person(
+    conn: BususagesDBConn,
+    jar: &CookieJar<'_>,
+    config: &State<Config>,
+    form: Form<Strict<DeletePerson>>,
+    superuser: Superuser,
+) -> Result<Redirect, Flash<Redirect>> {
+    let person_id = form.id;
+    let person = conn
+        .run(move |c| sql_interface::search_person(c, &SearchPersonBy::Id(person_id)))
+        .await
+        .map_err(|err| {
+            server_error(
+                &format!(
+                    "Error while searching person to impersonate: {}\nPerson ID: {}",
+                    err, person_id
+                ),
+                "an error occured while searching the person",
+            )
+        })?;
+
+    authflow::impersonate(&conn, jar, config, &superuser, person.id)
+        .await
+        .map_err(|err| {
+            server_error(
+                &format!("Error while starting impersonation session: {}", err),
+                "an error occured while starting the session",
+            )
+        })?;
+
+    log::warn!(
+        "Superuser {} is viewing the site as {} ({} {})",
+        superuser.person_id(),
+        person.id,
+        person.prename,
+        person.name
+    );
+    Ok(Redirect::to(uri!(super::dashboard)))
+}
+
 #[get("/person/list?<id>")]
 pub async fn introspect_person(
     conn: BususagesDBConn,
     id: i64,
-    _superuser: Superuser,
+    _registrar: Registrar,
 ) -> Result<Template, Flash<Redirect>> {
     #[derive(Debug, Serialize)]
-    struct TemplateRegistration {
+    struct DatedRegistration {
         pretty_date: String,
-        registration: sql_interface::Registration,
+        #[serde(flatten)]
+        registration: TemplateRegistration,
     }
 
     let registrations = conn
@@ -456,9 +1674,9 @@ pub async fn introspect_person(
 
     let registrations: Vec<_> = registrations
         .into_iter()
-        .map(|r| TemplateRegistration {
-            pretty_date: format_date(r.drive.date),
-            registration: r,
+        .map(|r| DatedRegistration {
+            pretty_date: format_labeled_date(r.drive.date, &r.drive.label),
+            registration: r.into(),
         })
         .collect();
 
@@ -475,7 +1693,7 @@ pub async fn introspect_person(
 #[derive(FromForm, Debug, Clone)]
 pub struct RegistrationForm {
     id: i64,
-    date: time::Date,
+    drive_id: i64,
     new_state: bool,
 }
 
@@ -483,7 +1701,7 @@ impl RegistrationForm {
     #[must_use]
     pub fn to_registration_update(&self) -> sql_interface::RegistrationUpdate {
         sql_interface::RegistrationUpdate {
-            date: time_to_chrono_date(self.date),
+            drive_id: self.drive_id,
             person_id: self.id,
             registered: self.new_state,
         }
@@ -494,14 +1712,23 @@ impl RegistrationForm {
 #[post("/person/register", data = "<registration>")]
 pub async fn register_person(
     conn: BususagesDBConn,
+    mailer: &State<Mailer>,
     registration: Form<Strict<RegistrationForm>>,
-    _superuser: Superuser,
+    registrar: Registrar,
 ) -> Result<Redirect, Flash<Redirect>> {
     let update = registration.to_registration_update();
-    match conn
-        .run(move |c| sql_interface::update_registration(c, &update))
+    let id = registration.id;
+    let actor_id = registrar.person_id();
+    let applied = match conn
+        .run(move |c| sql_interface::update_registration(c, &update, Some(actor_id)))
         .await
     {
+        Err(ApplyRegistrationError::NotForPerson) => {
+            return Err(Flash::error(
+                Redirect::to(uri!(introspect_person(id = id))),
+                "This drive is restricted to groups the person isn't in.",
+            ))
+        }
         Err(err) => {
             return Err(server_error(
                 &format!(
@@ -511,36 +1738,109 @@ pub async fn register_person(
                 "ein Fehler trat während der Aktualisierung der Anmeldung auf",
             ))
         }
-        _ => (),
+        Ok(applied) => applied,
     };
 
-    let id = registration.id;
+    let drive_id = registration.drive_id;
+    if applied.changed {
+        let change = RegistrationChange::new(registration.new_state, true);
+        notify_registration_change(&conn, mailer, id, drive_id, change).await;
+    }
+    notify_promoted(&conn, mailer, drive_id, applied.promoted).await;
+
     Ok(Redirect::to(uri!(introspect_person(id = id))))
 }
 
+/// A choice in the select of a setting.
+#[derive(Debug, Serialize)]
+struct SettingOption {
+    value: String,
+    label: &'static str,
+    selected: bool,
+}
+
+/// A setting with everything needed to render its form.
+#[derive(Debug, Serialize)]
+struct TemplateSetting {
+    name: &'static str,
+    title: &'static str,
+    description: &'static str,
+    /// One of `text`, `number` and `select`.
+    input: &'static str,
+    min: Option<i64>,
+    max: Option<i64>,
+    options: Vec<SettingOption>,
+    value: String,
+}
+
+impl TemplateSetting {
+    fn new(setting: &'static Setting, value: String) -> Self {
+        let (input, min, max, options) = match setting.kind {
+            SettingType::Text => ("text", None, None, Vec::new()),
+            SettingType::Integer { min, max } => ("number", Some(min), Some(max), Vec::new()),
+            SettingType::OptionalWeekday => {
+                let none = ("".to_string(), "None");
+                let weekdays = WEEKDAYS
+                    .iter()
+                    .enumerate()
+                    .map(|(day, label)| (day.to_string(), *label));
+                let options = std::iter::once(none)
+                    .chain(weekdays)
+                    .map(|(option, label)| SettingOption {
+                        selected: option == value,
+                        value: option,
+                        label,
+                    })
+                    .collect();
+                ("select", None, None, options)
+            }
+        };
+
+        Self {
+            name: setting.name,
+            title: setting.title,
+            description: setting.description,
+            input,
+            min,
+            max,
+            options,
+            value,
+        }
+    }
+}
+
 #[get("/settings")]
 pub async fn settings(
     conn: BususagesDBConn,
     flash: Option<FlashMessage<'_>>,
     _superuser: Superuser,
 ) -> Result<Template, Flash<Redirect>> {
-    let mut settings = conn.run(sql_interface::all_settings).await.map_err(|err| {
+    let mut values = conn.run(sql_interface::all_settings).await.map_err(|err| {
         server_error(
             format!("Error while fetching current setting values: {}", err),
             "ein Fehler trat während des Abfragen der Werte der aktuellen Einstellungen auf",
         )
     })?;
-    settings.insert(
-        "flash".to_string(),
-        flash
-            .map(|flash| flash.message().to_string())
-            .unwrap_or_else(|| "".to_string()),
-    );
-    Ok(Template::render("settings", settings))
+    let settings: Vec<_> = SETTINGS
+        .iter()
+        .filter(|setting| setting.user_visible)
+        .map(|setting| {
+            let value = values.remove(setting.name).unwrap_or_default();
+            TemplateSetting::new(setting, value)
+        })
+        .collect();
+
+    Ok(Template::render(
+        "settings",
+        context! {
+            flash: flash.map(|flash| flash.message().to_string()),
+            settings,
+        },
+    ))
 }
 
 #[derive(FromForm, Debug, Clone)]
-pub struct Setting {
+pub struct SettingUpdate {
     name: String,
     value: String,
 }
@@ -548,41 +1848,27 @@ pub struct Setting {
 #[post("/settings/set", data = "<update>")]
 pub async fn set_setting(
     conn: BususagesDBConn,
-    update: Form<Strict<Setting>>,
-    _superuser: Superuser,
+    update: Form<Strict<SettingUpdate>>,
+    superuser: Superuser,
 ) -> Result<Flash<Redirect>, Flash<Redirect>> {
-    // probably want to perform some additional validation here for new settings, but for now this is fine
-    let value = match update.name.as_ref() {
-        "login-message" => Value::Text(update.value.clone()),
-        "default-deadline" => match (update.value.len(), update.value.chars().next()) {
-            (0, None) => Value::Null,
-            (1, Some('0'..='6')) => Value::Integer(update.value.parse().unwrap()),
-            _ => {
-                return Err(server_error(
-                    format!("User wanted to set default deadline to '{}', which is invalid (validation/UI out of sync?)", update.value),
-                    "ein Fehler trat während der Anwendung der Default-Deadline auf",
-                ))
-            }
-        },
-        "default-registration-cap" => {
-            let cap = update.value.parse::<u32>().map_err(|_| {
-                Flash::error(Redirect::to(uri!(settings)), "Die Zahl ist nicht valide, oder zu groß.")
-            })?;
-            Value::Integer(cap as i64)
-        },
-        _ => {
-            return Err(server_error(
-                format!(
-                    "User wanted to set setting '{}' to '{}', which isn't validated for (but may exist in the database, in that case validation + database are out of sync)",
-                    update.name, update.value
-                ),
-                "ein Fehler trat während des Setzens der Einstellung auf",
-            ));
-        }
+    let Some(setting) = SETTINGS
+        .iter()
+        .find(|setting| setting.name == update.name && setting.user_visible)
+    else {
+        return Err(server_error(
+            format!(
+                "User wanted to set setting '{}' to '{}', which isn't in the registry or hidden",
+                update.name, update.value
+            ),
+            "ein Fehler trat während des Setzens der Einstellung auf",
+        ));
     };
-    let name = update.name.clone();
+    let value = setting
+        .parse(&update.value)
+        .map_err(|err| Flash::error(Redirect::to(uri!(settings)), err.to_string()))?;
 
-    conn.run(move |c| sql_interface::set_setting(c, name, value))
+    let actor_id = superuser.person_id();
+    conn.run(move |c| sql_interface::set_setting(c, setting.name, value, Some(actor_id)))
         .await
         .map_err(|err| {
             server_error(
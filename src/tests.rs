@@ -1,21 +1,80 @@
 use {
+    super::audit::{self, AuditAction, AuditFilter, AuditTarget},
+    super::authflow,
+    super::calendar,
+    super::export::{self, ExportFormat},
+    super::format_labeled_date,
+    super::mail::{Mailer, MemoryTransport},
+    super::migrations,
+    super::notifications::{self, RegistrationChange},
+    super::pdf,
+    super::person_import,
+    super::ratelimit::{Lockout, RateLimiter},
+    super::settings::{self, InvalidSetting, SettingValue},
     super::sql_interface::{
-        self, DriveFilter, NewPerson, RegistrationUpdate,
+        self, Drive, DriveFilter, NewPerson, RegistrationUpdate, ReminderPreference, Role, Scope,
         SearchPersonBy::{Email, Id},
-        SearchRegistrationsBy::{Date, PersonId},
+        SearchRegistrationsBy::{DriveId, PersonId},
         UpdatePerson, VisibilityFilter,
     },
-    chrono::{Days, NaiveDate},
+    super::tokens,
+    chrono::{Days, NaiveDate, Utc},
+    rocket::{
+        http::{ContentType, Header, Status},
+        local::blocking::{Client, LocalResponse},
+    },
     rusqlite::{types::Value, Connection},
+    std::{
+        path::{Path, PathBuf},
+        time::{Duration, Instant},
+    },
 };
 
 /// Creates a fresh empty database with tables defined.
 fn init_db() -> Connection {
     let mut conn = Connection::open_in_memory().unwrap();
+    // just like the connection pool does, the schema relies on cascading deletes
+    conn.execute_batch("PRAGMA foreign_keys = ON").unwrap();
     sql_interface::init_db_if_necessary(&mut conn).unwrap();
     conn
 }
 
+/// The persons most tests work with, in the order [`insert_persons`] inserts them.
+const PERSONS: [(&str, &str); 3] = [("Alice", "Beta"), ("Bob", "Echo"), ("Carol", "Delta")];
+
+/// Inserts the first `n` of [`PERSONS`], each with an email like `alice_beta@non-existent-domain`.
+/// Returns their IDs in that order.
+fn insert_persons(conn: &Connection, n: usize) -> Vec<i64> {
+    PERSONS[..n]
+        .iter()
+        .map(|(prename, name)| {
+            sql_interface::insert_new_person(
+                conn,
+                &NewPerson {
+                    prename: prename.to_string(),
+                    name: name.to_string(),
+                    email: format!(
+                        "{}_{}@non-existent-domain",
+                        prename.to_lowercase(),
+                        name.to_lowercase()
+                    )
+                    .parse()
+                    .unwrap(),
+                },
+                None,
+            )
+            .unwrap()
+        })
+        .collect()
+}
+
+/// Inserts a drive on 16.01.2009 without label or deadline and returns its ID.
+fn insert_drive(conn: &mut Connection) -> i64 {
+    let date = NaiveDate::from_ymd_opt(2009, 1, 16).unwrap();
+    sql_interface::insert_new_drive(conn, date, "", None, None).unwrap();
+    sql_interface::list_drives(conn).unwrap().past[0].id
+}
+
 #[test]
 fn persons() {
     let mut conn = init_db();
@@ -27,12 +86,13 @@ fn persons() {
         ("Carol", "Delta", "carol_delta@non-existent-domain"),
     ] {
         sql_interface::insert_new_person(
-            &mut conn,
+            &conn,
             &NewPerson {
                 prename: person.0.to_string(),
                 name: person.1.to_string(),
                 email: person.2.parse().unwrap(),
             },
+            None,
         )
         .unwrap();
     }
@@ -46,37 +106,275 @@ fn persons() {
     assert_eq!(bob, bob_by_id);
     assert_eq!(bob.prename, "Bob");
     assert_eq!(bob.name, "Echo");
-    assert_eq!(bob.is_superuser, false);
+    assert!(bob.roles.is_empty());
     assert_eq!(bob.token, None);
 
     // whoops, confused them with another one
     sql_interface::update_person(
-        &mut conn,
+        &conn,
         &UpdatePerson {
             id: bob.id,
             prename: "Jackie".to_string(),
             name: "Hotel".to_string(),
             email: "jackie_hotel@non-existent-domain".parse().unwrap(),
             is_visible: true,
+            group_id: None,
         },
+        None,
     )
     .unwrap();
     let jackie = sql_interface::search_person(&mut conn, &Id(bob.id)).unwrap();
     assert_eq!(jackie.prename, "Jackie");
     assert_eq!(jackie.name, "Hotel");
-    assert_eq!(jackie.is_superuser, false);
+    assert!(jackie.roles.is_empty());
     assert_eq!(jackie.token, None);
 
     let all_persons =
-        sql_interface::list_all_persons(&mut conn, VisibilityFilter::OnlyVisible).unwrap();
+        sql_interface::list_all_persons(&conn, VisibilityFilter::OnlyVisible).unwrap();
     for person in all_persons {
-        sql_interface::delete_person(&mut conn, person.id).unwrap();
+        sql_interface::delete_person(&mut conn, person.id, None).unwrap();
     }
     let all_persons =
-        sql_interface::list_all_persons(&mut conn, VisibilityFilter::IncludingInvisible).unwrap();
+        sql_interface::list_all_persons(&conn, VisibilityFilter::IncludingInvisible).unwrap();
     assert!(all_persons.is_empty());
 }
 
+#[test]
+fn person_import() {
+    let mut conn = init_db();
+
+    sql_interface::insert_new_person(
+        &conn,
+        &NewPerson {
+            prename: "Alice".to_string(),
+            name: "Beta".to_string(),
+            email: "alice_beta@non-existent-domain".parse().unwrap(),
+        },
+        None,
+    )
+    .unwrap();
+    sql_interface::insert_new_person(
+        &conn,
+        &NewPerson {
+            prename: "Bob".to_string(),
+            name: "Echo".to_string(),
+            email: "bob_echo@non-existent-domain".parse().unwrap(),
+        },
+        None,
+    )
+    .unwrap();
+
+    let csv = "name,prename,email
+Beta, Alice, alice_beta@non-existent-domain
+Echo-Foxtrot,Bob,bob_echo@non-existent-domain
+Delta,Carol,carol_delta@non-existent-domain
+Golf,Dave,not an email
+Hotel,Erin
+Delta,Caroline,carol_delta@non-existent-domain
+";
+    let plan = person_import::plan_import(&conn, csv).unwrap();
+    assert_eq!(plan.unchanged, 1);
+    assert_eq!(plan.updates.len(), 1);
+    assert_eq!(plan.updates[0].old.name, "Echo");
+    assert_eq!(plan.updates[0].new.name, "Echo-Foxtrot");
+    assert_eq!(plan.inserts.len(), 1);
+    assert_eq!(plan.inserts[0].prename, "Carol");
+    let conflict_lines: Vec<_> = plan.conflicts.iter().map(|c| c.line).collect();
+    assert_eq!(conflict_lines, [5, 6, 7]);
+
+    // planning alone doesn't change anything
+    assert_eq!(
+        sql_interface::list_all_persons(&conn, VisibilityFilter::IncludingInvisible)
+            .unwrap()
+            .len(),
+        2
+    );
+
+    let applied = person_import::apply_import(&mut conn, csv, None).unwrap();
+    assert!(applied.has_changes());
+    let names: Vec<_> =
+        sql_interface::list_all_persons(&conn, VisibilityFilter::IncludingInvisible)
+            .unwrap()
+            .into_iter()
+            .map(|person| format!("{} {}", person.prename, person.name))
+            .collect();
+    assert_eq!(names, ["Alice Beta", "Carol Delta", "Bob Echo-Foxtrot"]);
+
+    // importing the same file again is a no-op
+    let plan = person_import::plan_import(&conn, csv).unwrap();
+    assert!(!plan.has_changes());
+    assert_eq!(plan.unchanged, 3);
+}
+
+#[test]
+fn roles() {
+    let mut conn = init_db();
+
+    let [alice, bob] = insert_persons(&conn, 2)[..] else {
+        unreachable!()
+    };
+
+    // duplicates are ignored, order doesn't matter
+    sql_interface::set_roles(
+        &mut conn,
+        alice,
+        &[Role::Registrar, Role::Driver, Role::Driver],
+        None,
+    )
+    .unwrap();
+    sql_interface::set_roles(&mut conn, bob, &[Role::Admin], None).unwrap();
+    assert_eq!(
+        sql_interface::get_roles(&mut conn, alice).unwrap(),
+        [Role::Driver, Role::Registrar]
+    );
+    assert_eq!(
+        sql_interface::search_person(&mut conn, &Id(bob))
+            .unwrap()
+            .roles,
+        [Role::Admin]
+    );
+
+    let all_roles = sql_interface::list_roles(&mut conn).unwrap();
+    assert_eq!(all_roles.len(), 2);
+    assert_eq!(all_roles[&alice], [Role::Driver, Role::Registrar]);
+
+    // roles are replaced, not added
+    sql_interface::set_roles(&mut conn, alice, &[], None).unwrap();
+    assert!(sql_interface::get_roles(&mut conn, alice)
+        .unwrap()
+        .is_empty());
+    assert!(sql_interface::search_person(&mut conn, &Id(alice))
+        .unwrap()
+        .roles
+        .is_empty());
+}
+
+#[test]
+fn groups() {
+    let mut conn = init_db();
+
+    let persons: Vec<_> = insert_persons(&conn, 3)
+        .into_iter()
+        .map(|id| sql_interface::search_person(&mut conn, &Id(id)).unwrap())
+        .collect();
+
+    sql_interface::insert_group(&mut conn, "10b", None).unwrap();
+    sql_interface::insert_group(&mut conn, "10a", None).unwrap();
+    assert!(matches!(
+        sql_interface::insert_group(&mut conn, "10a", None),
+        Err(sql_interface::InsertGroupError::NameAlreadyInUse)
+    ));
+    let groups = sql_interface::list_groups(&mut conn).unwrap();
+    let (group_a, group_b) = (groups[0].id, groups[1].id);
+    assert_eq!(groups[0].name, "10a");
+
+    // Alice in 10b, Bob in 10a, Carol in none
+    for (person, group_id) in persons.iter().zip([Some(group_b), Some(group_a), None]) {
+        sql_interface::update_person(
+            &conn,
+            &UpdatePerson {
+                id: person.id,
+                prename: person.prename.clone(),
+                name: person.name.clone(),
+                email: person.email.clone(),
+                is_visible: true,
+                group_id,
+            },
+            None,
+        )
+        .unwrap();
+    }
+    let (alice, bob, carol) = (persons[0].id, persons[1].id, persons[2].id);
+    assert_eq!(
+        sql_interface::list_person_groups(&conn).unwrap(),
+        [(alice, group_b), (bob, group_a)].into()
+    );
+
+    let date = NaiveDate::from_ymd_opt(2009, 1, 16).unwrap();
+    sql_interface::insert_new_drive(&conn, date, "", None, None).unwrap();
+    let drive_id = sql_interface::list_drives(&mut conn).unwrap().past[0].id;
+
+    // without restrictions, the list is sorted by group, persons without one at the end
+    let regs = sql_interface::search_registrations(&mut conn, &DriveId(drive_id)).unwrap();
+    let overview: Vec<_> = regs
+        .iter()
+        .map(|reg| (reg.person.prename.as_str(), reg.group.as_deref()))
+        .collect();
+    assert_eq!(
+        overview,
+        [
+            ("Bob", Some("10a")),
+            ("Alice", Some("10b")),
+            ("Carol", None)
+        ]
+    );
+
+    // restricted to 10a, only Bob sees the drive and may register
+    sql_interface::set_drive_groups(&mut conn, drive_id, &[group_a], None).unwrap();
+    assert_eq!(
+        sql_interface::list_drive_groups(&mut conn).unwrap()[&drive_id],
+        [group_a]
+    );
+    let regs = sql_interface::search_registrations(&mut conn, &DriveId(drive_id)).unwrap();
+    assert_eq!(regs.len(), 1);
+    assert_eq!(regs[0].person.id, bob);
+    for (person_id, visible) in [(alice, false), (bob, true), (carol, false)] {
+        let regs = sql_interface::search_registrations(
+            &mut conn,
+            &PersonId {
+                id: person_id,
+                filter: DriveFilter::ListAll,
+            },
+        )
+        .unwrap();
+        assert_eq!(regs.len(), visible as usize);
+    }
+    assert!(matches!(
+        sql_interface::update_registration(
+            &mut conn,
+            &RegistrationUpdate {
+                drive_id,
+                person_id: carol,
+                registered: true,
+            },
+            None,
+        ),
+        Err(sql_interface::ApplyRegistrationError::NotForPerson)
+    ));
+    assert!(matches!(
+        sql_interface::join_waitlist(&mut conn, carol, drive_id),
+        Err(sql_interface::ApplyRegistrationError::NotForPerson)
+    ));
+
+    // registered persons stay on the list even if the restriction changes afterwards
+    sql_interface::update_registration(
+        &mut conn,
+        &RegistrationUpdate {
+            drive_id,
+            person_id: bob,
+            registered: true,
+        },
+        None,
+    )
+    .unwrap();
+    sql_interface::set_drive_groups(&mut conn, drive_id, &[group_b], None).unwrap();
+    let regs = sql_interface::search_registrations(&mut conn, &DriveId(drive_id)).unwrap();
+    let ids: Vec<_> = regs.iter().map(|reg| reg.person.id).collect();
+    assert_eq!(ids, [bob, alice]);
+
+    // deleting the only group of a drive opens it to everyone again
+    sql_interface::delete_group(&mut conn, group_b, None).unwrap();
+    assert!(sql_interface::list_drive_groups(&mut conn)
+        .unwrap()
+        .is_empty());
+    assert_eq!(
+        sql_interface::list_person_groups(&conn).unwrap(),
+        [(bob, group_a)].into()
+    );
+    let regs = sql_interface::search_registrations(&mut conn, &DriveId(drive_id)).unwrap();
+    assert_eq!(regs.len(), 3);
+}
+
 #[test]
 fn register() {
     let mut conn = init_db();
@@ -86,12 +384,13 @@ fn register() {
         ("Bob", "Echo", "bob_echo@non-existent-domain"),
     ] {
         sql_interface::insert_new_person(
-            &mut conn,
+            &conn,
             &NewPerson {
                 prename: person.0.to_string(),
                 name: person.1.to_string(),
                 email: person.2.parse().unwrap(),
             },
+            None,
         )
         .unwrap();
     }
@@ -105,16 +404,17 @@ fn register() {
     // release date)
     let date = NaiveDate::from_ymd_opt(2009, 1, 16).unwrap();
     let deadline = (date - Days::new(2)).and_hms_opt(19, 2, 00).unwrap();
-    sql_interface::insert_new_drive(&mut conn, date, Some(deadline)).unwrap();
+    sql_interface::insert_new_drive(&conn, date, "", Some(deadline), None).unwrap();
+    let drive = &sql_interface::list_drives(&mut conn).unwrap().past[0];
 
     let regupdate = RegistrationUpdate {
-        date,
+        drive_id: drive.id,
         person_id: bob.id,
         registered: true,
     };
-    sql_interface::update_registration(&mut conn, &regupdate).unwrap();
+    sql_interface::update_registration(&mut conn, &regupdate, None).unwrap();
 
-    let regs = sql_interface::search_registrations(&mut conn, &Date(date)).unwrap();
+    let regs = sql_interface::search_registrations(&mut conn, &DriveId(drive.id)).unwrap();
     assert_eq!(regs.len(), 2);
     let reg = &regs[1]; // relying explicitly on sorting
     assert_eq!(reg.drive.date, date);
@@ -136,19 +436,726 @@ fn register() {
     assert!(reg.registered);
 }
 
+#[test]
+fn exports() {
+    let mut conn = init_db();
+
+    let bob = insert_persons(&conn, 2)[1];
+
+    let date = NaiveDate::from_ymd_opt(2009, 1, 16).unwrap();
+    sql_interface::insert_new_drive(&conn, date, "", None, None).unwrap();
+    let drive_id = sql_interface::list_drives(&mut conn).unwrap().past[0].id;
+    sql_interface::update_registration(
+        &mut conn,
+        &RegistrationUpdate {
+            drive_id,
+            person_id: bob,
+            registered: true,
+        },
+        None,
+    )
+    .unwrap();
+    sql_interface::update_attendance(&mut conn, bob, drive_id, Some(false), None).unwrap();
+
+    let regs = sql_interface::search_registrations(&mut conn, &DriveId(drive_id)).unwrap();
+    let table = export::drive_table(&regs);
+    let csv = table.export(ExportFormat::Csv, "2009-01-16").unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "First name,Last name,Email,Group,Registered,Waitlist position,Attended
+Alice,Beta,alice_beta@non-existent-domain,,no,,
+Bob,Echo,bob_echo@non-existent-domain,,yes,,no
+"
+    );
+
+    let counted = sql_interface::list_persons_counted_registrations(&mut conn, None, None).unwrap();
+    let table = export::registrations_table(&counted);
+    assert_eq!(table.rows.len(), 1);
+    assert_eq!(
+        table.rows[0][3..],
+        [
+            export::Cell::Number(1),
+            export::Cell::Number(1),
+            export::Cell::Number(0),
+            export::Cell::Number(1)
+        ]
+    );
+
+    // spreadsheets are zip files, checking more would mean parsing them
+    let xlsx = table
+        .export(ExportFormat::Xlsx, "Registrations: [all]")
+        .unwrap();
+    assert!(xlsx.starts_with(b"PK"));
+    // except that ODS starts with its uncompressed mimetype
+    let ods = table
+        .export(ExportFormat::Ods, "Registrations: [all]")
+        .unwrap();
+    assert!(ods.starts_with(b"PK"));
+    assert_eq!(
+        &ods[30..84],
+        b"mimetypeapplication/vnd.oasis.opendocument.spreadsheet"
+    );
+
+    // names can't smuggle formulas into spreadsheets opening the CSV
+    let table = export::Table {
+        headers: vec!["Name"],
+        rows: [
+            "=HYPERLINK(\"http://evil\")",
+            "+1",
+            "-1",
+            "@SUM(A1)",
+            "Bob-Echo",
+        ]
+        .into_iter()
+        .map(|name| vec![name.into()])
+        .collect(),
+    };
+    let csv = table.export(ExportFormat::Csv, "names").unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "Name
+\"'=HYPERLINK(\"\"http://evil\"\")\"
+'+1
+'-1
+'@SUM(A1)
+Bob-Echo
+"
+    );
+}
+
+#[test]
+fn multiple_drives_per_date() {
+    let mut conn = init_db();
+
+    let alice = insert_persons(&conn, 1)[0];
+
+    let date = NaiveDate::from_ymd_opt(2009, 1, 16).unwrap();
+    sql_interface::insert_new_drive(&conn, date, "Hinfahrt", None, None).unwrap();
+    sql_interface::insert_new_drive(&conn, date, "Rückfahrt", None, None).unwrap();
+    assert!(matches!(
+        sql_interface::insert_new_drive(&conn, date, "Hinfahrt", None, None),
+        Err(sql_interface::InsertDriveError::AlreadyExists)
+    ));
+
+    let drives = sql_interface::list_drives(&mut conn).unwrap().past;
+    assert_eq!(drives.len(), 2);
+    let (outbound, inbound) = (&drives[0], &drives[1]);
+    assert_eq!(outbound.label, "Hinfahrt");
+    assert_eq!(inbound.label, "Rückfahrt");
+
+    // registering for one leg leaves the other one untouched
+    sql_interface::update_registration(
+        &mut conn,
+        &RegistrationUpdate {
+            drive_id: inbound.id,
+            person_id: alice,
+            registered: true,
+        },
+        None,
+    )
+    .unwrap();
+    assert!(!sql_interface::is_registered(&conn, alice, outbound.id).unwrap());
+    assert!(sql_interface::is_registered(&conn, alice, inbound.id).unwrap());
+
+    let regs = sql_interface::search_registrations(
+        &mut conn,
+        &PersonId {
+            id: alice,
+            filter: DriveFilter::ListAll,
+        },
+    )
+    .unwrap();
+    let overview: Vec<_> = regs
+        .iter()
+        .map(|reg| (reg.drive.label.as_str(), reg.registered))
+        .collect();
+    assert_eq!(overview, [("Hinfahrt", false), ("Rückfahrt", true)]);
+
+    // renaming a leg into its sibling is a conflict
+    assert!(matches!(
+        sql_interface::update_drive_deadline(
+            &mut conn,
+            Drive {
+                label: "Hinfahrt".to_string(),
+                ..inbound.clone()
+            },
+            None,
+        ),
+        Err(sql_interface::UpdateDriveError::AlreadyExists)
+    ));
+}
+
+#[test]
+fn waitlist() {
+    let mut conn = init_db();
+
+    let [alice, bob, carol] = insert_persons(&conn, 3)[..] else {
+        unreachable!()
+    };
+
+    let date = NaiveDate::from_ymd_opt(2009, 1, 16).unwrap();
+    sql_interface::insert_new_drive(&conn, date, "", None, None).unwrap();
+    let drive = sql_interface::list_drives(&mut conn).unwrap().past.remove(0);
+    let drive_id = drive.id;
+    sql_interface::update_drive_deadline(
+        &mut conn,
+        Drive {
+            registration_cap: Some(1),
+            ..drive
+        },
+        None,
+    )
+    .unwrap();
+
+    let register = |conn: &mut Connection, person_id, registered| {
+        sql_interface::update_registration(
+            conn,
+            &RegistrationUpdate {
+                drive_id,
+                person_id,
+                registered,
+            },
+            None,
+        )
+        .unwrap()
+    };
+    assert!(register(&mut conn, alice, true).changed);
+    assert!(!register(&mut conn, alice, true).changed);
+
+    // the drive is full, so carol and bob line up (in that order)
+    sql_interface::join_waitlist(&mut conn, carol, drive_id).unwrap();
+    sql_interface::join_waitlist(&mut conn, bob, drive_id).unwrap();
+    sql_interface::join_waitlist(&mut conn, carol, drive_id).unwrap();
+
+    let positions = |conn: &mut Connection| {
+        sql_interface::search_registrations(conn, &DriveId(drive_id))
+            .unwrap()
+            .into_iter()
+            .map(|reg| (reg.person.id, reg.registered, reg.waitlist_position))
+            .collect::<Vec<_>>()
+    };
+    // sorted by last name: Beta, Delta, Echo
+    assert_eq!(
+        positions(&mut conn),
+        [(alice, true, None), (carol, false, Some(1)), (bob, false, Some(2))]
+    );
+
+    // alice frees her seat, which goes to carol since she was first
+    assert_eq!(register(&mut conn, alice, false).promoted, [carol]);
+    assert_eq!(
+        positions(&mut conn),
+        [(alice, false, None), (carol, true, None), (bob, false, Some(1))]
+    );
+
+    // raising the cap hands the new seats to everyone still waiting
+    let drive = sql_interface::get_drive(&mut conn, drive_id).unwrap().unwrap();
+    assert_eq!(drive.already_registered_count, 1);
+    let promoted = sql_interface::update_drive_deadline(
+        &mut conn,
+        Drive {
+            registration_cap: Some(5),
+            ..drive
+        },
+        None,
+    )
+    .unwrap();
+    assert_eq!(promoted, [bob]);
+    assert_eq!(
+        positions(&mut conn),
+        [(alice, false, None), (carol, true, None), (bob, true, None)]
+    );
+}
+
+#[test]
+fn attendance() {
+    let mut conn = init_db();
+
+    let [alice, bob] = insert_persons(&conn, 2)[..] else {
+        unreachable!()
+    };
+
+    let date = NaiveDate::from_ymd_opt(2011, 3, 9).unwrap();
+    sql_interface::insert_new_drive(&conn, date, "", None, None).unwrap();
+    let drive_id = sql_interface::list_drives(&mut conn).unwrap().past[0].id;
+
+    sql_interface::update_registration(
+        &mut conn,
+        &RegistrationUpdate {
+            drive_id,
+            person_id: alice,
+            registered: true,
+        },
+        None,
+    )
+    .unwrap();
+
+    // alice didn't show up, bob came without registering
+    sql_interface::update_attendance(&mut conn, alice, drive_id, Some(false), None).unwrap();
+    sql_interface::update_attendance(&mut conn, bob, drive_id, Some(true), None).unwrap();
+
+    let registrations = sql_interface::search_registrations(&mut conn, &DriveId(drive_id)).unwrap();
+    let state: Vec<_> = registrations
+        .iter()
+        .map(|r| (r.person.id, r.registered, r.attended))
+        .collect();
+    assert_eq!(
+        state,
+        [(alice, true, Some(false)), (bob, false, Some(true))]
+    );
+
+    // changing the registration afterwards keeps the attendance
+    sql_interface::update_registration(
+        &mut conn,
+        &RegistrationUpdate {
+            drive_id,
+            person_id: alice,
+            registered: false,
+        },
+        None,
+    )
+    .unwrap();
+    sql_interface::update_attendance(&mut conn, bob, drive_id, None, None).unwrap();
+
+    let registrations = sql_interface::search_registrations(&mut conn, &DriveId(drive_id)).unwrap();
+    assert_eq!(registrations[0].attended, Some(false));
+    assert_eq!(registrations[1].attended, None);
+
+    assert!(matches!(
+        sql_interface::update_attendance(&mut conn, bob, drive_id + 1, Some(true), None),
+        Err(sql_interface::ApplyRegistrationError::UnknownDrive)
+    ));
+}
+
+#[test]
+fn no_shows() {
+    let mut conn = init_db();
+
+    let [alice, bob] = insert_persons(&conn, 2)[..] else {
+        unreachable!()
+    };
+
+    let first = NaiveDate::from_ymd_opt(2012, 4, 2).unwrap();
+    let second = NaiveDate::from_ymd_opt(2012, 4, 3).unwrap();
+    sql_interface::insert_new_drive(&conn, first, "", None, None).unwrap();
+    sql_interface::insert_new_drive(&conn, second, "", None, None).unwrap();
+    let drives = sql_interface::list_drives(&mut conn).unwrap().past;
+    let (first_id, second_id) = (drives[0].id, drives[1].id);
+
+    for drive_id in [first_id, second_id] {
+        sql_interface::update_registration(
+            &mut conn,
+            &RegistrationUpdate {
+                drive_id,
+                person_id: alice,
+                registered: true,
+            },
+            None,
+        )
+        .unwrap();
+    }
+    sql_interface::update_attendance(&mut conn, alice, first_id, Some(false), None).unwrap();
+    sql_interface::update_attendance(&mut conn, alice, second_id, Some(true), None).unwrap();
+    sql_interface::update_attendance(&mut conn, bob, first_id, Some(true), None).unwrap();
+
+    let counted = sql_interface::list_persons_counted_registrations(&mut conn, None, None).unwrap();
+    let counts: Vec<_> = counted
+        .persons
+        .iter()
+        .map(|p| {
+            (
+                p.person.id,
+                p.count,
+                p.no_shows,
+                p.unregistered_shows,
+                p.checked,
+            )
+        })
+        .collect();
+    assert_eq!(counts, [(alice, 2, 1, 0, 2), (bob, 0, 0, 1, 0)]);
+    assert_eq!(
+        (
+            counted.sum,
+            counted.no_shows_sum,
+            counted.unregistered_shows_sum
+        ),
+        (2, 1, 1)
+    );
+
+    // bob only showed up on the first drive, so he's gone from the second day on
+    let counted =
+        sql_interface::list_persons_counted_registrations(&mut conn, Some(second), None).unwrap();
+    assert_eq!(counted.persons.len(), 1);
+    assert_eq!(counted.no_shows_sum, 0);
+
+    let drives = sql_interface::list_drives_attendance(&mut conn, None, Some(first)).unwrap();
+    assert_eq!(drives.len(), 1);
+    assert_eq!(drives[0].drive.already_registered_count, 1);
+    assert_eq!(
+        (
+            drives[0].no_shows,
+            drives[0].unregistered_shows,
+            drives[0].checked
+        ),
+        (1, 1, 1)
+    );
+}
+
+#[test]
+fn deadline_reminders() {
+    let mut conn = init_db();
+
+    let [alice, bob, carol] = insert_persons(&conn, 3)[..] else {
+        unreachable!()
+    };
+
+    let now = NaiveDate::from_ymd_opt(2013, 5, 6)
+        .unwrap()
+        .and_hms_opt(12, 0, 0)
+        .unwrap();
+    let soon = NaiveDate::from_ymd_opt(2013, 5, 7).unwrap();
+    let later = NaiveDate::from_ymd_opt(2013, 5, 14).unwrap();
+    sql_interface::insert_new_drive(
+        &conn,
+        soon,
+        "",
+        Some(now + chrono::Duration::hours(6)),
+        None,
+    )
+    .unwrap();
+    sql_interface::insert_new_drive(
+        &conn,
+        later,
+        "",
+        Some(now + chrono::Duration::days(7)),
+        None,
+    )
+    .unwrap();
+
+    // only the drive whose deadline is within the default of 24 hours is due
+    let due = sql_interface::drives_due_for_reminder(&mut conn, now).unwrap();
+    assert_eq!(due.len(), 1);
+    assert_eq!(due[0].date, soon);
+    let drive = due[0].clone();
+
+    assert_eq!(
+        sql_interface::get_reminder_preference(&mut conn, alice).unwrap(),
+        ReminderPreference::Unregistered
+    );
+    sql_interface::set_reminder_preference(&mut conn, bob, ReminderPreference::Always).unwrap();
+    sql_interface::set_reminder_preference(&mut conn, carol, ReminderPreference::Never).unwrap();
+    for person_id in [alice, bob] {
+        sql_interface::update_registration(
+            &mut conn,
+            &RegistrationUpdate {
+                drive_id: drive.id,
+                person_id,
+                registered: true,
+            },
+            None,
+        )
+        .unwrap();
+    }
+
+    // alice is registered already, carol doesn't want any reminders
+    let recipients: Vec<_> = sql_interface::reminder_recipients(&mut conn, drive.id)
+        .unwrap()
+        .into_iter()
+        .map(|(person, registered)| (person.id, registered))
+        .collect();
+    assert_eq!(recipients, [(bob, true)]);
+
+    sql_interface::mark_reminder_sent(&mut conn, drive.id).unwrap();
+    assert!(sql_interface::drives_due_for_reminder(&mut conn, now)
+        .unwrap()
+        .is_empty());
+
+    // moving the deadline means another reminder
+    sql_interface::update_drive_deadline(
+        &mut conn,
+        Drive {
+            deadline: Some(now + chrono::Duration::hours(8)),
+            ..drive.clone()
+        },
+        None,
+    )
+    .unwrap();
+    assert_eq!(
+        sql_interface::drives_due_for_reminder(&mut conn, now)
+            .unwrap()
+            .len(),
+        1
+    );
+
+    sql_interface::set_setting(&mut conn, "reminder-hours", 0, None).unwrap();
+    assert!(sql_interface::drives_due_for_reminder(&mut conn, now)
+        .unwrap()
+        .is_empty());
+
+    // quick registration tokens can only be taken once
+    let id = sql_interface::insert_quick_registration(
+        &mut conn,
+        carol,
+        drive.id,
+        "hashed".to_string(),
+        1000,
+    )
+    .unwrap();
+    let quick_registration = sql_interface::get_quick_registration(&mut conn, id)
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        (quick_registration.person_id, quick_registration.drive_id),
+        (carol, drive.id)
+    );
+    assert!(sql_interface::delete_quick_registration(&mut conn, id).unwrap());
+    assert!(!sql_interface::delete_quick_registration(&mut conn, id).unwrap());
+
+    sql_interface::insert_quick_registration(
+        &mut conn,
+        carol,
+        drive.id,
+        "hashed".to_string(),
+        1000,
+    )
+    .unwrap();
+    assert_eq!(
+        sql_interface::delete_expired_quick_registrations(&mut conn, 1001).unwrap(),
+        1
+    );
+}
+
+#[rocket::async_test]
+async fn mails() {
+    let mut conn = init_db();
+    let alice = insert_persons(&conn, 1)[0];
+    let person = sql_interface::search_person(&mut conn, &Id(alice)).unwrap();
+    let date = NaiveDate::from_ymd_opt(2014, 6, 20).unwrap();
+    sql_interface::insert_new_drive(&conn, date, "Hinfahrt", None, None).unwrap();
+    let drive = sql_interface::list_drives(&mut conn)
+        .unwrap()
+        .past
+        .remove(0);
+
+    let transport = MemoryTransport::default();
+    let mailer = Mailer::new(
+        "busliste@non-existent-domain".parse().unwrap(),
+        Box::new(transport.clone()),
+    );
+
+    authflow::send_login_mail(
+        &mailer,
+        "https://busliste.example/login?token=secret",
+        "012345",
+        person.email.clone(),
+    )
+    .await
+    .unwrap();
+    notifications::send_registration_change(
+        &mailer,
+        &person,
+        &drive,
+        RegistrationChange::RegisteredBySuperuser,
+    )
+    .await
+    .unwrap();
+
+    let sent = transport.sent.lock().unwrap();
+    assert_eq!(sent.len(), 2);
+    assert!(sent.iter().all(|mail| mail.to.email == person.email));
+
+    let login = &sent[0];
+    assert_eq!(login.subject, "[Komplett normale Busliste] Anmeldung");
+    assert!(login
+        .body
+        .contains("https://busliste.example/login?token=secret"));
+    assert!(login.body.contains("012345"));
+
+    let change = &sent[1];
+    assert_eq!(
+        change.subject,
+        format!(
+            "[Komplett normale Busliste] Angemeldet: {}",
+            format_labeled_date(date, "Hinfahrt")
+        )
+    );
+    assert!(change.body.starts_with("Hallo Alice,"));
+    assert!(change.body.contains("ein Superuser hat dich"));
+
+    // everyone gets mails about changed registrations, unless they opt out
+    assert!(sql_interface::wants_registration_mails(&mut conn, alice).unwrap());
+    sql_interface::set_registration_mails(&mut conn, alice, false).unwrap();
+    assert!(!sql_interface::wants_registration_mails(&mut conn, alice).unwrap());
+}
+
+#[test]
+fn login_codes() {
+    let mut conn = init_db();
+
+    let alice = insert_persons(&conn, 1)[0];
+    assert!(sql_interface::get_login_code(&mut conn, alice)
+        .unwrap()
+        .is_none());
+
+    sql_interface::set_login_code(&mut conn, alice, Some(tokens::hash_token(b"012345"))).unwrap();
+    let code = sql_interface::get_login_code(&mut conn, alice)
+        .unwrap()
+        .unwrap();
+    assert_eq!(code.attempts, 0);
+    assert!(tokens::verify_code_hash("012345", &code.hash));
+
+    // every attempt is counted before the code is checked, so five wrong ones use up all of them
+    let now = Utc::now().timestamp();
+    let mut try_code = |code: &str| {
+        sql_interface::reserve_login_code_attempt(&mut conn, alice, 5, now)
+            .unwrap()
+            .is_some_and(|hash| tokens::verify_code_hash(code, &hash))
+    };
+    for wrong in ["000000", "111111", "222222", "333333", "444444"] {
+        assert!(!try_code(wrong));
+    }
+    // and then even the right one is refused
+    assert!(!try_code("012345"));
+    assert_eq!(
+        sql_interface::get_login_code(&mut conn, alice)
+            .unwrap()
+            .unwrap()
+            .attempts,
+        5
+    );
+
+    // as is an expired one
+    sql_interface::set_login_code(&mut conn, alice, Some("hashed".to_string())).unwrap();
+    assert_eq!(
+        sql_interface::reserve_login_code_attempt(&mut conn, alice, 5, now + 11 * 60).unwrap(),
+        None
+    );
+    assert_eq!(
+        sql_interface::reserve_login_code_attempt(&mut conn, alice, 5, now).unwrap(),
+        Some("hashed".to_string())
+    );
+
+    // a new code starts from zero again
+    sql_interface::set_login_code(&mut conn, alice, Some("hashed2".to_string())).unwrap();
+    let code = sql_interface::get_login_code(&mut conn, alice)
+        .unwrap()
+        .unwrap();
+    assert_eq!(code.attempts, 0);
+    sql_interface::set_login_code(&mut conn, alice, None).unwrap();
+    assert!(sql_interface::get_login_code(&mut conn, alice)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn sessions() {
+    let mut conn = init_db();
+
+    let [alice, bob] = insert_persons(&conn, 2)[..] else {
+        unreachable!()
+    };
+
+    let laptop = sql_interface::create_session(&mut conn, alice, 1000, None).unwrap();
+    let phone = sql_interface::create_session(&mut conn, alice, 2000, None).unwrap();
+    let bobs = sql_interface::create_session(&mut conn, bob, 1000, None).unwrap();
+    assert!(sql_interface::session_valid(&mut conn, laptop, alice, 1000).unwrap());
+    assert!(!sql_interface::session_valid(&mut conn, laptop, alice, 1001).unwrap());
+    // a session can't be used by someone else
+    assert!(!sql_interface::session_valid(&mut conn, laptop, bob, 500).unwrap());
+
+    sql_interface::delete_session(&mut conn, laptop).unwrap();
+    assert!(!sql_interface::session_valid(&mut conn, laptop, alice, 500).unwrap());
+    assert!(sql_interface::session_valid(&mut conn, phone, alice, 500).unwrap());
+
+    sql_interface::create_session(&mut conn, alice, 2000, None).unwrap();
+    assert_eq!(
+        sql_interface::delete_sessions_of_person(&mut conn, alice, None).unwrap(),
+        2
+    );
+    assert!(!sql_interface::session_valid(&mut conn, phone, alice, 500).unwrap());
+    assert!(sql_interface::session_valid(&mut conn, bobs, bob, 500).unwrap());
+
+    sql_interface::create_session(&mut conn, bob, 2000, None).unwrap();
+    assert_eq!(
+        sql_interface::delete_expired_sessions(&mut conn, 1001).unwrap(),
+        1
+    );
+    assert!(!sql_interface::session_valid(&mut conn, bobs, bob, 500).unwrap());
+
+    // ending all sessions of a superuser also ends them viewing the site as someone else
+    let viewing = sql_interface::create_session(&mut conn, bob, 2000, Some(alice)).unwrap();
+    assert!(sql_interface::session_valid(&mut conn, viewing, bob, 500).unwrap());
+    assert_eq!(
+        sql_interface::delete_sessions_of_person(&mut conn, alice, None).unwrap(),
+        1
+    );
+    assert!(!sql_interface::session_valid(&mut conn, viewing, bob, 500).unwrap());
+
+    // sessions of deleted persons aren't valid anymore
+    let tablet = sql_interface::create_session(&mut conn, bob, 2000, None).unwrap();
+    sql_interface::delete_person(&mut conn, bob, None).unwrap();
+    assert!(!sql_interface::session_valid(&mut conn, tablet, bob, 500).unwrap());
+}
+
+#[test]
+fn drive_schedules() {
+    let mut conn = init_db();
+
+    // friday and sunday, except for one weekend
+    sql_interface::insert_drive_schedule(&mut conn, 4, "Hinfahrt").unwrap();
+    sql_interface::insert_drive_schedule(&mut conn, 6, "Rückfahrt").unwrap();
+    let date = |day| NaiveDate::from_ymd_opt(2009, 1, day).unwrap();
+    sql_interface::insert_schedule_exception(&mut conn, date(23), date(25), "Ferien").unwrap();
+
+    // a monday, with 4 weeks ahead by default
+    let today = date(12);
+    let created = sql_interface::generate_scheduled_drives(&mut conn, today).unwrap();
+    assert_eq!(created, 6);
+
+    let drives = sql_interface::list_drives(&mut conn).unwrap().past;
+    let overview: Vec<_> = drives
+        .iter()
+        .map(|drive| (drive.date.to_string(), drive.label.as_str()))
+        .collect();
+    assert_eq!(
+        overview,
+        [
+            ("2009-01-16".to_string(), "Hinfahrt"),
+            ("2009-01-18".to_string(), "Rückfahrt"),
+            ("2009-01-30".to_string(), "Hinfahrt"),
+            ("2009-02-01".to_string(), "Rückfahrt"),
+            ("2009-02-06".to_string(), "Hinfahrt"),
+            ("2009-02-08".to_string(), "Rückfahrt"),
+        ]
+    );
+    // default deadline is on wednesday
+    assert_eq!(
+        drives[0].deadline,
+        Some(date(14).and_hms_opt(23, 0, 0).unwrap())
+    );
+    assert_eq!(drives[0].registration_cap, Some(50));
+
+    // deleted drives stay deleted, only new dates are generated
+    sql_interface::delete_drive(&mut conn, drives[0].id, None).unwrap();
+    let created = sql_interface::generate_scheduled_drives(&mut conn, today).unwrap();
+    assert_eq!(created, 0);
+    let created = sql_interface::generate_scheduled_drives(&mut conn, date(19)).unwrap();
+    assert_eq!(created, 2);
+    assert_eq!(sql_interface::list_drives(&mut conn).unwrap().past.len(), 7);
+}
+
 #[test]
 fn settings() {
     let mut conn = init_db();
 
-    let _ = sql_interface::get_setting(&mut conn, "login-message").unwrap();
+    let _ = sql_interface::get_setting(&conn, "login-message").unwrap();
 
     let very_special_message =
         "this is totally not text that'd ever appear on the login page would it";
-    sql_interface::set_setting(&mut conn, "login-message", very_special_message).unwrap();
+    sql_interface::set_setting(&mut conn, "login-message", very_special_message, None).unwrap();
 
     // some hypothetical business logic
 
-    let retrieved = sql_interface::get_setting(&mut conn, "login-message").unwrap();
+    let retrieved = sql_interface::get_setting(&conn, "login-message").unwrap();
 
     if let Value::Text(content) = retrieved {
         assert_eq!(content, very_special_message);
@@ -162,3 +1169,1085 @@ fn settings() {
     let all_settings = sql_interface::all_settings(&mut conn).unwrap();
     assert_eq!(all_settings["login-message"], very_special_message);
 }
+
+#[test]
+fn settings_registry() {
+    let mut conn = init_db();
+
+    // every default is valid and already in a fresh database
+    let all_settings = sql_interface::all_settings(&mut conn).unwrap();
+    assert_eq!(all_settings.len(), settings::SETTINGS.len());
+    assert_eq!(settings::seed_defaults(&conn).unwrap(), 0);
+    assert_eq!(
+        settings::DEFAULT_DEADLINE.get_integer(&conn).unwrap(),
+        Some(2)
+    );
+
+    assert_eq!(
+        settings::SCHEDULE_WEEKS_AHEAD.parse(" 12"),
+        Ok(SettingValue::Integer(12))
+    );
+    assert_eq!(
+        settings::SCHEDULE_WEEKS_AHEAD.parse("0"),
+        Err(InvalidSetting::OutOfBounds { min: 1, max: 52 })
+    );
+    assert_eq!(
+        settings::REMINDER_HOURS.parse("a day"),
+        Err(InvalidSetting::NotAnInteger)
+    );
+    assert_eq!(settings::DEFAULT_DEADLINE.parse(""), Ok(SettingValue::None));
+    assert_eq!(
+        settings::DEFAULT_DEADLINE.parse("7"),
+        Err(InvalidSetting::NotAWeekday)
+    );
+
+    let no_deadline = settings::DEFAULT_DEADLINE.parse("").unwrap();
+    sql_interface::set_setting(&mut conn, "default-deadline", no_deadline, None).unwrap();
+    assert_eq!(settings::DEFAULT_DEADLINE.get_integer(&conn).unwrap(), None);
+    assert_eq!(
+        sql_interface::default_deadline(&conn, NaiveDate::from_ymd_opt(2023, 6, 2).unwrap())
+            .unwrap(),
+        None
+    );
+
+    // settings added after the database was created get their default
+    conn.execute("DELETE FROM settings WHERE name == 'reminder-hours'", [])
+        .unwrap();
+    assert_eq!(settings::seed_defaults(&conn).unwrap(), 1);
+    assert_eq!(
+        settings::REMINDER_HOURS.get_integer(&conn).unwrap(),
+        Some(24)
+    );
+    assert_eq!(
+        settings::LOGIN_MESSAGE.get_text(&conn).unwrap(),
+        settings::LOGIN_MESSAGE.default
+    );
+}
+
+#[test]
+fn rate_limiting() {
+    let limiter = RateLimiter::new(2, Duration::from_secs(60));
+    let start = Instant::now();
+    let later = |secs| start + Duration::from_secs(secs);
+
+    limiter.attempt("alice", start).unwrap();
+    limiter.attempt("alice", later(10)).unwrap();
+    assert_eq!(
+        limiter.attempt("alice", later(20)),
+        Err(Lockout {
+            retry_after: Duration::from_secs(40)
+        })
+    );
+    // refused attempts don't extend the lockout, and others aren't affected
+    assert_eq!(
+        limiter.attempt("alice", later(30)),
+        Err(Lockout {
+            retry_after: Duration::from_secs(30)
+        })
+    );
+    limiter.attempt("bob", later(30)).unwrap();
+
+    // the first attempt left the window, so there's one free again
+    limiter.attempt("alice", later(60)).unwrap();
+    assert!(limiter.attempt("alice", later(61)).is_err());
+    // unless an attempt is taken back
+    limiter.undo(&"alice", later(60));
+    limiter.attempt("alice", later(61)).unwrap();
+    limiter.attempt("alice", later(75)).unwrap();
+
+    // requests at the same time can't get more attempts together than one alone
+    let limiter = RateLimiter::new(5, Duration::from_secs(60));
+    let allowed: usize = std::thread::scope(|scope| {
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                scope.spawn(|| {
+                    (0..100)
+                        .filter(|_| limiter.attempt("carol", Instant::now()).is_ok())
+                        .count()
+                })
+            })
+            .collect();
+        threads
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .sum()
+    });
+    assert_eq!(allowed, 5);
+}
+
+#[test]
+fn pdf_list() {
+    let mut conn = init_db();
+    let date = NaiveDate::from_ymd_opt(2009, 1, 16).unwrap();
+    sql_interface::insert_new_drive(&conn, date, "", None, None).unwrap();
+    let drive_id = sql_interface::list_drives(&mut conn).unwrap().past[0].id;
+
+    let page_count = |pdf: &[u8]| {
+        pdf.windows(10).filter(|w| w == b"/Type/Page").count()
+            - pdf.windows(11).filter(|w| w == b"/Type/Pages").count()
+    };
+
+    let regs = sql_interface::search_registrations(&mut conn, &DriveId(drive_id)).unwrap();
+    let empty = pdf::drive_list("Freitag, 16.01.2009", "now", &regs).unwrap();
+    assert!(empty.starts_with(b"%PDF"));
+    assert_eq!(page_count(&empty), 1);
+
+    for i in 0..80 {
+        sql_interface::insert_new_person(
+            &conn,
+            &NewPerson {
+                prename: format!("Person {}", i),
+                name: "mit einem außergewöhnlich langen Nachnamen".to_string(),
+                email: format!("{}@non-existent-domain", i).parse().unwrap(),
+            },
+            None,
+        )
+        .unwrap();
+    }
+    let regs = sql_interface::search_registrations(&mut conn, &DriveId(drive_id)).unwrap();
+    let full = pdf::drive_list("Freitag, 16.01.2009", "now", &regs).unwrap();
+    assert_eq!(page_count(&full), 3);
+}
+
+#[test]
+fn calendar_feed() {
+    let mut conn = init_db();
+    let alice = insert_persons(&conn, 1)[0];
+
+    assert_eq!(
+        sql_interface::get_calendar_token(&mut conn, alice).unwrap(),
+        None
+    );
+    let (raw_token, encoded_token) = tokens::generate_token();
+    let hash = tokens::hash_feed_token(&raw_token);
+    sql_interface::set_calendar_token(&mut conn, alice, Some(hash.clone())).unwrap();
+    let db_token = sql_interface::get_calendar_token(&mut conn, alice)
+        .unwrap()
+        .unwrap();
+    assert_eq!(db_token, hash);
+    assert!(tokens::verify_feed_token_hash(&encoded_token, &db_token));
+    let (_, other_token) = tokens::generate_token();
+    assert!(!tokens::verify_feed_token_hash(&other_token, &db_token));
+    assert!(!tokens::verify_feed_token_hash("not base64!", &db_token));
+    // links from before the switch to SHA-256 can't be checked anymore
+    assert!(!tokens::verify_feed_token_hash(
+        &encoded_token,
+        &tokens::hash_token(&raw_token)
+    ));
+    sql_interface::set_calendar_token(&mut conn, alice, None).unwrap();
+    assert_eq!(
+        sql_interface::get_calendar_token(&mut conn, alice).unwrap(),
+        None
+    );
+
+    let now = NaiveDate::from_ymd_opt(2009, 1, 10)
+        .unwrap()
+        .and_hms_opt(12, 0, 0)
+        .unwrap();
+    let deadline = |day| {
+        NaiveDate::from_ymd_opt(2009, 1, day)
+            .unwrap()
+            .and_hms_opt(23, 59, 59)
+    };
+    for (day, label, deadline) in [
+        (16, "Hin, zurück", deadline(14)),
+        (23, "", deadline(21)),
+        (9, "", deadline(7)),
+    ] {
+        let date = NaiveDate::from_ymd_opt(2009, 1, day).unwrap();
+        sql_interface::insert_new_drive(&conn, date, label, deadline, None).unwrap();
+    }
+    let first_drive = sql_interface::list_drives(&mut conn)
+        .unwrap()
+        .past
+        .into_iter()
+        .find(|drive| drive.label == "Hin, zurück")
+        .unwrap()
+        .id;
+    sql_interface::update_registration(
+        &mut conn,
+        &RegistrationUpdate {
+            drive_id: first_drive,
+            person_id: alice,
+            registered: true,
+        },
+        None,
+    )
+    .unwrap();
+
+    let regs = sql_interface::search_registrations(
+        &mut conn,
+        &PersonId {
+            id: alice,
+            filter: DriveFilter::ListAll,
+        },
+    )
+    .unwrap();
+    let feed = calendar::feed(&regs, now, 24);
+    assert!(feed.starts_with("BEGIN:VCALENDAR\r\n"));
+    assert!(feed.ends_with("END:VCALENDAR\r\n"));
+    assert!(feed.lines().all(|line| line.len() <= 75));
+
+    // registered drive as all-day event, with label escaped
+    assert!(feed.contains("DTSTART;VALUE=DATE:20090116\r\nDTEND;VALUE=DATE:20090117\r\n"));
+    assert!(feed.contains("SUMMARY:Busfahrt (Hin\\, zurück)\r\n"));
+    // only the unregistered drive whose deadline is still ahead has an alarm
+    assert!(feed.contains("DTSTART:20090121T235959Z\r\n"));
+    assert!(!feed.contains("20090107T235959Z"));
+    assert_eq!(feed.matches("BEGIN:VEVENT").count(), 2);
+    assert_eq!(feed.matches("TRIGGER:-PT24H").count(), 1);
+}
+
+#[test]
+fn openapi_document() {
+    let document: serde_json::Value =
+        serde_json::from_str(include_str!("../docs/openapi.json")).unwrap();
+    assert_eq!(document["servers"][0]["url"], "/api/v1");
+
+    // every referenced schema needs to exist
+    let schemas = document["components"]["schemas"].as_object().unwrap();
+    let text = document.to_string();
+    for reference in text.split("\"#/components/schemas/").skip(1) {
+        let name = reference.split('"').next().unwrap();
+        assert!(schemas.contains_key(name), "missing schema {}", name);
+    }
+}
+
+#[test]
+fn api_tokens() {
+    let mut conn = init_db();
+    let alice = insert_persons(&conn, 1)[0];
+
+    assert_eq!("register".parse(), Ok(Scope::Register));
+    assert_eq!("admin".parse::<Scope>(), Err(()));
+
+    let reader =
+        sql_interface::insert_api_token(&mut conn, alice, "Reader", "hash1", &[Scope::Read], 100)
+            .unwrap();
+    let script = sql_interface::insert_api_token(
+        &mut conn,
+        alice,
+        "Script",
+        "hash2",
+        &[Scope::Read, Scope::Drives],
+        200,
+    )
+    .unwrap();
+
+    let token = sql_interface::get_api_token(&mut conn, script)
+        .unwrap()
+        .unwrap();
+    assert_eq!(token.person_id, alice);
+    assert_eq!(token.hash, "hash2");
+    assert_eq!(token.scopes, [Scope::Read, Scope::Drives]);
+    assert_eq!(token.last_used, None);
+
+    sql_interface::touch_api_token(&mut conn, reader, 300).unwrap();
+    let tokens = sql_interface::list_api_tokens(&mut conn, alice).unwrap();
+    let names: Vec<_> = tokens.iter().map(|token| token.name.as_str()).collect();
+    assert_eq!(names, ["Script", "Reader"]);
+    assert_eq!(tokens[1].last_used, Some(300));
+
+    // only the owner can delete a token
+    assert!(!sql_interface::delete_api_token(&mut conn, alice + 1, reader).unwrap());
+    assert!(sql_interface::delete_api_token(&mut conn, alice, reader).unwrap());
+    assert!(sql_interface::get_api_token(&mut conn, reader)
+        .unwrap()
+        .is_none());
+
+    // tokens go along with their person
+    sql_interface::delete_person(&mut conn, alice, None).unwrap();
+    assert!(sql_interface::get_api_token(&mut conn, script)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn api_token_scopes() {
+    use rocket::http::Method::{Get, Post, Put};
+
+    let permits = |scope, method, path| authflow::scope_permits(scope, method, path);
+
+    assert!(permits(Scope::Read, Get, "/api/v1/me"));
+    assert!(permits(Scope::Read, Get, "/api/v1/registrations"));
+    assert!(permits(Scope::Read, Get, "/api/v1/drives/3/registrations"));
+    // no person list, no exports and nothing of the site itself
+    for path in [
+        "/api/v1/persons",
+        "/person",
+        "/person/list",
+        "/drive/list/export",
+        "/registrations/export",
+        "/audit/export",
+        "/",
+    ] {
+        assert!(!permits(Scope::Read, Get, path), "{}", path);
+    }
+    assert!(!permits(Scope::Read, Get, "/api/v1/drives//registrations"));
+    assert!(!permits(Scope::Read, Put, "/api/v1/registrations/3"));
+
+    assert!(permits(Scope::Register, Put, "/api/v1/registrations/3"));
+    assert!(!permits(
+        Scope::Register,
+        Put,
+        "/api/v1/persons/1/registrations/3"
+    ));
+    assert!(!permits(Scope::Register, Post, "/register"));
+    assert!(!permits(Scope::Register, Get, "/api/v1/registrations"));
+
+    assert!(permits(Scope::Drives, Post, "/api/v1/drives"));
+    assert!(!permits(Scope::Drives, Post, "/api/v1/persons"));
+    assert!(!permits(Scope::Drives, Post, "/drive/delete"));
+}
+
+/// A client for the whole site with the extra config, backed by a fresh database file named after
+/// the test so tests can run in parallel. Returns the path of the file as well, for seeding it and
+/// removing it afterwards.
+fn site_client(name: &str, config: &[(&str, &str)]) -> (Client, PathBuf) {
+    let db = std::env::temp_dir().join(format!("busliste-{}-{}.db", name, std::process::id()));
+    remove_db(&db);
+    let mut figment = rocket::Config::figment()
+        .merge(("outside_address", "http://localhost:8000"))
+        .merge(("email", "busliste@non-existent-domain"))
+        .merge(("jwt_key", "dGVzdGluZyB0ZXN0aW5nIHRlc3Rpbmc="))
+        .merge(("databases.bususages.url", db.to_str().unwrap()))
+        .merge(("databases.bususages.pool_size", 4));
+    for setting in config {
+        figment = figment.merge(*setting);
+    }
+    let client = Client::tracked(super::rocket().configure(figment)).unwrap();
+    (client, db)
+}
+
+/// Removes the database file of [`site_client`] including the files of its journal.
+fn remove_db(db: &Path) {
+    for suffix in ["", "-wal", "-shm"] {
+        let mut path = db.as_os_str().to_owned();
+        path.push(suffix);
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Asserts that the JSON has every property the schema from `docs/openapi.json` requires and none
+/// it doesn't define, following references to other schemas.
+fn assert_matches_schema(value: &serde_json::Value, schema_name: &str) {
+    let document: serde_json::Value =
+        serde_json::from_str(include_str!("../docs/openapi.json")).unwrap();
+    let schema = &document["components"]["schemas"][schema_name];
+    let object = value.as_object().unwrap();
+
+    for required in schema["required"].as_array().into_iter().flatten() {
+        let required = required.as_str().unwrap();
+        assert!(
+            object.contains_key(required),
+            "{} lacks {}",
+            value,
+            required
+        );
+    }
+    for (key, property) in object {
+        let property_schema = &schema["properties"][key];
+        assert!(
+            !property_schema.is_null(),
+            "{} isn't in {}",
+            key,
+            schema_name
+        );
+        if property.is_null() {
+            assert_eq!(property_schema["nullable"], true, "{} isn't nullable", key);
+        } else if let Some(reference) = property_schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/components/schemas/");
+            assert_matches_schema(property, name);
+        }
+    }
+}
+
+#[test]
+fn api_requests() {
+    let (client, db) = site_client("api_requests", &[]);
+    let mut conn = Connection::open(&db).unwrap();
+    conn.execute_batch("PRAGMA foreign_keys = ON").unwrap();
+    let alice = insert_persons(&conn, 1)[0];
+    let date = Utc::now().date_naive() + Days::new(30);
+    sql_interface::insert_new_drive(&conn, date, "", None, None).unwrap();
+    let drive_id = sql_interface::list_drives(&mut conn).unwrap().future[0].id;
+
+    let mut bearer = |scopes: &[Scope]| {
+        let (raw, encoded) = tokens::generate_token();
+        let hash = tokens::hash_feed_token(&raw);
+        let id =
+            sql_interface::insert_api_token(&mut conn, alice, "Script", &hash, scopes, 0).unwrap();
+        Header::new(
+            "Authorization",
+            format!("Bearer {}{}_{}", authflow::API_TOKEN_PREFIX, id, encoded),
+        )
+    };
+    let reader = bearer(&[Scope::Read]);
+    let registrar = bearer(&[Scope::Register]);
+
+    let error_code = |response: LocalResponse| {
+        let body: serde_json::Value = response.into_json().unwrap();
+        assert_matches_schema(&body, "Error");
+        body["error"].as_str().unwrap().to_string()
+    };
+
+    // without or with a wrong token, nothing is accessible
+    let response = client.get("/api/v1/me").dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(error_code(response), "unauthorized");
+    let response = client
+        .get("/api/v1/me")
+        .header(Header::new("Authorization", "Bearer knb_1_wrong"))
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(error_code(response), "unauthorized");
+
+    let response = client.get("/api/v1/me").header(reader.clone()).dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let me: serde_json::Value = response.into_json().unwrap();
+    assert_matches_schema(&me, "Person");
+    assert_eq!(me["id"], alice);
+    assert_eq!(me["email"], "alice_beta@non-existent-domain");
+
+    // the scopes of a token are enforced
+    let response = client
+        .put(format!("/api/v1/registrations/{}", drive_id))
+        .header(reader)
+        .header(ContentType::JSON)
+        .body(r#"{"registered": true}"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(error_code(response), "unauthorized");
+    let response = client
+        .get("/api/v1/me")
+        .header(registrar.clone())
+        .dispatch();
+    assert_eq!(response.status(), Status::Unauthorized);
+    assert_eq!(error_code(response), "unauthorized");
+
+    let response = client
+        .put(format!("/api/v1/registrations/{}", drive_id))
+        .header(registrar.clone())
+        .header(ContentType::JSON)
+        .body(r#"{"registered": true}"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::Ok);
+    let registration: serde_json::Value = response.into_json().unwrap();
+    assert_matches_schema(&registration, "Registration");
+    assert_eq!(registration["registered"], true);
+    assert_eq!(registration["drive"]["id"], drive_id);
+    assert!(sql_interface::is_registered(&conn, alice, drive_id).unwrap());
+
+    // errors of the routes themselves are mapped as well
+    let response = client
+        .put(format!("/api/v1/registrations/{}", drive_id + 1))
+        .header(registrar.clone())
+        .header(ContentType::JSON)
+        .body(r#"{"registered": true}"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    assert_eq!(error_code(response), "not_found");
+    let response = client
+        .put(format!("/api/v1/registrations/{}", drive_id))
+        .header(registrar)
+        .header(ContentType::JSON)
+        .body(r#"{"registered": "yes"}"#)
+        .dispatch();
+    assert_eq!(response.status(), Status::UnprocessableEntity);
+    assert_eq!(error_code(response), "invalid_request");
+
+    drop(client);
+    remove_db(&db);
+}
+
+#[test]
+fn kiosk_token() {
+    let (client, db) = site_client("kiosk_token", &[("kiosk_token", "cafeteria")]);
+    assert_eq!(
+        client.get("/mensa?token=cafeteria").dispatch().status(),
+        Status::Ok
+    );
+    for uri in [
+        "/mensa?token=cafeteri",
+        "/mensa?token=cafeteriA",
+        "/mensa?token=cafeteria2",
+        "/mensa?token=",
+        "/mensa",
+        "/mensa/events?token=cafeteri",
+    ] {
+        assert_eq!(
+            client.get(uri).dispatch().status(),
+            Status::NotFound,
+            "{}",
+            uri
+        );
+    }
+    drop(client);
+    remove_db(&db);
+
+    // without a token configured, the kiosk is disabled entirely
+    let (client, db) = site_client("kiosk_disabled", &[]);
+    for uri in ["/mensa?token=", "/mensa?token=cafeteria"] {
+        assert_eq!(
+            client.get(uri).dispatch().status(),
+            Status::NotFound,
+            "{}",
+            uri
+        );
+    }
+    drop(client);
+    remove_db(&db);
+}
+
+/// The schema of version 0.2.0, from before `schema_version` existed.
+const LEGACY_SCHEMA: &str = "
+CREATE TABLE person(
+    person_id INTEGER,
+    prename TEXT NOT NULL,
+    name TEXT NOT NULL,
+    email TEXT NOT NULL,
+    token TEXT,
+    token_expiration INTEGER,
+    is_superuser BOOLEAN NOT NULL,
+    is_visible BOOLEAN NOT NULL,
+    UNIQUE(email),
+    PRIMARY KEY (person_id AUTOINCREMENT)
+);
+CREATE TABLE drive(
+    drive_id INTEGER,
+    drivedate DATE NOT NULL,
+    deadline DATETIME,
+    registration_cap INTEGER,
+    UNIQUE(drivedate),
+    PRIMARY KEY (drive_id AUTOINCREMENT)
+);
+CREATE TABLE registration(
+    id INTEGER,
+    person_id INTEGER NOT NULL,
+    drive_id INTEGER NOT NULL,
+    registered BOOLEAN NOT NULL,
+    UNIQUE(person_id, drive_id),
+    FOREIGN KEY (person_id) REFERENCES person(person_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    FOREIGN KEY (drive_id) REFERENCES drive(drive_id)
+            ON DELETE CASCADE
+            ON UPDATE CASCADE,
+    PRIMARY KEY (id AUTOINCREMENT)
+);
+CREATE TABLE settings(
+    name TEXT NOT NULL,
+    value,
+    PRIMARY KEY (name)
+);
+INSERT INTO person(prename, name, email, is_superuser, is_visible)
+VALUES ('John', 'Doe', 'john@non-existent-domain', true, true);
+INSERT INTO drive(drivedate, deadline, registration_cap)
+VALUES ('2009-01-16', '2009-01-14T23:59:59', 50);
+INSERT INTO registration(person_id, drive_id, registered)
+VALUES (1, 1, true);
+";
+
+/// The columns of all tables, for comparing schemas.
+fn table_layout(conn: &Connection) -> Vec<(String, String)> {
+    let mut statement = conn
+        .prepare(
+            "SELECT sqlite_master.name, info.name || ' ' || info.type
+            FROM sqlite_master, pragma_table_info(sqlite_master.name) AS info
+            WHERE sqlite_master.type == 'table'
+            ORDER BY sqlite_master.name, info.name",
+        )
+        .unwrap();
+    let rows = statement
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap();
+    rows.collect::<Result<_, _>>().unwrap()
+}
+
+#[test]
+fn migrations() {
+    // a fresh database already has everything
+    let mut fresh = init_db();
+    assert!(migrations::pending(&mut fresh).unwrap().is_empty());
+    assert!(migrations::migrate(&mut fresh, false).unwrap().is_empty());
+
+    let mut conn = Connection::open_in_memory().unwrap();
+    conn.execute_batch(LEGACY_SCHEMA).unwrap();
+
+    // what's applied already is found by looking at the schema
+    let pending: Vec<_> = migrations::pending(&mut conn)
+        .unwrap()
+        .iter()
+        .map(|migration| migration.name)
+        .collect();
+    assert_eq!(pending.len(), 16);
+    assert!(pending.iter().all(|name| name.starts_with("0.3.0-")));
+
+    // foreign keys are put back to what they were before, whether on or off
+    let foreign_keys = |conn: &Connection| -> bool {
+        conn.query_row("PRAGMA foreign_keys", [], |row| row.get(0))
+            .unwrap()
+    };
+
+    // a dry run changes nothing
+    conn.execute_batch("PRAGMA foreign_keys = OFF").unwrap();
+    assert_eq!(migrations::migrate(&mut conn, true).unwrap(), pending);
+    assert_eq!(migrations::pending(&mut conn).unwrap().len(), 16);
+    assert!(!foreign_keys(&conn));
+
+    conn.execute_batch("PRAGMA foreign_keys = ON").unwrap();
+    assert_eq!(migrations::migrate(&mut conn, false).unwrap(), pending);
+    assert!(migrations::pending(&mut conn).unwrap().is_empty());
+    assert!(foreign_keys(&conn));
+    assert_eq!(table_layout(&conn), table_layout(&fresh));
+
+    // settings aren't added by migrations, but seeded with their defaults afterwards
+    let settings_count: usize = conn
+        .query_row("SELECT count(*) FROM settings", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(settings_count, 0);
+    assert_eq!(
+        settings::seed_defaults(&conn).unwrap(),
+        settings::SETTINGS.len()
+    );
+
+    // rebuilding the drive table kept the registration, and superusers became admins
+    let john = sql_interface::search_person(&mut conn, &Id(1)).unwrap();
+    assert_eq!(john.roles, [Role::Admin]);
+    let regs = sql_interface::search_registrations(
+        &mut conn,
+        &PersonId {
+            id: 1,
+            filter: DriveFilter::ListAll,
+        },
+    )
+    .unwrap();
+    assert_eq!(regs.len(), 1);
+    assert!(regs[0].registered);
+}
+
+#[test]
+fn audit_log() {
+    let mut conn = init_db();
+
+    let [alice, bob, carol] = insert_persons(&conn, 3)[..] else {
+        unreachable!()
+    };
+
+    let date = NaiveDate::from_ymd_opt(2009, 1, 16).unwrap();
+    sql_interface::insert_new_drive(&conn, date, "", None, None).unwrap();
+    let drive = sql_interface::list_drives(&mut conn)
+        .unwrap()
+        .past
+        .remove(0);
+    let drive_id = drive.id;
+    sql_interface::update_drive_deadline(
+        &mut conn,
+        Drive {
+            registration_cap: Some(1),
+            ..drive
+        },
+        Some(alice),
+    )
+    .unwrap();
+
+    let register = |conn: &mut Connection, person_id, registered, actor_id| {
+        sql_interface::update_registration(
+            conn,
+            &RegistrationUpdate {
+                drive_id,
+                person_id,
+                registered,
+            },
+            actor_id,
+        )
+        .unwrap()
+    };
+    register(&mut conn, bob, true, Some(bob));
+    // not a change, so not recorded
+    register(&mut conn, bob, true, Some(bob));
+    sql_interface::join_waitlist(&mut conn, carol, drive_id).unwrap();
+    register(&mut conn, bob, false, Some(alice));
+
+    sql_interface::set_setting(&mut conn, "reminder-hours", 12, Some(alice)).unwrap();
+    sql_interface::set_setting(&mut conn, "reminder-hours", 12, Some(alice)).unwrap();
+    sql_interface::delete_person(&mut conn, bob, Some(alice)).unwrap();
+
+    let entries = audit::list(&conn, &AuditFilter::default()).unwrap();
+    let actions: Vec<_> = entries.iter().map(|entry| entry.action).collect();
+    assert_eq!(
+        actions,
+        [
+            AuditAction::PersonDeletion,
+            AuditAction::SettingChange,
+            AuditAction::WaitlistPromotion,
+            AuditAction::Registration,
+            AuditAction::Registration,
+            AuditAction::DriveUpdate,
+            AuditAction::DriveCreation,
+            AuditAction::PersonCreation,
+            AuditAction::PersonCreation,
+            AuditAction::PersonCreation,
+        ]
+    );
+
+    let deletion = &entries[0];
+    assert_eq!(deletion.actor_name.as_deref(), Some("Alice Beta"));
+    assert_eq!(deletion.target_person_id, Some(bob));
+    // the person is gone, but the log still knows who it was
+    assert_eq!(deletion.target_person_name, None);
+    assert!(deletion.before.as_deref().unwrap().contains("Echo"));
+    assert_eq!(deletion.after, None);
+
+    let setting = &entries[1];
+    assert_eq!(setting.target_setting.as_deref(), Some("reminder-hours"));
+    assert_eq!(setting.before.as_deref(), Some("24"));
+    assert_eq!(setting.after.as_deref(), Some("12"));
+
+    let promotion = &entries[2];
+    assert_eq!(promotion.actor_id, Some(alice));
+    assert_eq!(promotion.target_person_name.as_deref(), Some("Carol Delta"));
+    assert_eq!(promotion.target_drive_date, Some(date));
+
+    let drive_update = &entries[5];
+    assert!(drive_update
+        .before
+        .as_deref()
+        .unwrap()
+        .contains(r#""registration_cap":50"#));
+    assert!(drive_update
+        .after
+        .as_deref()
+        .unwrap()
+        .contains(r#""registration_cap":1"#));
+
+    // filtering
+    let by_bob = AuditFilter {
+        person_id: Some(bob),
+        ..AuditFilter::default()
+    };
+    // bob's creation, both of his registrations and his deletion
+    assert_eq!(audit::list(&conn, &by_bob).unwrap().len(), 4);
+    let registrations = AuditFilter {
+        action: Some(AuditAction::Registration),
+        limit: Some(1),
+        ..AuditFilter::default()
+    };
+    let latest = audit::list(&conn, &registrations).unwrap();
+    assert_eq!(latest.len(), 1);
+    assert_eq!(latest[0].actor_id, Some(alice));
+    let in_the_future = AuditFilter {
+        since: Some(chrono::Utc::now().timestamp() + 60),
+        ..AuditFilter::default()
+    };
+    assert!(audit::list(&conn, &in_the_future).unwrap().is_empty());
+
+    // entries can't be changed or removed
+    assert!(conn
+        .execute("UPDATE audit_log SET actor_id = NULL", [])
+        .is_err());
+    assert!(conn.execute("DELETE FROM audit_log", []).is_err());
+    assert_eq!(
+        audit::list(&conn, &AuditFilter::default()).unwrap().len(),
+        10
+    );
+
+    // every other kind of change, each on a fresh database with Alice (1), Bob (2), a drive (1) and
+    // a group (1), and made by Alice
+    for (change, expected) in AUDIT_CASES {
+        let mut conn = init_db();
+        let [alice, bob] = insert_persons(&conn, 2)[..] else {
+            unreachable!()
+        };
+        let drive_id = insert_drive(&mut conn);
+        sql_interface::insert_group(&mut conn, "10a", None).unwrap();
+        let group_id = sql_interface::list_groups(&mut conn).unwrap()[0].id;
+        assert_eq!((alice, bob, drive_id, group_id), (1, 2, 1, 1));
+        let before = audit::list(&conn, &AuditFilter::default()).unwrap().len();
+
+        change(&mut conn);
+
+        let entries = audit::list(&conn, &AuditFilter::default()).unwrap();
+        let entries = &entries[..entries.len() - before];
+        assert_eq!(entries.len(), expected.len(), "{:?}", entries);
+        for (entry, (action, target, before, after)) in entries.iter().zip(*expected) {
+            let (person_id, drive_id, group_id) = match *target {
+                AuditTarget::Person(id) => (Some(id), None, None),
+                AuditTarget::Drive(id) => (None, Some(id), None),
+                AuditTarget::Registration {
+                    person_id,
+                    drive_id,
+                } => (Some(person_id), Some(drive_id), None),
+                AuditTarget::Group(id) => (None, None, Some(id)),
+                AuditTarget::Setting(_) | AuditTarget::Nothing => (None, None, None),
+            };
+            assert_eq!(entry.action, *action);
+            assert_eq!(entry.actor_id, Some(alice), "{:?}", action);
+            assert_eq!(
+                (
+                    entry.target_person_id,
+                    entry.target_drive_id,
+                    entry.target_group_id
+                ),
+                (person_id, drive_id, group_id),
+                "{:?}",
+                action
+            );
+            assert_eq!(entry.before.as_deref(), *before, "{:?}", action);
+            assert_eq!(entry.after.as_deref(), *after, "{:?}", action);
+        }
+    }
+}
+
+/// An entry the audit log is expected to have: what was done to which target, with the values
+/// before and after as JSON.
+type ExpectedEntry = (
+    AuditAction,
+    AuditTarget<'static>,
+    Option<&'static str>,
+    Option<&'static str>,
+);
+
+/// A change for [`audit_log`] together with the entries it leaves, newest first.
+type AuditCase = (fn(&mut Connection), &'static [ExpectedEntry]);
+
+/// Most changes are made twice, but only recorded once, since the second time doesn't change
+/// anything.
+const AUDIT_CASES: &[AuditCase] = &[
+    (
+        |conn| {
+            for _ in 0..2 {
+                sql_interface::update_attendance(conn, 2, 1, Some(true), Some(1)).unwrap();
+            }
+        },
+        &[(
+            AuditAction::Attendance,
+            AuditTarget::Registration {
+                person_id: 2,
+                drive_id: 1,
+            },
+            Some(r#"{"attended":null}"#),
+            Some(r#"{"attended":true}"#),
+        )],
+    ),
+    (
+        |conn| {
+            let date = NaiveDate::from_ymd_opt(2009, 1, 17).unwrap();
+            let tx = conn.transaction().unwrap();
+            sql_interface::insert_new_drive(&tx, date, "Hinfahrt", None, Some(1)).unwrap();
+            tx.commit().unwrap();
+            // already exists, so nothing was created
+            assert!(
+                sql_interface::insert_new_drive(conn, date, "Hinfahrt", None, Some(1)).is_err()
+            );
+        },
+        &[(
+            AuditAction::DriveCreation,
+            AuditTarget::Drive(2),
+            None,
+            Some(
+                r#"{"date":"2009-01-17","deadline":null,"label":"Hinfahrt","registration_cap":50}"#,
+            ),
+        )],
+    ),
+    (
+        |conn| {
+            for _ in 0..2 {
+                sql_interface::set_drive_groups(conn, 1, &[1], Some(1)).unwrap();
+            }
+        },
+        &[(
+            AuditAction::DriveGroupChange,
+            AuditTarget::Drive(1),
+            Some(r#"{"groups":[]}"#),
+            Some(r#"{"groups":[1]}"#),
+        )],
+    ),
+    (
+        |conn| {
+            for _ in 0..2 {
+                sql_interface::delete_drive(conn, 1, Some(1)).unwrap();
+            }
+        },
+        &[(
+            AuditAction::DriveDeletion,
+            AuditTarget::Drive(1),
+            Some(r#"{"date":"2009-01-16","deadline":null,"label":"","registration_cap":50}"#),
+            None,
+        )],
+    ),
+    (
+        |conn| {
+            for _ in 0..2 {
+                // the second time, the email is taken
+                let _ = sql_interface::insert_new_person(
+                    conn,
+                    &NewPerson {
+                        prename: "Carol".to_string(),
+                        name: "Delta".to_string(),
+                        email: "carol_delta@non-existent-domain".parse().unwrap(),
+                    },
+                    Some(1),
+                );
+            }
+        },
+        &[(
+            AuditAction::PersonCreation,
+            AuditTarget::Person(3),
+            None,
+            Some(r#"{"email":"carol_delta@non-existent-domain","name":"Delta","prename":"Carol"}"#),
+        )],
+    ),
+    (
+        |conn| {
+            let update = UpdatePerson {
+                id: 2,
+                prename: "Bob".to_string(),
+                name: "Echo".to_string(),
+                email: "bob@non-existent-domain".parse().unwrap(),
+                is_visible: true,
+                group_id: Some(1),
+            };
+            for _ in 0..2 {
+                sql_interface::update_person(conn, &update, Some(1)).unwrap();
+            }
+        },
+        &[
+            (
+                AuditAction::GroupMembership,
+                AuditTarget::Person(2),
+                Some(r#"{"group_id":null}"#),
+                Some(r#"{"group_id":1}"#),
+            ),
+            (
+                AuditAction::PersonUpdate,
+                AuditTarget::Person(2),
+                Some(
+                    r#"{"email":"bob_echo@non-existent-domain","is_visible":true,"name":"Echo","prename":"Bob"}"#,
+                ),
+                Some(
+                    r#"{"email":"bob@non-existent-domain","is_visible":true,"name":"Echo","prename":"Bob"}"#,
+                ),
+            ),
+        ],
+    ),
+    (
+        |conn| {
+            let csv = "name,prename,email
+Beta-Gamma,Alice,alice_beta@non-existent-domain
+Delta,Carol,carol_delta@non-existent-domain
+";
+            for _ in 0..2 {
+                person_import::apply_import(conn, csv, Some(1)).unwrap();
+            }
+        },
+        // the inserted and updated persons are recorded on their own as well
+        &[
+            (
+                AuditAction::PersonImport,
+                AuditTarget::Nothing,
+                None,
+                Some(r#"{"inserted":[3],"updated":[1]}"#),
+            ),
+            (
+                AuditAction::PersonUpdate,
+                AuditTarget::Person(1),
+                Some(
+                    r#"{"email":"alice_beta@non-existent-domain","is_visible":true,"name":"Beta","prename":"Alice"}"#,
+                ),
+                Some(
+                    r#"{"email":"alice_beta@non-existent-domain","is_visible":true,"name":"Beta-Gamma","prename":"Alice"}"#,
+                ),
+            ),
+            (
+                AuditAction::PersonCreation,
+                AuditTarget::Person(3),
+                None,
+                Some(
+                    r#"{"email":"carol_delta@non-existent-domain","name":"Delta","prename":"Carol"}"#,
+                ),
+            ),
+        ],
+    ),
+    (
+        |conn| {
+            sql_interface::set_roles(conn, 2, &[Role::Admin, Role::Driver], Some(1)).unwrap();
+            sql_interface::set_roles(conn, 2, &[Role::Driver, Role::Admin], Some(1)).unwrap();
+        },
+        &[(
+            AuditAction::RoleChange,
+            AuditTarget::Person(2),
+            Some(r#"{"roles":[]}"#),
+            Some(r#"{"roles":["driver","admin"]}"#),
+        )],
+    ),
+    (
+        |conn| {
+            sql_interface::insert_group(conn, "10b", Some(1)).unwrap();
+            // the name is taken, so nothing was created
+            assert!(sql_interface::insert_group(conn, "10b", Some(1)).is_err());
+        },
+        &[(
+            AuditAction::GroupCreation,
+            AuditTarget::Group(2),
+            None,
+            Some(r#"{"name":"10b"}"#),
+        )],
+    ),
+    (
+        |conn| {
+            for _ in 0..2 {
+                sql_interface::delete_group(conn, 1, Some(1)).unwrap();
+            }
+        },
+        &[(
+            AuditAction::GroupDeletion,
+            AuditTarget::Group(1),
+            Some(r#"{"name":"10a"}"#),
+            None,
+        )],
+    ),
+    (
+        |conn| {
+            sql_interface::create_session(conn, 2, 1000, None).unwrap();
+            sql_interface::create_session(conn, 2, 2000, None).unwrap();
+            assert_eq!(
+                sql_interface::delete_sessions_of_person(conn, 2, Some(1)).unwrap(),
+                2
+            );
+            assert_eq!(
+                sql_interface::delete_sessions_of_person(conn, 2, Some(1)).unwrap(),
+                0
+            );
+        },
+        &[(
+            AuditAction::SessionRevocation,
+            AuditTarget::Person(2),
+            Some(r#"{"sessions":2}"#),
+            Some(r#"{"sessions":0}"#),
+        )],
+    ),
+    (
+        |conn| {
+            // ordinary sessions aren't recorded
+            let own_session = sql_interface::create_session(conn, 1, 1000, None).unwrap();
+            sql_interface::delete_session(conn, own_session).unwrap();
+            let session = sql_interface::create_session(conn, 2, 2000, Some(1)).unwrap();
+            sql_interface::delete_session(conn, session).unwrap();
+        },
+        &[
+            (
+                AuditAction::ImpersonationEnd,
+                AuditTarget::Person(2),
+                Some(r#"{"session_id":2}"#),
+                None,
+            ),
+            (
+                AuditAction::ImpersonationStart,
+                AuditTarget::Person(2),
+                None,
+                Some(r#"{"expiration":2000,"session_id":2}"#),
+            ),
+        ],
+    ),
+];
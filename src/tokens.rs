@@ -0,0 +1,90 @@
+use {
+    argon2::{
+        password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+        Algorithm, Argon2, Params, Version,
+    },
+    base64ct::{Base64UrlUnpadded, Encoding},
+    rand::Rng,
+    sha2::{Digest, Sha256},
+    subtle::ConstantTimeEq,
+};
+
+/// Generates a token with 128 random bytes, constant-time encoded in URL-safe base64. Also returns
+/// the random bytes used.
+pub fn generate_token() -> ([u8; 128], String) {
+    let mut rng = rand::thread_rng();
+    let mut bytes = [0_u8; 128];
+
+    rng.fill(&mut bytes);
+
+    (bytes, Base64UrlUnpadded::encode_string(&bytes))
+}
+
+// Constructs an [`argon2::Argon2`] instance with reasonable settings.
+fn construct_argon2_instance() -> Argon2<'static> {
+    Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15_u32 * 1024_u32, 2, 1, None).unwrap(),
+    )
+}
+
+/// Hashes the raw bytes of a token generated by [`generate_token`] for storing it in the database.
+pub fn hash_token(raw_token: &[u8]) -> String {
+    let salt = SaltString::generate(rand::thread_rng());
+    let argon2 = construct_argon2_instance();
+    argon2
+        .hash_password(raw_token, &salt)
+        .expect("Could not hash token!")
+        .to_string()
+}
+
+/// Checks a code typed in by a client against the hash stored in the database. Spaces are ignored,
+/// as some like to group digits.
+pub fn verify_code_hash(client_code: &str, db_code: &str) -> bool {
+    let client_code: String = client_code.chars().filter(|c| !c.is_whitespace()).collect();
+
+    let argon2 = construct_argon2_instance();
+    let db_code_hash = PasswordHash::new(db_code).expect("Invalid code hash in DB!");
+    argon2
+        .verify_password(client_code.as_bytes(), &db_code_hash)
+        .is_ok()
+}
+
+/// Checks a token sent by a client against the hash stored in the database.
+pub fn verify_token_hash(client_token: &str, db_token: &str) -> bool {
+    let client_token_bytes = match Base64UrlUnpadded::decode_vec(client_token) {
+        // possibly evil client, but we just friendly say "something happened and idk what"
+        Err(_) => return false,
+        Ok(x) => x,
+    };
+
+    let argon2 = construct_argon2_instance();
+    let db_token_hash = PasswordHash::new(db_token).expect("Invalid token hash in DB!");
+    argon2
+        .verify_password(&client_token_bytes, &db_token_hash)
+        .is_ok()
+}
+
+/// Hashes the raw bytes of a token generated by [`generate_token`] with SHA-256, for tokens which
+/// are checked on every request, like the one of the calendar feed or API tokens. The token is
+/// random enough that a slow hash like [`hash_token`] buys nothing there, it would only make each
+/// request expensive.
+pub fn hash_feed_token(raw_token: &[u8]) -> String {
+    Base64UrlUnpadded::encode_string(&Sha256::digest(raw_token))
+}
+
+/// Checks a token sent by a client against the hash from [`hash_feed_token`] in constant time.
+pub fn verify_feed_token_hash(client_token: &str, db_token: &str) -> bool {
+    let Ok(client_token_bytes) = Base64UrlUnpadded::decode_vec(client_token) else {
+        return false;
+    };
+    let Ok(db_token_hash) = Base64UrlUnpadded::decode_vec(db_token) else {
+        return false;
+    };
+
+    Sha256::digest(&client_token_bytes)
+        .as_slice()
+        .ct_eq(&db_token_hash)
+        .into()
+}